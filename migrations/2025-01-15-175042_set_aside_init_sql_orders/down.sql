@@ -0,0 +1 @@
+ALTER TABLE IF EXISTS init_sql_orders RENAME TO orders;
@@ -0,0 +1,12 @@
+-- Databases created from init.sql already have an orders table before any
+-- migration has run, so the next migration could not create its own. Move
+-- that table aside; 2026-10-19-100000_reconcile_orders puts it back.
+DO $$
+BEGIN
+    IF to_regclass('orders') IS NOT NULL AND NOT EXISTS (
+        SELECT 1 FROM __diesel_schema_migrations WHERE version = '20250115175043'
+    ) THEN
+        ALTER TABLE orders RENAME TO init_sql_orders;
+    END IF;
+END
+$$;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS balance_journal;
+DROP TABLE IF EXISTS settlements;
\ No newline at end of file
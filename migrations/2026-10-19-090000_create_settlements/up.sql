@@ -0,0 +1,18 @@
+CREATE TABLE settlements (
+    trade_id UUID PRIMARY KEY,
+    pair TEXT NOT NULL,
+    settled_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE balance_journal (
+    id BIGSERIAL PRIMARY KEY,
+    trade_id UUID NOT NULL REFERENCES settlements(trade_id),
+    order_id UUID,
+    user_id UUID NOT NULL,
+    asset TEXT NOT NULL,
+    amount NUMERIC NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('trade', 'fee'))
+);
+
+CREATE INDEX idx_balance_journal_trade ON balance_journal(trade_id);
+CREATE INDEX idx_balance_journal_user_asset ON balance_journal(user_id, asset);
@@ -0,0 +1,118 @@
+-- Replace the original orders table with the engine's order model:
+-- UUID ids, side/status enums, partial fills and the remaining size.
+-- Databases created from init.sql bring their own orders table, set aside
+-- by 2025-01-15-175042_set_aside_init_sql_orders, so every step only does
+-- what is still missing.
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb') THEN
+        CREATE EXTENSION IF NOT EXISTS timescaledb;
+    END IF;
+END
+$$;
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'order_side') THEN
+        CREATE TYPE order_side AS ENUM ('bid', 'ask');
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'order_status') THEN
+        CREATE TYPE order_status AS ENUM ('new', 'filled', 'partially_filled', 'cancelled');
+    END IF;
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema()
+          AND table_name = 'orders'
+          AND column_name = 'order_type'
+    ) THEN
+        ALTER TABLE orders RENAME TO legacy_orders;
+    END IF;
+    IF to_regclass('init_sql_orders') IS NOT NULL THEN
+        ALTER TABLE init_sql_orders RENAME TO orders;
+    END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS orders (
+    id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    session_id UUID,
+    client_order_id TEXT,
+    market TEXT,
+    side order_side NOT NULL,
+    status order_status NOT NULL,
+    price NUMERIC NOT NULL,
+    size NUMERIC NOT NULL,
+    remaining_size NUMERIC NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    -- Hypertables need the partitioning column in every unique index.
+    PRIMARY KEY (id, created_at)
+);
+
+-- The baseline init.sql table had no owners, sessions or markets. Its rows
+-- go to the nil user.
+ALTER TABLE orders
+    ADD COLUMN IF NOT EXISTS user_id UUID NOT NULL
+        DEFAULT '00000000-0000-0000-0000-000000000000',
+    ADD COLUMN IF NOT EXISTS session_id UUID,
+    ADD COLUMN IF NOT EXISTS client_order_id TEXT,
+    ADD COLUMN IF NOT EXISTS market TEXT;
+ALTER TABLE orders ALTER COLUMN user_id DROP DEFAULT;
+
+-- Legacy rows had no market or partial fills; open orders are taken as
+-- untouched and filled orders as fully filled. Their timestamps were stored
+-- without a zone and are read as UTC.
+DO $$
+BEGIN
+    IF to_regclass('legacy_orders') IS NOT NULL THEN
+        INSERT INTO orders (id, user_id, side, status, price, size, remaining_size, created_at, updated_at)
+        SELECT
+            gen_random_uuid(),
+            user_id,
+            CASE order_type WHEN 'buy' THEN 'bid'::order_side ELSE 'ask'::order_side END,
+            CASE status
+                WHEN 'open' THEN 'new'::order_status
+                WHEN 'filled' THEN 'filled'::order_status
+                ELSE 'cancelled'::order_status
+            END,
+            price,
+            amount,
+            CASE status WHEN 'filled' THEN 0 ELSE amount END,
+            COALESCE(created_at AT TIME ZONE 'UTC', NOW()),
+            COALESCE(updated_at AT TIME ZONE 'UTC', created_at AT TIME ZONE 'UTC', NOW())
+        FROM legacy_orders;
+
+        DROP TABLE legacy_orders;
+    END IF;
+END
+$$;
+
+-- The init.sql table was keyed by id alone.
+DO $$
+BEGIN
+    IF NOT EXISTS (
+        SELECT 1 FROM pg_index i
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+        WHERE i.indrelid = 'orders'::regclass AND i.indisprimary AND a.attname = 'created_at'
+    ) THEN
+        ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_pkey;
+        ALTER TABLE orders ADD PRIMARY KEY (id, created_at);
+    END IF;
+END
+$$;
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+        PERFORM create_hypertable('orders', 'created_at', migrate_data => true, if_not_exists => true);
+    END IF;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS idx_orders_status ON orders(status);
+CREATE INDEX IF NOT EXISTS idx_orders_side ON orders(side);
+CREATE INDEX IF NOT EXISTS idx_orders_client_order_id
+    ON orders(user_id, client_order_id)
+    WHERE client_order_id IS NOT NULL;
@@ -0,0 +1,37 @@
+-- Every execution the engine produces, keyed by engine sequence number.
+CREATE TABLE trades (
+    id UUID NOT NULL,
+    sequence BIGINT NOT NULL,
+    market TEXT NOT NULL,
+    buy_order_id UUID NOT NULL,
+    buyer_id UUID NOT NULL,
+    sell_order_id UUID NOT NULL,
+    seller_id UUID NOT NULL,
+    -- NULL for auction uncrosses, which have no aggressor.
+    aggressor order_side,
+    maker_order_id UUID,
+    taker_order_id UUID,
+    price NUMERIC NOT NULL,
+    quantity NUMERIC NOT NULL,
+    buyer_fee_rate NUMERIC,
+    buyer_fee_amount NUMERIC,
+    buyer_fee_asset TEXT,
+    seller_fee_rate NUMERIC,
+    seller_fee_amount NUMERIC,
+    seller_fee_asset TEXT,
+    executed_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (id, executed_at)
+);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+        PERFORM create_hypertable('trades', 'executed_at');
+    END IF;
+END
+$$;
+
+CREATE INDEX idx_trades_market_executed_at ON trades(market, executed_at DESC);
+CREATE INDEX idx_trades_buyer_executed_at ON trades(buyer_id, executed_at DESC);
+CREATE INDEX idx_trades_seller_executed_at ON trades(seller_id, executed_at DESC);
+CREATE INDEX idx_trades_sequence ON trades(sequence);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS engine_checkpoint;
+ALTER TABLE orders DROP COLUMN IF EXISTS sequence;
@@ -0,0 +1,10 @@
+-- Sequence number of the event that accepted each order, for replaying
+-- time priority on recovery.
+ALTER TABLE orders ADD COLUMN sequence BIGINT;
+
+-- The last engine event applied to the database. A single row.
+CREATE TABLE engine_checkpoint (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    last_sequence BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_orders_user_open;
+DROP INDEX IF EXISTS idx_orders_user_market_created_at;
+DROP INDEX IF EXISTS idx_orders_user_created_at;
@@ -0,0 +1,8 @@
+-- Account pages list a user's orders newest first, optionally narrowed to
+-- one market; the trailing id keeps cursor pagination on the index.
+CREATE INDEX idx_orders_user_created_at ON orders(user_id, created_at DESC, id DESC);
+CREATE INDEX idx_orders_user_market_created_at
+    ON orders(user_id, market, created_at DESC, id DESC);
+CREATE INDEX idx_orders_user_open
+    ON orders(user_id, created_at DESC, id DESC)
+    WHERE status IN ('new', 'partially_filled');
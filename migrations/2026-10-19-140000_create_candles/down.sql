@@ -0,0 +1,14 @@
+DO $$
+DECLARE
+    name TEXT;
+BEGIN
+    FOREACH name IN ARRAY ARRAY['candles_1m', 'candles_5m', 'candles_15m', 'candles_1h', 'candles_1d']
+    LOOP
+        IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+            EXECUTE format('DROP MATERIALIZED VIEW IF EXISTS %I', name);
+        ELSE
+            EXECUTE format('DROP VIEW IF EXISTS %I', name);
+        END IF;
+    END LOOP;
+END
+$$;
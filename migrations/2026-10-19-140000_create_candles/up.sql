@@ -0,0 +1,58 @@
+-- OHLCV candles per market, one view per interval. With TimescaleDB they
+-- are continuous aggregates over the trades hypertable, refreshed by policy
+-- and topped up in real time; without it they are plain views computing the
+-- same buckets on read.
+DO $$
+DECLARE
+    candle RECORD;
+BEGIN
+    FOR candle IN
+        SELECT * FROM (VALUES
+            ('candles_1m', INTERVAL '1 minute', INTERVAL '1 hour'),
+            ('candles_5m', INTERVAL '5 minutes', INTERVAL '3 hours'),
+            ('candles_15m', INTERVAL '15 minutes', INTERVAL '6 hours'),
+            ('candles_1h', INTERVAL '1 hour', INTERVAL '1 day'),
+            ('candles_1d', INTERVAL '1 day', INTERVAL '3 days')
+        ) AS intervals(name, width, refresh_window)
+    LOOP
+        IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+            EXECUTE format(
+                'CREATE MATERIALIZED VIEW %I
+                 WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+                 SELECT market,
+                        time_bucket(%L::interval, executed_at) AS bucket,
+                        first(price, executed_at) AS open,
+                        max(price) AS high,
+                        min(price) AS low,
+                        last(price, executed_at) AS close,
+                        sum(quantity) AS volume,
+                        sum(price * quantity) AS quote_volume,
+                        count(*) AS trades
+                 FROM trades
+                 GROUP BY market, bucket
+                 WITH NO DATA',
+                candle.name, candle.width);
+            PERFORM add_continuous_aggregate_policy(
+                candle.name::regclass,
+                start_offset => candle.refresh_window,
+                end_offset => candle.width,
+                schedule_interval => candle.width);
+        ELSE
+            EXECUTE format(
+                'CREATE VIEW %I AS
+                 SELECT market,
+                        date_bin(%L::interval, executed_at, TIMESTAMPTZ ''2000-01-03'') AS bucket,
+                        (array_agg(price ORDER BY executed_at, sequence))[1] AS open,
+                        max(price) AS high,
+                        min(price) AS low,
+                        (array_agg(price ORDER BY executed_at DESC, sequence DESC))[1] AS close,
+                        sum(quantity) AS volume,
+                        sum(price * quantity) AS quote_volume,
+                        count(*) AS trades
+                 FROM trades
+                 GROUP BY market, bucket',
+                candle.name, candle.width);
+        END IF;
+    END LOOP;
+END
+$$;
@@ -0,0 +1,21 @@
+-- Every market the engine has listed, so a restart reopens each one with
+-- its own precision and in the state it was left in.
+CREATE TABLE markets (
+    pair TEXT PRIMARY KEY,
+    price_scale INT NOT NULL,
+    quantity_scale INT NOT NULL,
+    trading_state TEXT NOT NULL CHECK (trading_state IN (
+        'pre-open', 'auction', 'continuous', 'halted', 'closed', 'delisted'
+    )),
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+-- Markets only known from their orders were opened with the default
+-- precision for continuous trading.
+INSERT INTO markets (pair, price_scale, quantity_scale, trading_state, updated_at)
+SELECT DISTINCT market, 8, 8, 'continuous', NOW()
+FROM orders
+WHERE market IS NOT NULL;
+
+COMMENT ON COLUMN orders.sequence IS
+    'Event that last put the order at the back of its queue: its acceptance, or an amend that lost priority.';
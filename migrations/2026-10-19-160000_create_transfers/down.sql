@@ -0,0 +1,9 @@
+DELETE FROM balance_journal WHERE transfer_id IS NOT NULL;
+DROP INDEX IF EXISTS idx_balance_journal_transfer;
+ALTER TABLE balance_journal
+    DROP CONSTRAINT IF EXISTS balance_journal_source_check,
+    DROP CONSTRAINT IF EXISTS balance_journal_kind_check,
+    DROP COLUMN IF EXISTS transfer_id,
+    ALTER COLUMN trade_id SET NOT NULL,
+    ADD CONSTRAINT balance_journal_kind_check CHECK (kind IN ('trade', 'fee'));
+DROP TABLE IF EXISTS transfers;
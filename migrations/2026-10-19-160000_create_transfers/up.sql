@@ -0,0 +1,22 @@
+-- Deposits and withdrawals: funds that enter or leave the exchange rather
+-- than move between users in a trade. Their journal rows point here instead
+-- of at a settlement, so the journal alone still sums to every balance.
+CREATE TABLE transfers (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    asset TEXT NOT NULL,
+    -- Positive for a deposit, negative for a withdrawal.
+    amount NUMERIC NOT NULL,
+    transferred_at TIMESTAMPTZ NOT NULL
+);
+
+ALTER TABLE balance_journal
+    ALTER COLUMN trade_id DROP NOT NULL,
+    ADD COLUMN transfer_id UUID REFERENCES transfers(id),
+    DROP CONSTRAINT IF EXISTS balance_journal_kind_check,
+    ADD CONSTRAINT balance_journal_kind_check
+        CHECK (kind IN ('trade', 'fee', 'deposit', 'withdrawal')),
+    ADD CONSTRAINT balance_journal_source_check
+        CHECK ((trade_id IS NULL) <> (transfer_id IS NULL));
+
+CREATE INDEX idx_balance_journal_transfer ON balance_journal(transfer_id);
@@ -0,0 +1,9 @@
+-- Keys clients authenticate with. Only a SHA-256 hash of each key is kept,
+-- so reading the table does not give the keys away.
+CREATE TABLE api_keys (
+    key_hash BYTEA PRIMARY KEY,
+    user_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_keys_user ON api_keys(user_id);
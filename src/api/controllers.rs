@@ -0,0 +1,707 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::routes::{AdminRequest, ClientRequest, Response};
+use crate::db::pool::DbPool;
+use crate::domain::order::{Order, OrderFilter, PageRequest};
+use crate::errors::custom_error::OrderError;
+use crate::errors::engine_error::EngineError;
+use crate::matching_engine::engine::{MassCancelFilter, OrderRef, TradingPair};
+use crate::matching_engine::pipeline::{CommandOutput, CommandPublisher, EngineCommand, Reply};
+use crate::matching_engine::ring_buffer::WaitStrategy;
+use crate::matching_engine::session::{SessionConfig, SessionKind};
+use crate::repository::api_key_repository::ApiKeyRepository;
+use crate::repository::candle_repository::CandleRepository;
+use crate::repository::order_repository::OrderRepository;
+use crate::repository::trade_repository::TradeRepository;
+use crate::services::order_service::OrderService;
+
+/// What a client connection has established so far.
+#[derive(Debug, Default)]
+pub struct ClientConnection {
+    /// Set once the connection authenticates.
+    user_id: Option<Uuid>,
+    /// Sessions opened here, ended when the connection closes.
+    sessions: Vec<Uuid>,
+}
+
+#[cfg(test)]
+impl ClientConnection {
+    pub fn authenticated(user_id: Uuid) -> ClientConnection {
+        ClientConnection {
+            user_id: Some(user_id),
+            sessions: Vec::new(),
+        }
+    }
+}
+
+/// Turns gateway requests into engine commands and answers order queries.
+///
+/// Commands go on the ring without waiting: a full ring is reported to the
+/// caller rather than stalling every connection behind it. Once on the ring,
+/// a command is answered with what the engine made of it.
+pub struct Gateway<R: OrderRepository, W: WaitStrategy> {
+    orders: OrderService<R>,
+    commands: CommandPublisher<W>,
+    /// Answers the queries that read market data rather than orders.
+    pool: DbPool,
+}
+
+impl<R: OrderRepository, W: WaitStrategy> Gateway<R, W> {
+    pub fn new(
+        orders: OrderService<R>,
+        commands: CommandPublisher<W>,
+        pool: DbPool,
+    ) -> Gateway<R, W> {
+        Gateway {
+            orders,
+            commands,
+            pool,
+        }
+    }
+
+    /// Handles one client request on `connection`. Nothing but
+    /// `Authenticate` is accepted until the connection has authenticated.
+    pub async fn client(
+        &self,
+        request: ClientRequest,
+        connection: &mut ClientConnection,
+    ) -> Response {
+        match (request, connection.user_id) {
+            (ClientRequest::Authenticate { api_key }, None) => {
+                match ApiKeyRepository::find_user(&self.pool, &api_key).await {
+                    Ok(Some(user_id)) => {
+                        connection.user_id = Some(user_id);
+                        Response::Authenticated { user_id }
+                    }
+                    Ok(None) => Response::error("Unknown API key"),
+                    Err(e) => Response::error(e),
+                }
+            }
+            (request, Some(user_id)) => self.user_request(user_id, request, connection).await,
+            (_, None) => Response::error("Authenticate before sending requests"),
+        }
+    }
+
+    /// Handles a request from an authenticated connection, on behalf of
+    /// `user_id`.
+    async fn user_request(
+        &self,
+        user_id: Uuid,
+        request: ClientRequest,
+        connection: &mut ClientConnection,
+    ) -> Response {
+        match request {
+            ClientRequest::Authenticate { .. } => {
+                Response::error("The connection is already authenticated")
+            }
+            ClientRequest::OpenSession { session_id } => {
+                let response = self
+                    .submit(EngineCommand::OpenSession {
+                        session_id,
+                        user_id,
+                        kind: SessionKind::Tcp,
+                        config: SessionConfig::default(),
+                        now: Utc::now(),
+                    })
+                    .await;
+                if response == Response::Accepted {
+                    connection.sessions.push(session_id);
+                }
+                response
+            }
+            ClientRequest::Heartbeat { session_id } => {
+                if !connection.sessions.contains(&session_id) {
+                    return Response::error("The session was not opened on this connection");
+                }
+                self.submit(EngineCommand::Heartbeat {
+                    session_id,
+                    now: Utc::now(),
+                })
+                .await
+            }
+            ClientRequest::PlaceLimitOrder {
+                market,
+                session_id,
+                client_order_id,
+                side,
+                price,
+                size,
+            } => {
+                let mut order = Order::new(side, size).with_user(user_id);
+                order.pair = market.parse().ok();
+                order.session_id = session_id;
+                order.client_order_id = client_order_id;
+                order.price = price;
+                let order_id = order.id;
+                match self.orders.place_order(&self.commands, order) {
+                    Ok(reply) => Self::answer(reply, Response::Placed { order_id }).await,
+                    Err(message) => Response::error(message),
+                }
+            }
+            ClientRequest::PlaceMarketOrder {
+                market,
+                session_id,
+                client_order_id,
+                side,
+                size,
+            } => {
+                let mut order = Order::new(side, size).with_user(user_id);
+                order.pair = market.parse().ok();
+                order.session_id = session_id;
+                order.client_order_id = client_order_id;
+                let order_id = order.id;
+                match self.orders.place_market_order(&self.commands, order) {
+                    Ok(reply) => Self::answer(reply, Response::Placed { order_id }).await,
+                    Err(message) => Response::error(message),
+                }
+            }
+            ClientRequest::CancelOrder {
+                market,
+                session_id,
+                order_id,
+            } => {
+                self.submit_to(&market, |pair| EngineCommand::CancelOrder {
+                    pair,
+                    order_id,
+                    user_id,
+                    session_id,
+                })
+                .await
+            }
+            ClientRequest::CancelByClientOrderId {
+                market,
+                session_id,
+                client_order_id,
+            } => {
+                self.submit_to(&market, |pair| EngineCommand::CancelByClientOrderId {
+                    pair,
+                    user_id,
+                    session_id,
+                    client_order_id,
+                })
+                .await
+            }
+            ClientRequest::AmendOrder {
+                market,
+                session_id,
+                order_id,
+                client_order_id,
+                price,
+                remaining,
+            } => {
+                let order = match (order_id, client_order_id) {
+                    (Some(order_id), _) => OrderRef::Id(order_id),
+                    (None, Some(client_order_id)) => OrderRef::Client {
+                        user_id,
+                        client_order_id,
+                    },
+                    (None, None) => {
+                        return Response::error("An amend must name an order_id or client_order_id")
+                    }
+                };
+                self.submit_to(&market, |pair| EngineCommand::AmendOrder {
+                    pair,
+                    order,
+                    user_id,
+                    session_id,
+                    price,
+                    remaining,
+                })
+                .await
+            }
+            // Other users' orders are reported as missing, not forbidden, so
+            // their ids cannot be probed.
+            ClientRequest::GetOrder { order_id } => match self.orders.get_order(order_id).await {
+                Ok(order) if order.user_id == user_id => Response::Order { order },
+                Ok(_) => Response::error(OrderError::NotFound(order_id)),
+                Err(message) => Response::error(message),
+            },
+            ClientRequest::OpenOrders { after, limit } => match self
+                .orders
+                .open_orders(user_id, PageRequest { after, limit })
+                .await
+            {
+                Ok(page) => Response::Orders { page },
+                Err(message) => Response::error(message),
+            },
+            ClientRequest::OrderHistory {
+                market,
+                statuses,
+                from,
+                to,
+                after,
+                limit,
+            } => {
+                let pair = match market.as_deref().map(str::parse).transpose() {
+                    Ok(pair) => pair,
+                    Err(e) => return Response::error(e),
+                };
+                let filter = OrderFilter {
+                    pair,
+                    statuses,
+                    from,
+                    to,
+                    ..OrderFilter::for_user(user_id)
+                };
+                match self
+                    .orders
+                    .order_history(&filter, PageRequest { after, limit })
+                    .await
+                {
+                    Ok(page) => Response::Orders { page },
+                    Err(message) => Response::error(message),
+                }
+            }
+            ClientRequest::Trades { from, to } => {
+                match TradeRepository::find_for_user(&self.pool, user_id, from, to).await {
+                    Ok(trades) => Response::Trades { trades },
+                    Err(e) => Response::error(e),
+                }
+            }
+            ClientRequest::Ticker { market } => {
+                self.submit_to(&market, |pair| EngineCommand::Ticker { pair })
+                    .await
+            }
+            ClientRequest::Candles {
+                market,
+                interval,
+                from,
+                to,
+            } => {
+                let pair = match market.parse() {
+                    Ok(pair) => pair,
+                    Err(e) => return Response::error(e),
+                };
+                match CandleRepository::find_range(&self.pool, &pair, interval, from, to).await {
+                    Ok(candles) => Response::Candles { candles },
+                    Err(e) => Response::error(e),
+                }
+            }
+        }
+    }
+
+    /// Handles one operator request.
+    pub async fn admin(&self, request: AdminRequest) -> Response {
+        match request {
+            AdminRequest::AddMarket { market } => {
+                self.submit_to(&market, |pair| EngineCommand::AddMarket { pair })
+                    .await
+            }
+            AdminRequest::SetTradingState { market, state } => {
+                self.submit_to(&market, |pair| EngineCommand::SetTradingState {
+                    pair,
+                    state,
+                })
+                .await
+            }
+            AdminRequest::DelistMarket { market, reason } => {
+                self.submit_to(&market, |pair| EngineCommand::DelistMarket { pair, reason })
+                    .await
+            }
+            AdminRequest::RemoveMarket { market, reason } => {
+                self.submit_to(&market, |pair| EngineCommand::RemoveMarket { pair, reason })
+                    .await
+            }
+            AdminRequest::MassCancel {
+                user_id,
+                market,
+                side,
+                price_range,
+            } => {
+                let pair = match market.as_deref().map(str::parse).transpose() {
+                    Ok(pair) => pair,
+                    Err(e) => return Response::error(e),
+                };
+                self.submit(EngineCommand::MassCancel {
+                    filter: MassCancelFilter {
+                        user_id,
+                        pair,
+                        side,
+                        price_range,
+                    },
+                })
+                .await
+            }
+            AdminRequest::Deposit {
+                transfer_id,
+                user_id,
+                asset,
+                amount,
+            } => {
+                self.submit(EngineCommand::Deposit {
+                    transfer_id,
+                    user_id,
+                    asset,
+                    amount,
+                })
+                .await
+            }
+            AdminRequest::Withdraw {
+                transfer_id,
+                user_id,
+                asset,
+                amount,
+            } => {
+                self.submit(EngineCommand::Withdraw {
+                    transfer_id,
+                    user_id,
+                    asset,
+                    amount,
+                })
+                .await
+            }
+            AdminRequest::IssueApiKey { user_id } => {
+                match ApiKeyRepository::issue(&self.pool, user_id).await {
+                    Ok(api_key) => Response::ApiKey { api_key },
+                    Err(e) => Response::error(e),
+                }
+            }
+        }
+    }
+
+    /// Ends the sessions of a connection that closed, so their orders are
+    /// cancelled now rather than when the heartbeat times out.
+    pub fn disconnect(&self, connection: &ClientConnection) {
+        for session_id in connection.sessions.iter().copied() {
+            self.commands.publish(EngineCommand::DisconnectSession {
+                session_id,
+                now: Utc::now(),
+            });
+        }
+    }
+
+    async fn submit_to(
+        &self,
+        market: &str,
+        command: impl FnOnce(TradingPair) -> EngineCommand,
+    ) -> Response {
+        match market.parse() {
+            Ok(pair) => self.submit(command(pair)).await,
+            Err(e) => Response::error(e),
+        }
+    }
+
+    async fn submit(&self, command: EngineCommand) -> Response {
+        match self.commands.try_submit(command) {
+            Ok(reply) => Self::answer(reply, Response::Accepted).await,
+            Err(_) => Response::error("The engine is busy, try again"),
+        }
+    }
+
+    /// Waits for the engine to run a command, answering `accepted` if it
+    /// went through without handing anything back.
+    async fn answer(reply: Reply, accepted: Response) -> Response {
+        match reply.await {
+            Ok(Ok(CommandOutput::Done)) => accepted,
+            Ok(Ok(CommandOutput::Archived(archive))) => Response::Archived { archive },
+            Ok(Ok(CommandOutput::Ticker(ticker))) => Response::Ticker { ticker },
+            Ok(Err(EngineError::DuplicateClientOrderId {
+                existing_order_id, ..
+            })) => Response::AlreadyPlaced {
+                order_id: existing_order_id,
+            },
+            Ok(Err(reason)) => Response::rejected(reason),
+            Err(_) => Response::error("The engine stopped before running the command"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::events::{CancelReason, EngineEvent};
+    use crate::matching_engine::pipeline::pipeline;
+    use crate::matching_engine::rate_limit::{
+        ActionLimits, RateLimit, RateLimiter, TierLimits, UserTier,
+    };
+    use crate::matching_engine::ring_buffer::BusySpinWait;
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use crate::matching_engine::types::order::OrderSide;
+    use crate::repository::order_repository::{InMemoryOrderRepository, OrderRepositoryExt};
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use std::thread;
+
+    #[tokio::test]
+    async fn requests_reach_the_engine_and_closed_connections_end_their_sessions() {
+        let (commands, subscriber, runner) = pipeline(MatchingEngine::new(), 16, 16, BusySpinWait);
+        let runner = thread::spawn(move || runner.run());
+        let gateway = Gateway::new(
+            OrderService::new(InMemoryOrderRepository::new()),
+            commands.clone(),
+            DB_POOL.clone(),
+        );
+        let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut connection = ClientConnection::authenticated(user_id);
+
+        let add = AdminRequest::AddMarket {
+            market: "BTC/USD".to_string(),
+        };
+        assert_eq!(gateway.admin(add).await, Response::Accepted);
+        let open = ClientRequest::OpenSession { session_id };
+        assert_eq!(
+            gateway.client(open, &mut connection).await,
+            Response::Accepted
+        );
+        assert_eq!(connection.sessions, vec![session_id]);
+        let place = |market: &str| ClientRequest::PlaceLimitOrder {
+            market: market.to_string(),
+            session_id: Some(session_id),
+            client_order_id: None,
+            side: OrderSide::Bid,
+            price: Price(dec!(100)),
+            size: Quantity(dec!(1)),
+        };
+        assert!(matches!(
+            gateway.client(place("BTC/USD"), &mut connection).await,
+            Response::Placed { .. }
+        ));
+        assert_eq!(
+            gateway.client(place("BTCUSD"), &mut connection).await,
+            Response::error("Order must name a market")
+        );
+        gateway.disconnect(&connection);
+        commands.publish(EngineCommand::Shutdown);
+
+        let engine = runner.join().unwrap();
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        assert_eq!(engine.market(&pair).unwrap().orderbook().len(), 0);
+        let mut events = Vec::new();
+        while let Some(event) = subscriber.try_next() {
+            events.push(event.event);
+        }
+        assert!(matches!(events[1], EngineEvent::OrderAccepted { .. }));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            EngineEvent::OrderCancelled {
+                reason: CancelReason::SessionDisconnected(id),
+                ..
+            } if *id == session_id
+        )));
+    }
+
+    #[tokio::test]
+    async fn connections_act_for_the_user_their_api_key_belongs_to() {
+        migrate();
+        let (user_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let orders = InMemoryOrderRepository::new();
+        let own = Order::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user_id);
+        let theirs = Order::new(OrderSide::Bid, Quantity(dec!(1))).with_user(other);
+        orders.create_order(&own).await.unwrap();
+        orders.create_order(&theirs).await.unwrap();
+        let (commands, _subscriber, _runner) =
+            pipeline(MatchingEngine::new(), 16, 16, BusySpinWait);
+        let gateway = Gateway::new(OrderService::new(orders), commands, DB_POOL.clone());
+        let mut connection = ClientConnection::default();
+
+        let get = |order_id| ClientRequest::GetOrder { order_id };
+        assert_eq!(
+            gateway.client(get(own.id), &mut connection).await,
+            Response::error("Authenticate before sending requests")
+        );
+        let Response::ApiKey { api_key } =
+            gateway.admin(AdminRequest::IssueApiKey { user_id }).await
+        else {
+            panic!("no API key was issued");
+        };
+        let wrong = ClientRequest::Authenticate {
+            api_key: format!("{}0", api_key),
+        };
+        assert_eq!(
+            gateway.client(wrong, &mut connection).await,
+            Response::error("Unknown API key")
+        );
+        let login = ClientRequest::Authenticate { api_key };
+        assert_eq!(
+            gateway.client(login, &mut connection).await,
+            Response::Authenticated { user_id }
+        );
+
+        assert!(matches!(
+            gateway.client(get(own.id), &mut connection).await,
+            Response::Order { order } if order.id == own.id
+        ));
+        assert_eq!(
+            gateway.client(get(theirs.id), &mut connection).await,
+            Response::error(OrderError::NotFound(theirs.id))
+        );
+        // Heartbeats only keep this connection's own sessions alive.
+        let heartbeat = ClientRequest::Heartbeat {
+            session_id: Uuid::new_v4(),
+        };
+        assert_eq!(
+            gateway.client(heartbeat, &mut connection).await,
+            Response::error("The session was not opened on this connection")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejections_are_answered_with_their_retry_hint() {
+        let one_per_second = RateLimit {
+            burst: 1,
+            per_second: 1,
+        };
+        let limits = ActionLimits {
+            place: one_per_second,
+            cancel: one_per_second,
+            amend: one_per_second,
+        };
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits,
+                per_session: limits,
+            },
+        );
+        let mut engine = MatchingEngine::new();
+        engine.add_market("BTC/USD".parse().unwrap()).unwrap();
+        engine.set_rate_limiter(limiter);
+        let (commands, _subscriber, runner) = pipeline(engine, 16, 16, BusySpinWait);
+        let runner = thread::spawn(move || runner.run());
+        let gateway = Gateway::new(
+            OrderService::new(InMemoryOrderRepository::new()),
+            commands.clone(),
+            DB_POOL.clone(),
+        );
+
+        let place = || ClientRequest::PlaceLimitOrder {
+            market: "BTC/USD".to_string(),
+            session_id: None,
+            client_order_id: None,
+            side: OrderSide::Bid,
+            price: Price(dec!(100)),
+            size: Quantity(dec!(1)),
+        };
+        let mut connection = ClientConnection::authenticated(Uuid::new_v4());
+        let cancel = ClientRequest::CancelOrder {
+            market: "ETH/USD".to_string(),
+            session_id: None,
+            order_id: Uuid::new_v4(),
+        };
+        assert!(matches!(
+            gateway.client(cancel, &mut connection).await,
+            Response::Rejected {
+                retry_after_ms: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            gateway.client(place(), &mut connection).await,
+            Response::Placed { .. }
+        ));
+        match gateway.client(place(), &mut connection).await {
+            Response::Rejected {
+                retry_after_ms: Some(retry_after_ms),
+                ..
+            } => assert!((1..=1_000).contains(&retry_after_ms)),
+            other => panic!("unexpected response {:?}", other),
+        }
+        commands.publish(EngineCommand::Shutdown);
+        runner.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn retried_placements_are_answered_with_the_order_already_placed() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market("BTC/USD".parse().unwrap()).unwrap();
+        let (commands, _subscriber, runner) = pipeline(engine, 16, 16, BusySpinWait);
+        let runner = thread::spawn(move || runner.run());
+        let gateway = Gateway::new(
+            OrderService::new(InMemoryOrderRepository::new()),
+            commands.clone(),
+            DB_POOL.clone(),
+        );
+
+        let mut connection = ClientConnection::authenticated(Uuid::new_v4());
+        let place = ClientRequest::PlaceLimitOrder {
+            market: "BTC/USD".to_string(),
+            session_id: None,
+            client_order_id: Some("bid-1".to_string()),
+            side: OrderSide::Bid,
+            price: Price(dec!(100)),
+            size: Quantity(dec!(1)),
+        };
+        let Response::Placed { order_id } = gateway.client(place.clone(), &mut connection).await
+        else {
+            panic!("the first placement was not accepted");
+        };
+        assert_eq!(
+            gateway.client(place, &mut connection).await,
+            Response::AlreadyPlaced { order_id }
+        );
+        commands.publish(EngineCommand::Shutdown);
+        let engine = runner.join().unwrap();
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        assert_eq!(engine.market(&pair).unwrap().orderbook().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn operators_mass_cancel_by_user_and_market() {
+        let (commands, subscriber, runner) = pipeline(MatchingEngine::new(), 16, 16, BusySpinWait);
+        let runner = thread::spawn(move || runner.run());
+        let gateway = Gateway::new(
+            OrderService::new(InMemoryOrderRepository::new()),
+            commands.clone(),
+            DB_POOL.clone(),
+        );
+        let (user_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let add = AdminRequest::AddMarket {
+            market: "BTC/USD".to_string(),
+        };
+        assert_eq!(gateway.admin(add).await, Response::Accepted);
+        for user_id in [user_id, other] {
+            let place = ClientRequest::PlaceLimitOrder {
+                market: "BTC/USD".to_string(),
+                session_id: None,
+                client_order_id: None,
+                side: OrderSide::Ask,
+                price: Price(dec!(100)),
+                size: Quantity(dec!(1)),
+            };
+            assert!(matches!(
+                gateway
+                    .client(place, &mut ClientConnection::authenticated(user_id))
+                    .await,
+                Response::Placed { .. }
+            ));
+        }
+        let cancel = |market: &str| AdminRequest::MassCancel {
+            user_id: Some(user_id),
+            market: Some(market.to_string()),
+            side: None,
+            price_range: None,
+        };
+        assert!(matches!(
+            gateway.admin(cancel("BTC")).await,
+            Response::Error { .. }
+        ));
+        assert_eq!(gateway.admin(cancel("BTC/USD")).await, Response::Accepted);
+        commands.publish(EngineCommand::Shutdown);
+
+        let engine = runner.join().unwrap();
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        assert_eq!(engine.market(&pair).unwrap().orderbook().len(), 1);
+        let (mut owners, mut cancelled) = (HashMap::new(), Vec::new());
+        while let Some(event) = subscriber.try_next() {
+            match event.event {
+                EngineEvent::OrderAccepted {
+                    order_id, user_id, ..
+                } => {
+                    owners.insert(order_id, user_id);
+                }
+                EngineEvent::OrderCancelled {
+                    order_id,
+                    reason: CancelReason::MassCancel,
+                    ..
+                } => cancelled.push(owners[&order_id]),
+                _ => {}
+            }
+        }
+        assert_eq!(cancelled, vec![user_id]);
+    }
+}
@@ -1 +1,3 @@
-
+//! Newline-delimited JSON over TCP in front of the matching engine.
+pub mod controllers;
+pub mod routes;
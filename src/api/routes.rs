@@ -0,0 +1,407 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+use super::controllers::{ClientConnection, Gateway};
+use crate::domain::order::{Order, OrderCursor, Page};
+use crate::domain::trade::ExecutedTrade;
+use crate::errors::engine_error::EngineError;
+use crate::matching_engine::candles::{Candle, CandleInterval};
+use crate::matching_engine::engine::ArchivedMarket;
+use crate::matching_engine::market_state::TradingState;
+use crate::matching_engine::ring_buffer::WaitStrategy;
+use crate::matching_engine::ticker::TickerStats;
+use crate::matching_engine::types::decimal::{Price, Quantity};
+use crate::matching_engine::types::order::{OrderSide, OrderStatus};
+use crate::repository::order_repository::PgOrderRepository;
+
+/// A request from a trading client. Markets are written `BASE/QUOTE`.
+///
+/// A connection starts with `Authenticate`; every other request acts for the
+/// user its API key belongs to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientRequest {
+    Authenticate {
+        api_key: String,
+    },
+    /// Ties orders to this connection: they are cancelled when it closes or
+    /// stops sending heartbeats.
+    OpenSession {
+        session_id: Uuid,
+    },
+    /// Only for sessions this connection opened.
+    Heartbeat {
+        session_id: Uuid,
+    },
+    PlaceLimitOrder {
+        market: String,
+        session_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        side: OrderSide,
+        price: Price,
+        size: Quantity,
+    },
+    PlaceMarketOrder {
+        market: String,
+        session_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        side: OrderSide,
+        size: Quantity,
+    },
+    CancelOrder {
+        market: String,
+        session_id: Option<Uuid>,
+        order_id: Uuid,
+    },
+    CancelByClientOrderId {
+        market: String,
+        session_id: Option<Uuid>,
+        client_order_id: String,
+    },
+    /// Names the order by `order_id` or, failing that, `client_order_id`.
+    AmendOrder {
+        market: String,
+        session_id: Option<Uuid>,
+        order_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        price: Option<Price>,
+        remaining: Option<Quantity>,
+    },
+    GetOrder {
+        order_id: Uuid,
+    },
+    OpenOrders {
+        after: Option<OrderCursor>,
+        limit: usize,
+    },
+    /// Orders in any market and status unless narrowed down; `from` and
+    /// `to` bound `created_at` as `[from, to)`.
+    OrderHistory {
+        market: Option<String>,
+        #[serde(default)]
+        statuses: Vec<OrderStatus>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        after: Option<OrderCursor>,
+        limit: usize,
+    },
+    /// The user's trades executed in `[from, to)`, oldest first.
+    Trades {
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    },
+    /// The market's rolling 24-hour statistics.
+    Ticker {
+        market: String,
+    },
+    /// The market's candles opening in `[from, to)`, oldest first.
+    Candles {
+        market: String,
+        interval: CandleInterval,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    },
+}
+
+/// A request from an operator.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminRequest {
+    AddMarket {
+        market: String,
+    },
+    SetTradingState {
+        market: String,
+        state: TradingState,
+    },
+    DelistMarket {
+        market: String,
+        reason: String,
+    },
+    /// Drops a market, delisting it first if needed, so its pair can be
+    /// listed again.
+    RemoveMarket {
+        market: String,
+        reason: String,
+    },
+    /// Cancels every resting order that matches all the given fields.
+    /// `price_range` is `[low, high]`, both inclusive.
+    MassCancel {
+        user_id: Option<Uuid>,
+        market: Option<String>,
+        side: Option<OrderSide>,
+        price_range: Option<(Price, Price)>,
+    },
+    Deposit {
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    },
+    Withdraw {
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    },
+    /// Creates an API key the user's clients can authenticate with.
+    IssueApiKey {
+        user_id: Uuid,
+    },
+}
+
+/// One request line: the request itself and an optional id, chosen by the
+/// sender, that its answer is tagged with.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Envelope<T> {
+    pub request_id: Option<u64>,
+    #[serde(flatten)]
+    pub request: T,
+}
+
+/// One answer line: the response, tagged with the id of the request it
+/// answers.
+#[derive(Debug, Serialize)]
+struct Answer<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<u64>,
+    #[serde(flatten)]
+    response: &'a Response,
+}
+
+/// The answer to one request. Commands are answered once the engine has
+/// run them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Authenticated {
+        user_id: Uuid,
+    },
+    /// Shown once; only its hash is kept.
+    ApiKey {
+        api_key: String,
+    },
+    Accepted,
+    Placed {
+        order_id: Uuid,
+    },
+    /// The client order id was already used; `order_id` is the order that
+    /// has it. Lets a client retry a placement without placing it twice.
+    AlreadyPlaced {
+        order_id: Uuid,
+    },
+    /// The engine refused the command. `retry_after_ms` is set when it was
+    /// rate limited.
+    Rejected {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        retry_after_ms: Option<i64>,
+    },
+    /// What was left of a market when it was delisted.
+    Archived {
+        archive: ArchivedMarket,
+    },
+    /// `None` if the market has not traded in the last 24 hours.
+    Ticker {
+        ticker: Option<TickerStats>,
+    },
+    Order {
+        order: Order,
+    },
+    Orders {
+        page: Page<Order>,
+    },
+    Trades {
+        trades: Vec<ExecutedTrade>,
+    },
+    Candles {
+        candles: Vec<Candle>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Response {
+    pub fn error(message: impl ToString) -> Response {
+        Response::Error {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn rejected(reason: EngineError) -> Response {
+        let retry_after_ms = match &reason {
+            EngineError::RateLimited(limited) => Some(limited.retry_after.num_milliseconds()),
+            _ => None,
+        };
+        Response::Rejected {
+            message: reason.to_string(),
+            retry_after_ms,
+        }
+    }
+}
+
+/// Accepts trading clients. Each line a client sends is a `ClientRequest`
+/// and is answered by one `Response` line, in the order they were sent.
+pub async fn serve_clients<W>(listener: TcpListener, gateway: Arc<Gateway<PgOrderRepository, W>>)
+where
+    W: WaitStrategy + Sync + 'static,
+{
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let gateway = gateway.clone();
+        tokio::spawn(async move {
+            let _ = client_connection(stream, &gateway).await;
+        });
+    }
+}
+
+/// Accepts operators, with `AdminRequest` lines in place of client ones.
+pub async fn serve_admin<W>(listener: TcpListener, gateway: Arc<Gateway<PgOrderRepository, W>>)
+where
+    W: WaitStrategy + Sync + 'static,
+{
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let gateway = gateway.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let (request_id, response) = match serde_json::from_str(&line) {
+                    Ok(Envelope {
+                        request_id,
+                        request,
+                    }) => (request_id, gateway.admin(request).await),
+                    Err(e) => (None, Response::error(e)),
+                };
+                if write_response(&mut writer, request_id, &response)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Answers one client until it hangs up, then ends the sessions it opened.
+async fn client_connection<W: WaitStrategy>(
+    stream: TcpStream,
+    gateway: &Gateway<PgOrderRepository, W>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut connection = ClientConnection::default();
+    let result = async {
+        while let Some(line) = lines.next_line().await? {
+            let (request_id, response) = match serde_json::from_str(&line) {
+                Ok(Envelope {
+                    request_id,
+                    request,
+                }) => (request_id, gateway.client(request, &mut connection).await),
+                Err(e) => (None, Response::error(e)),
+            };
+            write_response(&mut writer, request_id, &response).await?;
+        }
+        Ok(())
+    }
+    .await;
+    gateway.disconnect(&connection);
+    result
+}
+
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    request_id: Option<u64>,
+    response: &Response,
+) -> io::Result<()> {
+    let mut line = serde_json::to_vec(&Answer {
+        request_id,
+        response,
+    })?;
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn requests_are_read_from_tagged_json() {
+        let line = r#"{"type": "place_limit_order", "market": "BTC/USD",
+                        "side": "Bid", "price": "101.5", "size": "2"}"#;
+        assert_eq!(
+            serde_json::from_str::<ClientRequest>(line).unwrap(),
+            ClientRequest::PlaceLimitOrder {
+                market: "BTC/USD".to_string(),
+                session_id: None,
+                client_order_id: None,
+                side: OrderSide::Bid,
+                price: Price(dec!(101.5)),
+                size: Quantity(dec!(2)),
+            }
+        );
+        let session_id = Uuid::new_v4();
+        let tagged = format!(
+            r#"{{"request_id": 3, "type": "heartbeat", "session_id": "{}"}}"#,
+            session_id
+        );
+        assert_eq!(
+            serde_json::from_str::<Envelope<ClientRequest>>(&tagged).unwrap(),
+            Envelope {
+                request_id: Some(3),
+                request: ClientRequest::Heartbeat { session_id },
+            }
+        );
+        // Operator requests are not accepted from clients.
+        let deposit = format!(
+            r#"{{"type": "deposit", "transfer_id": "{}", "user_id": "{}",
+                "asset": "USD", "amount": "100"}}"#,
+            Uuid::new_v4(),
+            Uuid::new_v4()
+        );
+        assert!(serde_json::from_str::<ClientRequest>(&deposit).is_err());
+        assert!(serde_json::from_str::<AdminRequest>(&deposit).is_ok());
+    }
+
+    #[tokio::test]
+    async fn answers_carry_their_status_and_request_id() {
+        let answer = |request_id, response: &Response| {
+            serde_json::to_string(&Answer {
+                request_id,
+                response,
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            answer(None, &Response::Accepted),
+            r#"{"status":"accepted"}"#
+        );
+        assert_eq!(
+            answer(Some(7), &Response::error("no such market")),
+            r#"{"request_id":7,"status":"error","message":"no such market"}"#
+        );
+        let limited = Response::Rejected {
+            message: "slow down".to_string(),
+            retry_after_ms: Some(250),
+        };
+        assert_eq!(
+            answer(Some(8), &limited),
+            r#"{"request_id":8,"status":"rejected","message":"slow down","retry_after_ms":250}"#
+        );
+    }
+}
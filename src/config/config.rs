@@ -1,17 +1,76 @@
 use dotenv::dotenv;
 use std::env;
+use std::error::Error;
+use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
+    /// Where trading clients connect. Loopback unless set: API keys travel
+    /// in plain text, so a public gateway belongs behind a TLS proxy.
+    pub gateway_addr: String,
+    /// Where operators list markets and move funds. Keep it off public
+    /// interfaces: nothing on it is authenticated.
+    pub admin_addr: String,
+    /// Collects trading fees and pays out maker rebates.
+    pub fee_account: Uuid,
+    /// JSON file with per-market settings; see `MarketSettings`.
+    pub market_settings: Option<String>,
+    /// How often the engine is told the time, so timed auctions end and lost
+    /// sessions expire without waiting for the next command.
+    pub tick_interval: Duration,
+    /// How the matching thread and the threads feeding it wait on a full or
+    /// empty ring.
+    pub wait_strategy: WaitStrategyKind,
+}
+
+/// Picks one of the ring buffer's wait strategies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStrategyKind {
+    /// `BusySpinWait`: lowest latency, but every waiting thread keeps a core
+    /// busy.
+    BusySpin,
+    /// `YieldingWait`: spins briefly, then gives the core to other threads.
+    Yielding,
+    /// `ParkingWait`, the default: idle threads sleep.
+    Parking,
 }
 
 impl Config {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new() -> Result<Self, Box<dyn Error + Send + Sync>> {
         dotenv().ok();
 
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let database_url = env::var("DATABASE_URL")?;
+        let gateway_addr =
+            env::var("GATEWAY_ADDR").unwrap_or_else(|_| "127.0.0.1:7000".to_string());
+        let admin_addr = env::var("ADMIN_ADDR").unwrap_or_else(|_| "127.0.0.1:7001".to_string());
+        let fee_account = match env::var("FEE_ACCOUNT") {
+            Ok(id) if !id.is_empty() => id.parse()?,
+            _ => Uuid::nil(),
+        };
+        let market_settings = env::var("MARKET_SETTINGS")
+            .ok()
+            .filter(|path| !path.is_empty());
+        let tick_interval = match env::var("TICK_INTERVAL_MS") {
+            Ok(millis) => Duration::from_millis(millis.parse()?),
+            Err(_) => Duration::from_millis(100),
+        };
+        let wait_strategy = match env::var("WAIT_STRATEGY").as_deref() {
+            Ok("busy_spin") => WaitStrategyKind::BusySpin,
+            Ok("yielding") => WaitStrategyKind::Yielding,
+            Ok("parking") | Ok("") | Err(_) => WaitStrategyKind::Parking,
+            Ok(other) => return Err(format!("Unknown WAIT_STRATEGY {}", other).into()),
+        };
 
-        Ok(Config { database_url })
+        Ok(Config {
+            database_url,
+            gateway_addr,
+            admin_addr,
+            fee_account,
+            market_settings,
+            tick_interval,
+            wait_strategy,
+        })
     }
 }
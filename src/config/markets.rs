@@ -0,0 +1,232 @@
+use chrono::Duration;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use uuid::Uuid;
+
+use crate::errors::engine_error::EngineError;
+use crate::matching_engine::circuit_breaker::CircuitBreakerConfig;
+use crate::matching_engine::engine::MatchingEngine;
+use crate::matching_engine::fees::{FeeCurrency, FeeSchedule, FeeTier};
+use crate::matching_engine::rate_limit::{RateLimiter, TierLimits, UserTier};
+use crate::matching_engine::risk::{RiskChain, RiskCheck};
+
+/// Market settings and rate limits read from the `MARKET_SETTINGS` file at
+/// startup.
+///
+/// None of this is in the event journal, so it is applied again on every
+/// start, after recovery.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MarketSettings {
+    /// Keyed by `BASE/QUOTE`.
+    #[serde(default)]
+    pub markets: BTreeMap<String, MarketConfig>,
+    /// Requests are not limited unless this is set.
+    pub rate_limits: Option<RateLimitConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MarketConfig {
+    pub fees: Option<FeeConfig>,
+    /// Run in order before every order; none by default.
+    #[serde(default)]
+    pub risk_checks: Vec<RiskCheck>,
+    /// The market has no circuit breaker unless this is set.
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+}
+
+/// `CircuitBreakerConfig` with its durations in whole seconds. Fields left
+/// out use `CircuitBreakerConfig::default()`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerSettings {
+    pub band_bps: u32,
+    pub reference_window_secs: i64,
+    pub auction_duration_secs: i64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        let config = CircuitBreakerConfig::default();
+        CircuitBreakerSettings {
+            band_bps: config.band_bps,
+            reference_window_secs: config.reference_window.num_seconds(),
+            auction_duration_secs: config.auction_duration.num_seconds(),
+        }
+    }
+}
+
+impl From<CircuitBreakerSettings> for CircuitBreakerConfig {
+    fn from(settings: CircuitBreakerSettings) -> Self {
+        CircuitBreakerConfig {
+            band_bps: settings.band_bps,
+            reference_window: Duration::seconds(settings.reference_window_secs),
+            auction_duration: Duration::seconds(settings.auction_duration_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FeeConfig {
+    pub fee_currency: FeeCurrency,
+    pub tiers: Vec<FeeTier>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RateLimitConfig {
+    /// Tiers left out use `TierLimits::default()`.
+    #[serde(default)]
+    pub tiers: HashMap<UserTier, TierLimits>,
+    /// Users left out are `Standard`.
+    #[serde(default)]
+    pub users: HashMap<Uuid, UserTier>,
+}
+
+impl MarketSettings {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Configures every market in the file, listing the ones the engine does
+    /// not have yet, and turns on rate limits if the file has any.
+    ///
+    /// Arming a circuit breaker starts it with no reference price, so one
+    /// only takes effect once the market trades again after a restart.
+    pub fn apply(self, engine: &mut MatchingEngine) -> Result<(), EngineError> {
+        for (market, config) in self.markets {
+            let pair = market.parse()?;
+            if engine.market(&pair).is_none() {
+                engine.add_market(pair.clone())?;
+            }
+            if let Some(fees) = config.fees {
+                let schedule = FeeSchedule::new(fees.tiers, fees.fee_currency);
+                engine.set_fee_schedule(pair.clone(), schedule)?;
+            }
+            if let Some(breaker) = config.circuit_breaker {
+                engine.set_circuit_breaker(pair.clone(), breaker.into())?;
+            }
+            engine.set_risk_checks(pair, RiskChain::new(config.risk_checks))?;
+        }
+        if let Some(config) = self.rate_limits {
+            let mut limiter = RateLimiter::new();
+            for (tier, limits) in config.tiers {
+                limiter.set_tier_limits(tier, limits);
+            }
+            for (user_id, tier) in config.users {
+                limiter.set_user_tier(user_id, tier);
+            }
+            engine.set_rate_limiter(limiter);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::market_state::TradingState;
+    use crate::matching_engine::risk::RiskReject;
+    use crate::matching_engine::types::decimal::{Notional, Price, Quantity};
+    use crate::matching_engine::types::order::{OrderRecord, OrderSide};
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn settings_list_missing_markets_and_set_their_fees_and_risk_checks() {
+        let settings: MarketSettings = serde_json::from_str(
+            r#"{"markets": {"BTC/USD": {"fees": {"fee_currency": "Quote", "tiers": [
+                {"min_volume": "1000000", "maker_rate": "0", "taker_rate": "0.0005"},
+                {"min_volume": "0", "maker_rate": "0.001", "taker_rate": "0.002"}
+            ]}, "risk_checks": [{"OrderQuantityLimit": "10"}]}}}"#,
+        )
+        .unwrap();
+        let mut engine = MatchingEngine::new();
+        settings.apply(&mut engine).unwrap();
+
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        let schedule = engine.market(&pair).unwrap().fee_schedule().unwrap();
+        assert_eq!(schedule.fee_currency, FeeCurrency::Quote);
+        let tier = schedule.tier_for(Notional(dec!(10))).unwrap();
+        assert_eq!(tier.taker_rate, dec!(0.002));
+
+        let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(11)));
+        assert!(matches!(
+            engine.place_limit_order(pair, Price(dec!(100)), order),
+            Err(EngineError::RiskRejected {
+                reason: RiskReject::QuantityTooLarge { .. },
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rate_limits_follow_the_tier_the_settings_give_each_user() {
+        let maker = Uuid::new_v4();
+        let settings: MarketSettings = serde_json::from_str(&format!(
+            r#"{{"markets": {{"BTC/USD": {{}}}}, "rate_limits": {{
+                "tiers": {{"MarketMaker": {{
+                    "per_user": {{"place": {{"burst": 1, "per_second": 0}},
+                                 "cancel": {{"burst": 1, "per_second": 0}},
+                                 "amend": {{"burst": 1, "per_second": 0}}}},
+                    "per_session": {{"place": {{"burst": 1, "per_second": 0}},
+                                    "cancel": {{"burst": 1, "per_second": 0}},
+                                    "amend": {{"burst": 1, "per_second": 0}}}}
+                }}}},
+                "users": {{"{}": "MarketMaker"}}
+            }}}}"#,
+            maker
+        ))
+        .unwrap();
+        let mut engine = MatchingEngine::new();
+        settings.apply(&mut engine).unwrap();
+
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        let place = |engine: &mut MatchingEngine, user_id| {
+            let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user_id);
+            engine.place_limit_order(pair.clone(), Price(dec!(100)), order)
+        };
+        assert!(place(&mut engine, maker).is_ok());
+        assert!(matches!(
+            place(&mut engine, maker),
+            Err(EngineError::RateLimited(_))
+        ));
+        // Everyone else keeps the default limits.
+        let other = Uuid::new_v4();
+        assert!(place(&mut engine, other).is_ok());
+        assert!(place(&mut engine, other).is_ok());
+    }
+
+    #[tokio::test]
+    async fn configured_circuit_breakers_run_a_volatility_auction() {
+        let settings: MarketSettings = serde_json::from_str(
+            r#"{"markets": {"BTC/USD": {"circuit_breaker": {
+                "band_bps": 1000, "auction_duration_secs": 30
+            }}}}"#,
+        )
+        .unwrap();
+        let mut engine = MatchingEngine::new();
+        settings.apply(&mut engine).unwrap();
+
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        let trade_at = |engine: &mut MatchingEngine, price| {
+            let ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1)));
+            engine
+                .place_limit_order(pair.clone(), Price(price), ask)
+                .unwrap();
+            let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)));
+            engine.place_market_order(pair.clone(), bid)
+        };
+        assert!(trade_at(&mut engine, dec!(100)).is_ok());
+        // 20% away from the reference price, outside the 10% band.
+        assert!(matches!(
+            trade_at(&mut engine, dec!(120)),
+            Err(EngineError::CircuitBreakerTripped { .. })
+        ));
+
+        let market = engine.market(&pair).unwrap();
+        assert_eq!(market.state(), TradingState::Auction);
+        let ends_at = market.circuit_breaker().unwrap().auction_ends_at();
+        assert_eq!(ends_at, Some(engine.now() + Duration::seconds(30)));
+    }
+}
@@ -0,0 +1,102 @@
+use diesel::pg::PgConnection;
+use diesel::Connection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+pub type MigrationResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Applies every migration the database has not seen yet and returns the
+/// versions that were run.
+pub fn run_pending(database_url: &str) -> MigrationResult<Vec<String>> {
+    let mut connection = PgConnection::establish(database_url)?;
+    let applied = connection.run_pending_migrations(MIGRATIONS)?;
+    Ok(applied.iter().map(|version| version.to_string()).collect())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use std::sync::OnceLock;
+
+    /// Brings the test database up to date once per test run.
+    pub fn migrate() {
+        static MIGRATED: OnceLock<()> = OnceLock::new();
+        MIGRATED.get_or_init(|| {
+            dotenv::dotenv().ok();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            run_pending(&database_url).expect("migrations failed");
+        });
+    }
+
+    #[tokio::test]
+    async fn migrations_are_idempotent() {
+        migrate();
+        let database_url = std::env::var("DATABASE_URL").unwrap();
+        assert!(run_pending(&database_url).unwrap().is_empty());
+    }
+
+    /// Creates an empty database next to the test one, runs `setup` in it
+    /// and returns its url.
+    fn scratch_database(name: &str, setup: &str) -> String {
+        migrate();
+        let database_url = std::env::var("DATABASE_URL").unwrap();
+        let mut admin = PgConnection::establish(&database_url).unwrap();
+        admin
+            .batch_execute(&format!("DROP DATABASE IF EXISTS {name}"))
+            .unwrap();
+        admin
+            .batch_execute(&format!("CREATE DATABASE {name}"))
+            .unwrap();
+        let (server, _) = database_url.rsplit_once('/').unwrap();
+        let scratch_url = format!("{server}/{name}");
+        PgConnection::establish(&scratch_url)
+            .unwrap()
+            .batch_execute(setup)
+            .unwrap();
+        scratch_url
+    }
+
+    #[tokio::test]
+    async fn databases_created_from_init_sql_migrate() {
+        // The baseline init.sql, less the TimescaleDB extension and
+        // hypertable the test server does not have.
+        let url = scratch_database(
+            "migrate_from_init_sql",
+            "CREATE TYPE order_side AS ENUM ('bid', 'ask');
+             CREATE TYPE order_status AS ENUM ('new', 'filled', 'partially_filled', 'cancelled');
+             CREATE TABLE orders (
+                 id UUID PRIMARY KEY,
+                 created_at TIMESTAMPTZ NOT NULL,
+                 updated_at TIMESTAMPTZ NOT NULL,
+                 price DECIMAL NOT NULL,
+                 size DECIMAL NOT NULL,
+                 remaining_size DECIMAL NOT NULL,
+                 side order_side NOT NULL,
+                 status order_status NOT NULL
+             );
+             CREATE INDEX idx_orders_status ON orders(status);
+             CREATE INDEX idx_orders_side ON orders(side);
+             INSERT INTO orders VALUES (
+                 gen_random_uuid(), NOW(), NOW(), 100, 1, 1, 'bid', 'new'
+             );",
+        );
+
+        assert!(!run_pending(&url).unwrap().is_empty());
+        let mut connection = PgConnection::establish(&url).unwrap();
+        connection
+            .batch_execute(
+                "DO $$ BEGIN
+                     IF (SELECT COUNT(*) FROM orders) <> 1 THEN
+                         RAISE EXCEPTION 'orders were not kept';
+                     END IF;
+                     IF (SELECT user_id FROM orders) <> '00000000-0000-0000-0000-000000000000' THEN
+                         RAISE EXCEPTION 'orders were not given the nil user';
+                     END IF;
+                 END $$;",
+            )
+            .unwrap();
+        assert!(run_pending(&url).unwrap().is_empty());
+    }
+}
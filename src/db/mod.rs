@@ -1,2 +1,3 @@
+pub mod migrations;
 pub mod pool;
 pub mod schema;
@@ -1,15 +1,131 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "order_side"))]
+    pub struct OrderSide;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "order_status"))]
+    pub struct OrderStatus;
+}
+
+diesel::table! {
+    api_keys (key_hash) {
+        key_hash -> Bytea,
+        user_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    balance_journal (id) {
+        id -> Int8,
+        trade_id -> Nullable<Uuid>,
+        order_id -> Nullable<Uuid>,
+        user_id -> Uuid,
+        asset -> Text,
+        amount -> Numeric,
+        kind -> Text,
+        transfer_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    engine_checkpoint (id) {
+        id -> Bool,
+        last_sequence -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    markets (pair) {
+        pair -> Text,
+        price_scale -> Int4,
+        quantity_scale -> Int4,
+        trading_state -> Text,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
-    orders (id) {
-        id -> Int4,
+    use diesel::sql_types::*;
+    use super::sql_types::OrderSide;
+    use super::sql_types::OrderStatus;
+
+    orders (id, created_at) {
+        id -> Uuid,
         user_id -> Uuid,
-        token_id -> Int4,
-        order_type -> Text,
+        session_id -> Nullable<Uuid>,
+        client_order_id -> Nullable<Text>,
+        market -> Nullable<Text>,
+        side -> OrderSide,
+        status -> OrderStatus,
+        price -> Numeric,
+        size -> Numeric,
+        remaining_size -> Numeric,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        sequence -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    settlements (trade_id) {
+        trade_id -> Uuid,
+        pair -> Text,
+        settled_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OrderSide;
+
+    trades (id, executed_at) {
+        id -> Uuid,
+        sequence -> Int8,
+        market -> Text,
+        buy_order_id -> Uuid,
+        buyer_id -> Uuid,
+        sell_order_id -> Uuid,
+        seller_id -> Uuid,
+        aggressor -> Nullable<OrderSide>,
+        maker_order_id -> Nullable<Uuid>,
+        taker_order_id -> Nullable<Uuid>,
         price -> Numeric,
+        quantity -> Numeric,
+        buyer_fee_rate -> Nullable<Numeric>,
+        buyer_fee_amount -> Nullable<Numeric>,
+        buyer_fee_asset -> Nullable<Text>,
+        seller_fee_rate -> Nullable<Numeric>,
+        seller_fee_amount -> Nullable<Numeric>,
+        seller_fee_asset -> Nullable<Text>,
+        executed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    transfers (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        asset -> Text,
         amount -> Numeric,
-        status -> Text,
-        created_at -> Nullable<Timestamp>,
-        updated_at -> Nullable<Timestamp>,
+        transferred_at -> Timestamptz,
     }
 }
+
+diesel::joinable!(balance_journal -> settlements (trade_id));
+diesel::joinable!(balance_journal -> transfers (transfer_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    balance_journal,
+    engine_checkpoint,
+    markets,
+    orders,
+    settlements,
+    trades,
+    transfers,
+);
@@ -1,35 +1,150 @@
-use crate::matching_engine::types::decimal::Price;
-use chrono::NaiveDateTime;
-use diesel::prelude::Queryable;
+//! The order model shared by the service, repository and matching engine.
+//! Rows are converted to and from it only at the database boundary.
+pub use crate::matching_engine::types::order::{OrderRecord as Order, OrderStatus};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio_postgres::Row;
+use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
-#[diesel(table_name = orders)]
-pub struct Order {
-    pub id: i32,
-    pub user_id: uuid::Uuid,
-    pub token_id: i32,
-    pub order_type: String,
-    pub price: Price,
-    pub amount: Price,
-    pub status: String,
-    pub created_at: NaiveDateTime,
-    pub updated_at: NaiveDateTime,
-}
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::engine::TradingPair;
 
 impl Order {
-    pub fn from_row(row: &Row) -> Self {
-        Order {
-            id: row.get("id"),
+    /// Fails on a market that does not parse, so recovery cannot drop the
+    /// order from its book without a word.
+    pub fn from_row(row: &Row) -> Result<Self, OrderError> {
+        let id = row.get("id");
+        let pair = row
+            .get::<_, Option<String>>("market")
+            .map(|market| {
+                market.parse().map_err(|_| OrderError::Malformed {
+                    id,
+                    reason: format!("unknown market {:?}", market),
+                })
+            })
+            .transpose()?;
+        Ok(Order {
+            id,
+            pair,
             user_id: row.get("user_id"),
-            token_id: row.get("token_id"),
-            order_type: row.get("order_type"),
-            price: row.get("price"),
-            amount: row.get("amount"),
-            status: row.get("status"),
+            session_id: row.get("session_id"),
+            client_order_id: row.get("client_order_id"),
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
+            price: row.get("price"),
+            size: row.get("size"),
+            remaining_size: row.get("remaining_size"),
+            side: row.get("side"),
+            status: row.get("status"),
+        })
+    }
+}
+
+/// Position in a listing ordered newest first: the last order already seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl From<&Order> for OrderCursor {
+    fn from(order: &Order) -> Self {
+        OrderCursor {
+            created_at: order.created_at,
+            id: order.id,
+        }
+    }
+}
+
+/// Largest page the listing queries return, whatever the caller asks for.
+pub const MAX_PAGE_SIZE: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageRequest {
+    /// Start after this order; `None` for the first page.
+    pub after: Option<OrderCursor>,
+    pub limit: usize,
+}
+
+impl PageRequest {
+    #[cfg(test)]
+    pub fn first(limit: usize) -> PageRequest {
+        PageRequest { after: None, limit }
+    }
+
+    /// `limit` brought into `1..=MAX_PAGE_SIZE`. An empty page could not
+    /// point at the next one, so every page holds at least one order.
+    pub fn page_size(&self) -> usize {
+        self.limit.clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor for the following page, `None` on the last one.
+    pub next: Option<OrderCursor>,
+}
+
+impl Page<Order> {
+    /// Builds a page from up to `limit + 1` orders fetched in listing order;
+    /// the extra order only signals that another page exists.
+    pub fn from_overfetch(mut orders: Vec<Order>, limit: usize) -> Page<Order> {
+        let next = if orders.len() > limit {
+            orders.truncate(limit);
+            orders.last().map(OrderCursor::from)
+        } else {
+            None
+        };
+        Page {
+            items: orders,
+            next,
+        }
+    }
+}
+
+/// Which of a user's orders to list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderFilter {
+    pub user_id: Uuid,
+    pub pair: Option<TradingPair>,
+    /// Any status when empty.
+    pub statuses: Vec<OrderStatus>,
+    /// Inclusive lower bound on `created_at`.
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `created_at`.
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl OrderFilter {
+    pub fn for_user(user_id: Uuid) -> OrderFilter {
+        OrderFilter {
+            user_id,
+            pair: None,
+            statuses: Vec::new(),
+            from: None,
+            to: None,
         }
     }
+
+    /// Only orders that are still resting.
+    pub fn open(user_id: Uuid) -> OrderFilter {
+        OrderFilter {
+            statuses: vec![OrderStatus::New, OrderStatus::PartiallyFilled],
+            ..OrderFilter::for_user(user_id)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn matches(&self, order: &Order) -> bool {
+        order.user_id == self.user_id
+            && self
+                .pair
+                .as_ref()
+                .is_none_or(|pair| order.pair.as_ref() == Some(pair))
+            && (self.statuses.is_empty() || self.statuses.contains(&order.status))
+            && self.from.is_none_or(|from| order.created_at >= from)
+            && self.to.is_none_or(|to| order.created_at < to)
+    }
 }
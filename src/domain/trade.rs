@@ -1 +1,77 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+use uuid::Uuid;
 
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::events::{EngineEvent, SequencedEvent};
+use crate::matching_engine::fees::{Fee, Liquidity};
+use crate::matching_engine::types::order::OrderSide;
+use crate::matching_engine::types::trade::Trade;
+
+/// A trade as persisted: the execution plus the market it happened in and
+/// the sequence number of the engine event that reported it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutedTrade {
+    pub sequence: u64,
+    pub pair: TradingPair,
+    pub trade: Trade,
+}
+
+impl ExecutedTrade {
+    pub fn from_event(event: &SequencedEvent) -> Option<ExecutedTrade> {
+        match &event.event {
+            EngineEvent::Trade { pair, trade } => Some(ExecutedTrade {
+                sequence: event.sequence,
+                pair: pair.clone(),
+                trade: trade.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_row(row: &Row) -> Self {
+        let aggressor: Option<OrderSide> = row.get("aggressor");
+        let buyer_id: Uuid = row.get("buyer_id");
+        let seller_id: Uuid = row.get("seller_id");
+        // Auction trades charge both sides as makers.
+        let liquidity = |side| match aggressor {
+            Some(aggressor) if aggressor == side => Liquidity::Taker,
+            _ => Liquidity::Maker,
+        };
+        let fee = |prefix: &str, user_id, liquidity| {
+            let column = |name: &str| format!("{}_fee_{}", prefix, name);
+            let rate: Option<Decimal> = row.get(column("rate").as_str());
+            let amount: Option<Decimal> = row.get(column("amount").as_str());
+            let asset: Option<String> = row.get(column("asset").as_str());
+            Some(Fee {
+                user_id,
+                liquidity,
+                rate: rate?,
+                amount: amount?,
+                asset: asset?,
+            })
+        };
+        ExecutedTrade {
+            sequence: row.get::<_, i64>("sequence") as u64,
+            pair: row
+                .get::<_, String>("market")
+                .parse()
+                .expect("trades are stored with a BASE/QUOTE market"),
+            trade: Trade {
+                id: row.get("id"),
+                buy_order_id: row.get("buy_order_id"),
+                buyer_id,
+                sell_order_id: row.get("sell_order_id"),
+                seller_id,
+                aggressor,
+                price: row.get("price"),
+                quantity: row.get("quantity"),
+                executed_at: row.get::<_, DateTime<Utc>>("executed_at"),
+                buyer_fee: fee("buyer", buyer_id, liquidity(OrderSide::Bid)),
+                seller_fee: fee("seller", seller_id, liquidity(OrderSide::Ask)),
+            },
+        }
+    }
+}
@@ -1,10 +1,40 @@
 use deadpool_postgres;
 use std::fmt;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum OrderError {
     Database(tokio_postgres::Error),
     Pool(deadpool_postgres::PoolError),
+    NotFound(Uuid),
+    #[cfg(test)]
+    AlreadyExists(Uuid),
+    /// A stored row that does not describe a valid order.
+    Malformed {
+        id: Uuid,
+        reason: String,
+    },
+}
+
+impl OrderError {
+    /// Whether the same request may succeed if retried: the pool or the
+    /// connection failed, or the server gave up on the transaction rather
+    /// than rejecting its statements.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            OrderError::Pool(_) => true,
+            OrderError::Database(e) => match e.code() {
+                None => true,
+                // Connection exceptions, transaction rollbacks (serialization
+                // failures and deadlocks), insufficient resources and
+                // operator intervention.
+                Some(code) => matches!(&code.code()[..2], "08" | "40" | "53" | "57"),
+            },
+            OrderError::NotFound(_) | OrderError::Malformed { .. } => false,
+            #[cfg(test)]
+            OrderError::AlreadyExists(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for OrderError {
@@ -12,6 +42,12 @@ impl fmt::Display for OrderError {
         match self {
             OrderError::Database(e) => write!(f, "Database error: {}", e),
             OrderError::Pool(e) => write!(f, "Connection pool error: {}", e),
+            OrderError::NotFound(id) => write!(f, "Order {} not found", id),
+            #[cfg(test)]
+            OrderError::AlreadyExists(id) => write!(f, "Order {} already exists", id),
+            OrderError::Malformed { id, reason } => {
+                write!(f, "Order {} is malformed: {}", id, reason)
+            }
         }
     }
 }
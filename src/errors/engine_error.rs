@@ -0,0 +1,181 @@
+use std::fmt;
+use uuid::Uuid;
+
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::market_state::{MarketAction, TradingState};
+use crate::matching_engine::orderbook::OrderBookError;
+use crate::matching_engine::rate_limit::RateLimited;
+use crate::matching_engine::risk::RiskReject;
+use crate::matching_engine::types::decimal::{Price, Quantity};
+use crate::services::payment_gateway::BalanceError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineError {
+    InvalidTradingPair(String),
+    MarketNotFound(TradingPair),
+    MarketAlreadyExists(TradingPair),
+    OrderNotFound {
+        pair: TradingPair,
+        order_id: Uuid,
+    },
+    OrderBook(OrderBookError),
+    ActionNotAllowed {
+        pair: TradingPair,
+        state: TradingState,
+        action: MarketAction,
+    },
+    InvalidStateTransition {
+        pair: TradingPair,
+        from: TradingState,
+        to: TradingState,
+    },
+    CircuitBreakerTripped {
+        pair: TradingPair,
+        price: Price,
+        lower: Price,
+        upper: Price,
+    },
+    Balance(BalanceError),
+    RiskRejected {
+        pair: TradingPair,
+        order_id: Uuid,
+        reason: RiskReject,
+    },
+    RateLimited(RateLimited),
+    SessionNotFound(Uuid),
+    ClientOrderNotFound {
+        pair: TradingPair,
+        user_id: Uuid,
+        client_order_id: String,
+    },
+    DuplicateClientOrderId {
+        user_id: Uuid,
+        client_order_id: String,
+        existing_order_id: Uuid,
+    },
+    InvalidAmend {
+        pair: TradingPair,
+        order_id: Uuid,
+        reason: String,
+    },
+    InvalidOrder {
+        pair: TradingPair,
+        order_id: Uuid,
+        reason: String,
+    },
+    NotionalOutOfRange {
+        pair: TradingPair,
+        order_id: Uuid,
+        price: Price,
+        quantity: Quantity,
+    },
+    /// Deposits and withdrawals need a payment gateway to apply them to.
+    AccountsDisabled,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::InvalidTradingPair(pair) => {
+                write!(f, "{} is not a trading pair of the form BASE/QUOTE", pair)
+            }
+            EngineError::MarketNotFound(pair) => write!(
+                f,
+                "the orderbook for the given trading pair {} is not available",
+                pair
+            ),
+            EngineError::MarketAlreadyExists(pair) => {
+                write!(f, "a market for {} already exists", pair)
+            }
+            EngineError::OrderNotFound { pair, order_id } => {
+                write!(f, "order {} is not resting in {}", order_id, pair)
+            }
+            EngineError::OrderBook(e) => write!(f, "{}", e),
+            EngineError::ActionNotAllowed {
+                pair,
+                state,
+                action,
+            } => write!(f, "cannot {} while {} is {}", action, pair, state),
+            EngineError::InvalidStateTransition { pair, from, to } => {
+                write!(f, "{} cannot move from {} to {}", pair, from, to)
+            }
+            EngineError::CircuitBreakerTripped {
+                pair,
+                price,
+                lower,
+                upper,
+            } => write!(
+                f,
+                "{} would trade at {} outside the band {} - {}; volatility auction started",
+                pair, price.0, lower.0, upper.0
+            ),
+            EngineError::Balance(e) => write!(f, "{}", e),
+            EngineError::AccountsDisabled => write!(f, "balances are not tracked"),
+            EngineError::RiskRejected {
+                pair,
+                order_id,
+                reason,
+            } => write!(f, "order {} rejected in {}: {}", order_id, pair, reason),
+            EngineError::RateLimited(e) => write!(f, "{}", e),
+            EngineError::SessionNotFound(id) => write!(f, "session {} is not open", id),
+            EngineError::ClientOrderNotFound {
+                pair,
+                user_id,
+                client_order_id,
+            } => write!(
+                f,
+                "user {} has no live order {} in {}",
+                user_id, client_order_id, pair
+            ),
+            EngineError::DuplicateClientOrderId {
+                user_id,
+                client_order_id,
+                existing_order_id,
+            } => write!(
+                f,
+                "user {} already used client order id {} for order {}",
+                user_id, client_order_id, existing_order_id
+            ),
+            EngineError::InvalidAmend {
+                pair,
+                order_id,
+                reason,
+            } => write!(f, "cannot amend order {} in {}: {}", order_id, pair, reason),
+            EngineError::InvalidOrder {
+                pair,
+                order_id,
+                reason,
+            } => write!(f, "order {} in {} is invalid: {}", order_id, pair, reason),
+            EngineError::NotionalOutOfRange {
+                pair,
+                order_id,
+                price,
+                quantity,
+            } => write!(
+                f,
+                "order {} in {} is out of range: {} at {}",
+                order_id, pair, quantity.0, price.0
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<OrderBookError> for EngineError {
+    fn from(err: OrderBookError) -> EngineError {
+        EngineError::OrderBook(err)
+    }
+}
+
+impl From<BalanceError> for EngineError {
+    fn from(err: BalanceError) -> EngineError {
+        EngineError::Balance(err)
+    }
+}
+
+impl From<RateLimited> for EngineError {
+    fn from(err: RateLimited) -> EngineError {
+        EngineError::RateLimited(err)
+    }
+}
@@ -1 +1,2 @@
 pub mod custom_error;
+pub mod engine_error;
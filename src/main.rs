@@ -6,15 +6,141 @@ mod errors;
 mod matching_engine;
 mod repository;
 mod services;
+
+use api::controllers::Gateway;
+use api::routes::{serve_admin, serve_clients};
+use chrono::Utc;
+use config::config::{Config, WaitStrategyKind};
+use config::markets::MarketSettings;
+use db::pool::DB_POOL;
+use matching_engine::engine::MatchingEngine;
+use matching_engine::pipeline::{pipeline, EngineCommand};
+use matching_engine::ring_buffer::{BusySpinWait, ParkingWait, WaitStrategy, YieldingWait};
+use repository::order_repository::PgOrderRepository;
+use services::order_service::OrderService;
+use services::payment_gateway::PaymentGateway;
+use services::persistence::{
+    PersistenceConfig, PersistenceError, PersistenceHandle, PersistenceWriter,
+};
+use services::recovery::RecoveryService;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // // Load configuration
-    // let config = Config::new()?;
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::new()?;
+    let database_url = config.database_url.clone();
+    let applied =
+        tokio::task::spawn_blocking(move || db::migrations::run_pending(&database_url)).await??;
+    for version in applied {
+        println!("Applied migration {}", version);
+    }
+
+    // Every order reserves what it can spend.
+    let mut engine = MatchingEngine::new();
+    engine.set_payment_gateway(PaymentGateway::with_fee_account(config.fee_account));
+    let recovery = RecoveryService::recover_from_database(&DB_POOL, &mut engine).await?;
+    println!(
+        "Restored {} markets and {} open orders, resuming after event {}",
+        recovery.markets, recovery.orders, recovery.sequence
+    );
+    if recovery.cancelled > 0 || recovery.skipped > 0 {
+        println!(
+            "Cancelled {} orders whose session or funds are gone, skipped {} for unlisted markets",
+            recovery.cancelled, recovery.skipped
+        );
+    }
+    if let Some(path) = &config.market_settings {
+        MarketSettings::load(path)?.apply(&mut engine)?;
+    }
+
+    // Everything the engine publishes goes to the database; the process runs
+    // until the writer stops or the operator interrupts it.
+    let (persistence, writer) = PersistenceWriter::spawn(
+        DB_POOL.clone(),
+        PersistenceConfig::default(),
+        recovery.sequence,
+    );
+    if let Some(accounts) = engine.payment_gateway_mut() {
+        accounts.set_journal(persistence.persisted_sequence());
+    }
+    // Cancels issued while restoring, and markets the settings added, go out
+    // ahead of anything new.
+    for event in engine.drain_events() {
+        persistence.send(event).await?;
+    }
+    match config.wait_strategy {
+        WaitStrategyKind::BusySpin => {
+            serve(config, engine, persistence, writer, BusySpinWait).await
+        }
+        WaitStrategyKind::Yielding => {
+            serve(config, engine, persistence, writer, YieldingWait::default()).await
+        }
+        WaitStrategyKind::Parking => {
+            serve(config, engine, persistence, writer, ParkingWait::default()).await
+        }
+    }
+}
+
+/// Runs the engine behind its pipeline and serves clients and operators
+/// until the writer stops or the operator interrupts the process.
+async fn serve<W>(
+    config: Config,
+    engine: MatchingEngine,
+    persistence: PersistenceHandle,
+    mut writer: JoinHandle<Result<(), PersistenceError>>,
+    wait: W,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    W: WaitStrategy + Sync + 'static,
+{
+    let persisted = persistence.persisted_sequence();
+    let (commands, events, runner) = pipeline(engine, 1 << 16, 1 << 16, wait);
+    let runner = std::thread::spawn(move || runner.run());
+    std::thread::spawn(move || persistence.forward(events));
+
+    // Timed auctions end and lost sessions expire on the clock, not only when
+    // the next command arrives.
+    let ticks = commands.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(config.tick_interval);
+        ticks.publish(EngineCommand::Tick { now: Utc::now() });
+    });
 
-    // // Initialize database connection
-    // let db = Database::new(&config.database_url).await?;
+    let shutdown = commands.clone();
+    let gateway = Arc::new(Gateway::new(
+        OrderService::new(PgOrderRepository::new(DB_POOL.clone())),
+        commands,
+        DB_POOL.clone(),
+    ));
+    let clients = TcpListener::bind(&config.gateway_addr).await?;
+    let operators = TcpListener::bind(&config.admin_addr).await?;
+    println!(
+        "Accepting clients on {} and operators on {}",
+        config.gateway_addr, config.admin_addr
+    );
+    tokio::spawn(serve_clients(clients, gateway.clone()));
+    tokio::spawn(serve_admin(operators, gateway));
 
-    // println!("Trading engine started successfully!");
+    tokio::select! {
+        result = &mut writer => return Ok(result??),
+        signal = tokio::signal::ctrl_c() => signal?,
+    }
 
+    // Stop matching, then wait until everything the engine published is in
+    // the database.
+    shutdown.publish(EngineCommand::Shutdown);
+    let engine = tokio::task::spawn_blocking(move || runner.join())
+        .await?
+        .map_err(|_| "The matching thread panicked")?;
+    while persisted.get() < engine.sequence() && !writer.is_finished() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    if writer.is_finished() {
+        writer.await??;
+    }
+    println!("Stopped after event {}", engine.sequence());
     Ok(())
 }
@@ -0,0 +1,146 @@
+use super::types::fixed::{self, FixedPointError};
+use super::types::order::OrderSide;
+
+/// The single price an auction uncrosses at, and what executes there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Equilibrium {
+    pub price: fixed::Price,
+    pub volume: fixed::Quantity,
+    /// Quantity left unmatched at `price` on `imbalance_side`.
+    pub imbalance: fixed::Quantity,
+    pub imbalance_side: Option<OrderSide>,
+}
+
+/// Finds the equilibrium price from aggregated `(price, volume)` levels.
+///
+/// Candidates are every level price on either side. The winner maximises the
+/// executable volume, then minimises the imbalance, then sits closest to
+/// `reference`. Remaining ties go to the lower price so the result is
+/// deterministic. Returns `None` when the book does not cross.
+pub fn find_equilibrium(
+    bids: &[(fixed::Price, fixed::Quantity)],
+    asks: &[(fixed::Price, fixed::Quantity)],
+    reference: Option<fixed::Price>,
+) -> Result<Option<Equilibrium>, FixedPointError> {
+    let mut best: Option<(i128, i128, i128, fixed::Price)> = None;
+
+    for &(candidate, _) in bids.iter().chain(asks.iter()) {
+        let demand: i128 = bids
+            .iter()
+            .filter(|(price, _)| *price >= candidate)
+            .map(|(_, volume)| volume.lots() as i128)
+            .sum();
+        let supply: i128 = asks
+            .iter()
+            .filter(|(price, _)| *price <= candidate)
+            .map(|(_, volume)| volume.lots() as i128)
+            .sum();
+
+        let executable = demand.min(supply);
+        if executable == 0 {
+            continue;
+        }
+        let imbalance = demand - supply;
+        let distance = reference
+            .map(|reference| (candidate.ticks() as i128 - reference.ticks() as i128).abs())
+            .unwrap_or(0);
+
+        let key = (executable, imbalance, distance, candidate);
+        best = match best {
+            None => Some(key),
+            Some(current) if is_better(key, current) => Some(key),
+            keep => keep,
+        };
+    }
+
+    let Some((executable, imbalance, _, price)) = best else {
+        return Ok(None);
+    };
+    let to_quantity = |lots: i128| {
+        i64::try_from(lots)
+            .map(fixed::Quantity)
+            .map_err(|_| FixedPointError::Overflow)
+    };
+
+    Ok(Some(Equilibrium {
+        price,
+        volume: to_quantity(executable)?,
+        imbalance: to_quantity(imbalance.abs())?,
+        imbalance_side: match imbalance {
+            i if i > 0 => Some(OrderSide::Bid),
+            i if i < 0 => Some(OrderSide::Ask),
+            _ => None,
+        },
+    }))
+}
+
+fn is_better(
+    (executable, imbalance, distance, price): (i128, i128, i128, fixed::Price),
+    (best_executable, best_imbalance, best_distance, best_price): (i128, i128, i128, fixed::Price),
+) -> bool {
+    executable
+        .cmp(&best_executable)
+        .then(best_imbalance.abs().cmp(&imbalance.abs()))
+        .then(best_distance.cmp(&distance))
+        .then(best_price.cmp(&price))
+        .is_gt()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn level(price: i64, volume: i64) -> (fixed::Price, fixed::Quantity) {
+        (fixed::Price(price), fixed::Quantity(volume))
+    }
+
+    #[tokio::test]
+    async fn maximises_executable_volume() {
+        let bids = [level(102, 10), level(101, 10), level(100, 10)];
+        let asks = [level(99, 5), level(100, 10), level(101, 20)];
+
+        // 100 executes 15 and 102 executes 10, but 101 executes 20.
+        let equilibrium = find_equilibrium(&bids, &asks, None).unwrap().unwrap();
+        assert_eq!(equilibrium.price, fixed::Price(101));
+        assert_eq!(equilibrium.volume, fixed::Quantity(20));
+        assert_eq!(equilibrium.imbalance, fixed::Quantity(15));
+        assert_eq!(equilibrium.imbalance_side, Some(OrderSide::Ask));
+    }
+
+    #[tokio::test]
+    async fn breaks_volume_ties_on_imbalance() {
+        let bids = [level(101, 10), level(100, 8)];
+        let asks = [level(100, 10), level(101, 2)];
+
+        // Both prices execute 10; 100 leaves 8 bids over, 101 only 2 asks.
+        let equilibrium = find_equilibrium(&bids, &asks, None).unwrap().unwrap();
+        assert_eq!(equilibrium.volume, fixed::Quantity(10));
+        assert_eq!(equilibrium.imbalance, fixed::Quantity(2));
+        assert_eq!(equilibrium.imbalance_side, Some(OrderSide::Ask));
+        assert_eq!(equilibrium.price, fixed::Price(101));
+    }
+
+    #[tokio::test]
+    async fn breaks_remaining_ties_on_reference_price() {
+        let bids = [level(105, 10)];
+        let asks = [level(100, 10)];
+
+        let near_high = find_equilibrium(&bids, &asks, Some(fixed::Price(104)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(near_high.price, fixed::Price(105));
+        assert_eq!(near_high.imbalance_side, None);
+
+        let near_low = find_equilibrium(&bids, &asks, Some(fixed::Price(90)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(near_low.price, fixed::Price(100));
+    }
+
+    #[tokio::test]
+    async fn uncrossed_book_has_no_equilibrium() {
+        let bids = [level(99, 10)];
+        let asks = [level(100, 10)];
+        assert_eq!(find_equilibrium(&bids, &asks, None).unwrap(), None);
+    }
+}
@@ -0,0 +1,201 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::types::decimal::{Notional, Price, Quantity};
+use super::types::trade::Trade;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CandleInterval {
+    OneMinute,
+    FiveMinutes,
+    FifteenMinutes,
+    OneHour,
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 5] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::FifteenMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            CandleInterval::OneMinute => Duration::minutes(1),
+            CandleInterval::FiveMinutes => Duration::minutes(5),
+            CandleInterval::FifteenMinutes => Duration::minutes(15),
+            CandleInterval::OneHour => Duration::hours(1),
+            CandleInterval::OneDay => Duration::days(1),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::OneMinute => "1m",
+            CandleInterval::FiveMinutes => "5m",
+            CandleInterval::FifteenMinutes => "15m",
+            CandleInterval::OneHour => "1h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+
+    /// Start of the interval containing `time`. Intervals are aligned to
+    /// midnight UTC, the same as TimescaleDB's `time_bucket`.
+    pub fn bucket_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let width = self.duration().num_seconds();
+        let seconds = time.timestamp();
+        DateTime::from_timestamp(seconds - seconds.rem_euclid(width), 0)
+            .expect("bucket start is within chrono's range")
+    }
+}
+
+/// Open, high, low, close and volume of the trades in one interval.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub interval: CandleInterval,
+    pub open_time: DateTime<Utc>,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Quantity,
+    pub quote_volume: Notional,
+    pub trades: u64,
+}
+
+impl Candle {
+    fn opened_by(interval: CandleInterval, trade: &Trade) -> Candle {
+        Candle {
+            interval,
+            open_time: interval.bucket_start(trade.executed_at),
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.quantity,
+            quote_volume: trade.notional(),
+            trades: 1,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.quantity;
+        self.quote_volume += trade.notional();
+        self.trades += 1;
+    }
+
+    pub fn close_time(&self) -> DateTime<Utc> {
+        self.open_time + self.interval.duration()
+    }
+}
+
+/// The candle in progress at every interval for one market. Finished
+/// candles are served from the database.
+#[derive(Debug, Default)]
+pub struct CandleBuilder {
+    current: HashMap<CandleInterval, Candle>,
+}
+
+impl CandleBuilder {
+    pub fn record_trade(&mut self, trade: &Trade) {
+        for interval in CandleInterval::ALL {
+            match self.current.get_mut(&interval) {
+                // A trade stamped before the current interval belongs to a
+                // finished candle, which the continuous aggregate builds from
+                // the trades table by `executed_at`; it must not skew this one.
+                Some(candle) if trade.executed_at < candle.open_time => {}
+                Some(candle) if trade.executed_at < candle.close_time() => candle.add(trade),
+                _ => {
+                    self.current
+                        .insert(interval, Candle::opened_by(interval, trade));
+                }
+            }
+        }
+    }
+
+    /// The candle for the interval of the most recent trade.
+    #[cfg(test)]
+    pub fn current(&self, interval: CandleInterval) -> Option<&Candle> {
+        self.current.get(&interval)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::types::trade::tests::trade_at as trade;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn intervals_are_aligned_to_midnight() {
+        let at = Utc.with_ymd_and_hms(2026, 3, 14, 15, 9, 26).unwrap();
+        assert_eq!(
+            CandleInterval::FiveMinutes.bucket_start(at),
+            Utc.with_ymd_and_hms(2026, 3, 14, 15, 5, 0).unwrap()
+        );
+        assert_eq!(
+            CandleInterval::OneDay.bucket_start(at),
+            Utc.with_ymd_and_hms(2026, 3, 14, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn trades_roll_into_the_current_candle_until_it_closes() {
+        let start = Utc.with_ymd_and_hms(2026, 3, 14, 15, 0, 0).unwrap();
+        let mut builder = CandleBuilder::default();
+        builder.record_trade(&trade(start, dec!(100), dec!(1)));
+        builder.record_trade(&trade(start + Duration::seconds(20), dec!(104), dec!(2)));
+        builder.record_trade(&trade(start + Duration::seconds(40), dec!(99), dec!(1)));
+
+        let minute = builder.current(CandleInterval::OneMinute).unwrap();
+        assert_eq!(
+            (minute.open, minute.high, minute.low, minute.close),
+            (
+                Price(dec!(100)),
+                Price(dec!(104)),
+                Price(dec!(99)),
+                Price(dec!(99))
+            )
+        );
+        assert_eq!(minute.volume, Quantity(dec!(4)));
+        assert_eq!(minute.quote_volume, Notional(dec!(407)));
+        assert_eq!(minute.trades, 3);
+
+        builder.record_trade(&trade(start + Duration::seconds(65), dec!(101), dec!(1)));
+        let minute = builder.current(CandleInterval::OneMinute).unwrap();
+        assert_eq!(minute.open_time, start + Duration::minutes(1));
+        assert_eq!(minute.trades, 1);
+        let hour = builder.current(CandleInterval::OneHour).unwrap();
+        assert_eq!(hour.trades, 4);
+        assert_eq!(hour.close, Price(dec!(101)));
+    }
+
+    #[tokio::test]
+    async fn late_trades_do_not_touch_the_current_candle() {
+        let start = Utc.with_ymd_and_hms(2026, 3, 14, 15, 1, 0).unwrap();
+        let mut builder = CandleBuilder::default();
+        builder.record_trade(&trade(start, dec!(100), dec!(1)));
+        builder.record_trade(&trade(start - Duration::seconds(5), dec!(90), dec!(3)));
+
+        let minute = builder.current(CandleInterval::OneMinute).unwrap();
+        assert_eq!(minute.open_time, start);
+        assert_eq!(
+            (minute.low, minute.close),
+            (Price(dec!(100)), Price(dec!(100)))
+        );
+        assert_eq!(minute.volume, Quantity(dec!(1)));
+        assert_eq!(minute.trades, 1);
+
+        let hour = builder.current(CandleInterval::OneHour).unwrap();
+        assert_eq!(hour.trades, 2);
+        assert_eq!(hour.low, Price(dec!(90)));
+    }
+}
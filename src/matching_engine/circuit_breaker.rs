@@ -0,0 +1,149 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+use super::types::decimal::Price;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Largest allowed distance from the reference price, in basis points.
+    pub band_bps: u32,
+    /// Trades younger than this make up the rolling reference price.
+    pub reference_window: Duration,
+    /// How long the volatility auction runs before matching resumes.
+    pub auction_duration: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            band_bps: 500,
+            reference_window: Duration::minutes(5),
+            auction_duration: Duration::minutes(2),
+        }
+    }
+}
+
+/// Tracks a market's rolling reference price and the dynamic band around it.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    trades: VecDeque<(DateTime<Utc>, Price)>,
+    auction_ends_at: Option<DateTime<Utc>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            config,
+            trades: VecDeque::new(),
+            auction_ends_at: None,
+        }
+    }
+
+    pub fn record_trade(&mut self, price: Price, executed_at: DateTime<Utc>) {
+        self.trades.push_back((executed_at, price));
+        self.expire(executed_at);
+    }
+
+    /// Drops trades older than the window but keeps the latest one, so a quiet
+    /// market still has a reference.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.config.reference_window;
+        while self.trades.len() > 1 && self.trades.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.trades.pop_front();
+        }
+    }
+
+    /// Average trade price over the reference window.
+    pub fn reference_price(&self) -> Option<Price> {
+        if self.trades.is_empty() {
+            return None;
+        }
+        let total: Decimal = self.trades.iter().map(|(_, price)| price.0).sum();
+        Some(Price(total / Decimal::from(self.trades.len())))
+    }
+
+    /// Lowest and highest price a trade may print at right now.
+    pub fn band(&self) -> Option<(Price, Price)> {
+        let reference = self.reference_price()?;
+        let width = reference.0 * Decimal::from(self.config.band_bps) / Decimal::from(10_000);
+        Some((Price(reference.0 - width), Price(reference.0 + width)))
+    }
+
+    pub fn within_band(&self, price: Price) -> bool {
+        match self.band() {
+            Some((lower, upper)) => price >= lower && price <= upper,
+            None => true,
+        }
+    }
+
+    /// Starts the volatility auction clock.
+    pub fn trip(&mut self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let ends_at = now + self.config.auction_duration;
+        self.auction_ends_at = Some(ends_at);
+        ends_at
+    }
+
+    #[cfg(test)]
+    pub fn auction_ends_at(&self) -> Option<DateTime<Utc>> {
+        self.auction_ends_at
+    }
+
+    pub fn auction_due(&self, now: DateTime<Utc>) -> bool {
+        self.auction_ends_at.is_some_and(|ends_at| now >= ends_at)
+    }
+
+    /// Clears the auction clock and restarts the reference from the uncross price.
+    pub fn end_auction(&mut self, uncross_price: Option<Price>, now: DateTime<Utc>) {
+        self.auction_ends_at = None;
+        if let Some(price) = uncross_price {
+            self.trades.clear();
+            self.trades.push_back((now, price));
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn band_follows_the_rolling_reference() {
+        let mut breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            band_bps: 1_000,
+            reference_window: Duration::seconds(60),
+            auction_duration: Duration::seconds(30),
+        });
+        assert!(breaker.within_band(Price(dec!(1_000_000))));
+
+        let start = Utc::now();
+        breaker.record_trade(Price(dec!(100)), start);
+        breaker.record_trade(Price(dec!(110)), start + Duration::seconds(10));
+        assert_eq!(breaker.reference_price(), Some(Price(dec!(105))));
+        assert_eq!(
+            breaker.band(),
+            Some((Price(dec!(94.5)), Price(dec!(115.5))))
+        );
+
+        // The first trade ages out of the window.
+        breaker.record_trade(Price(dec!(120)), start + Duration::seconds(65));
+        assert_eq!(breaker.reference_price(), Some(Price(dec!(115))));
+        assert!(!breaker.within_band(Price(dec!(100))));
+    }
+
+    #[tokio::test]
+    async fn auction_runs_for_the_configured_duration() {
+        let mut breaker = CircuitBreaker::new(CircuitBreakerConfig::default());
+        let now = Utc::now();
+        let ends_at = breaker.trip(now);
+
+        assert!(!breaker.auction_due(now));
+        assert!(breaker.auction_due(ends_at));
+
+        breaker.end_auction(Some(Price(dec!(80))), ends_at);
+        assert_eq!(breaker.auction_ends_at(), None);
+        assert_eq!(breaker.reference_price(), Some(Price(dec!(80))));
+    }
+}
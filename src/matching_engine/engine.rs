@@ -1,18 +1,35 @@
-#![allow(dead_code)]
+use super::candles::CandleBuilder;
+use super::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig};
+use super::events::{CancelReason, EngineEvent, SequencedEvent};
+use super::fees::{FeeCurrency, FeeEngine, FeeSchedule};
+use super::market_state::{MarketAction, TradingState};
+use super::orderbook::{BookMode, BookSnapshot, OrderBook};
+use super::rate_limit::{RateLimitedAction, RateLimiter};
+use super::risk::{OrderContext, RiskChain};
+use super::session::{SessionConfig, SessionKind, SessionRegistry};
+use super::ticker::{Ticker, TickerStats};
+use super::types::decimal::{Notional, Price, Quantity};
+use super::types::fixed::Precision;
+use super::types::order::{OrderRecord, OrderSide};
+use super::types::trade::Trade;
+use crate::errors::engine_error::EngineError;
+use crate::services::payment_gateway::{BalanceError, PaymentGateway};
+use crate::services::settlement::Transfer;
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
-
-use super::orderbook::OrderBook;
-use super::types::order::OrderRecord;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::result::Result;
+use std::str::FromStr;
+use uuid::Uuid;
 
 /// Represents a trading pair in a cryptocurrency or traditional market
 ///
 /// # Fields
 /// * `base` - The base currency/asset (e.g., BTC in BTC/USD)
 /// * `quote` - The quote currency/asset (e.g., USD in BTC/USD)
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct TradingPair {
     base: String,
     quote: String,
@@ -22,6 +39,14 @@ impl TradingPair {
     pub fn new(base: String, quote: String) -> TradingPair {
         TradingPair { base, quote }
     }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
 }
 
 impl Display for TradingPair {
@@ -30,38 +55,2913 @@ impl Display for TradingPair {
     }
 }
 
+/// Parses the `BASE/QUOTE` form written by `Display`.
+impl FromStr for TradingPair {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((base, quote))
+                if !base.is_empty() && !quote.is_empty() && !quote.contains('/') =>
+            {
+                Ok(TradingPair::new(base.to_string(), quote.to_string()))
+            }
+            _ => Err(EngineError::InvalidTradingPair(s.to_string())),
+        }
+    }
+}
+
+/// A single market: its order book and where it is in its trading lifecycle.
+#[derive(Debug)]
+pub struct Market {
+    orderbook: OrderBook,
+    state: TradingState,
+    circuit_breaker: Option<CircuitBreaker>,
+    fee_schedule: Option<FeeSchedule>,
+    risk: RiskChain,
+    /// Net base bought minus sold, per user.
+    positions: HashMap<Uuid, Quantity>,
+    candles: CandleBuilder,
+    ticker: Ticker,
+}
+
+impl Market {
+    pub fn orderbook(&self) -> &OrderBook {
+        &self.orderbook
+    }
+
+    #[cfg(test)]
+    pub fn state(&self) -> TradingState {
+        self.state
+    }
+
+    #[cfg(test)]
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    #[cfg(test)]
+    pub fn fee_schedule(&self) -> Option<&FeeSchedule> {
+        self.fee_schedule.as_ref()
+    }
+
+    pub fn position(&self, user_id: Uuid) -> Quantity {
+        self.positions
+            .get(&user_id)
+            .copied()
+            .unwrap_or(Quantity::ZERO)
+    }
+
+    /// Rolling 24-hour statistics, `None` if the market has not traded in
+    /// that time.
+    pub fn ticker(&self) -> Option<TickerStats> {
+        self.ticker.stats()
+    }
+}
+
+/// What is left of a market after it is delisted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ArchivedMarket {
+    pub pair: TradingPair,
+    pub reason: String,
+    pub delisted_at: DateTime<Utc>,
+    /// Engine sequence number of the `MarketDelisted` event.
+    pub sequence: u64,
+    pub last_trade_price: Option<Price>,
+    /// Depth immediately before the mass cancellation.
+    pub final_book: BookSnapshot,
+    pub cancelled_orders: Vec<OrderRecord>,
+}
+
+/// What `MatchingEngine::restore` did with the orders it was given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoredOrders {
+    /// Put back on their books.
+    pub resting: usize,
+    /// Cancelled because the session they were entered on is gone, or the
+    /// funds behind them are.
+    pub cancelled: usize,
+    /// Left alone for want of a listed market.
+    pub skipped: usize,
+}
+
+/// Identifies a resting order either by the engine's id or by the client's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRef {
+    Id(Uuid),
+    Client {
+        user_id: Uuid,
+        client_order_id: String,
+    },
+}
+
+/// Selects the orders a mass cancel removes. `None` fields match everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MassCancelFilter {
+    pub user_id: Option<Uuid>,
+    pub pair: Option<TradingPair>,
+    pub side: Option<OrderSide>,
+    /// Inclusive bounds on the resting price.
+    pub price_range: Option<(Price, Price)>,
+}
+
+/// Client order ids each user may not reuse, counting orders that have
+/// already filled or been cancelled.
+const CLIENT_ORDER_ID_WINDOW: usize = 1_000;
+
+/// A user's most recently accepted client order ids, oldest first.
+#[derive(Debug, Default)]
+struct RecentClientOrderIds {
+    order_ids: HashMap<String, Uuid>,
+    oldest_first: VecDeque<String>,
+}
+
+impl RecentClientOrderIds {
+    fn get(&self, client_order_id: &str) -> Option<Uuid> {
+        self.order_ids.get(client_order_id).copied()
+    }
+
+    fn insert(&mut self, client_order_id: String, order_id: Uuid) {
+        if self
+            .order_ids
+            .insert(client_order_id.clone(), order_id)
+            .is_none()
+        {
+            self.oldest_first.push_back(client_order_id);
+        }
+        while self.oldest_first.len() > CLIENT_ORDER_ID_WINDOW {
+            if let Some(evicted) = self.oldest_first.pop_front() {
+                self.order_ids.remove(&evicted);
+            }
+        }
+    }
+}
+
 pub struct MatchingEngine {
-    orderbooks: HashMap<TradingPair, OrderBook>,
+    markets: HashMap<TradingPair, Market>,
+    archived: HashMap<TradingPair, ArchivedMarket>,
+    sequence: u64,
+    events: Vec<SequencedEvent>,
+    fees: FeeEngine,
+    /// When set, orders must be funded and fills move balances.
+    accounts: Option<PaymentGateway>,
+    rate_limiter: Option<RateLimiter>,
+    sessions: SessionRegistry,
+    client_order_ids: HashMap<Uuid, RecentClientOrderIds>,
+    /// Time of the command being processed. Every timestamp the engine
+    /// stamps and every timer it checks reads this, never the system clock.
+    now: DateTime<Utc>,
 }
 
 impl MatchingEngine {
     pub fn new() -> MatchingEngine {
         MatchingEngine {
-            orderbooks: HashMap::new(),
+            markets: HashMap::new(),
+            archived: HashMap::new(),
+            sequence: 0,
+            events: Vec::new(),
+            fees: FeeEngine::new(),
+            accounts: None,
+            rate_limiter: None,
+            sessions: SessionRegistry::new(),
+            client_order_ids: HashMap::new(),
+            now: DateTime::UNIX_EPOCH,
         }
     }
 
-    pub fn add_market(&mut self, pair: TradingPair) {
-        self.orderbooks.insert(pair.clone(), OrderBook::new());
-        println!("Opening a new orderbook for market {:?}", pair.to_string());
+    /// The engine's clock: the time of the latest command.
+    #[cfg(test)]
+    pub fn now(&self) -> DateTime<Utc> {
+        self.now
     }
 
-    pub fn place_limit_order(
+    /// Moves the clock to `now`, the time the next command was issued. The
+    /// clock never runs backwards, so commands stamped out of order by
+    /// different gateways still see time advance.
+    pub fn set_clock(&mut self, now: DateTime<Utc>) {
+        self.now = self.now.max(now);
+    }
+
+    /// Sequence number of the last emitted event.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Stamps `event` with the next sequence number and queues it for publishing.
+    pub fn emit(&mut self, event: EngineEvent) {
+        self.sequence += 1;
+        self.events.push(SequencedEvent {
+            sequence: self.sequence,
+            emitted_at: self.now,
+            event,
+        });
+    }
+
+    /// Hands out every event emitted since the last drain, oldest first.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, SequencedEvent> {
+        self.events.drain(..)
+    }
+
+    pub fn market(&self, pair: &TradingPair) -> Option<&Market> {
+        self.markets.get(pair)
+    }
+
+    /// Rolling 24-hour statistics for `pair`; `None` for unknown markets and
+    /// markets with no trades in the window.
+    #[cfg(test)]
+    pub fn ticker(&self, pair: &TradingPair) -> Option<TickerStats> {
+        self.markets.get(pair).and_then(Market::ticker)
+    }
+
+    pub fn add_market(&mut self, pair: TradingPair) -> Result<(), EngineError> {
+        self.add_market_with_precision(pair, Precision::default())
+    }
+
+    /// Opens a market whose prices and quantities are quoted to `precision`.
+    pub fn add_market_with_precision(
+        &mut self,
+        pair: TradingPair,
+        precision: Precision,
+    ) -> Result<(), EngineError> {
+        self.add_market_in_state(pair, precision, TradingState::Continuous)
+    }
+
+    /// Lists a market in `state`, e.g. `TradingState::PreOpen` ahead of an
+    /// opening auction.
+    pub fn add_market_in_state(
+        &mut self,
+        pair: TradingPair,
+        precision: Precision,
+        state: TradingState,
+    ) -> Result<(), EngineError> {
+        if self.markets.contains_key(&pair) {
+            return Err(EngineError::MarketAlreadyExists(pair));
+        }
+        self.insert_market(pair.clone(), precision, state);
+        self.emit(EngineEvent::MarketAdded {
+            pair,
+            precision,
+            state,
+        });
+        Ok(())
+    }
+
+    fn insert_market(&mut self, pair: TradingPair, precision: Precision, state: TradingState) {
+        let mut orderbook = OrderBook::with_precision(precision);
+        if state == TradingState::Auction {
+            orderbook.start_auction();
+        }
+        self.markets.insert(
+            pair.clone(),
+            Market {
+                orderbook,
+                state,
+                circuit_breaker: None,
+                fee_schedule: None,
+                risk: RiskChain::default(),
+                positions: HashMap::new(),
+                candles: CandleBuilder::default(),
+                ticker: Ticker::default(),
+            },
+        );
+    }
+
+    /// Reopens a market loaded at startup as it was left, without
+    /// publishing it again.
+    pub fn restore_market(
+        &mut self,
+        pair: TradingPair,
+        precision: Precision,
+        state: TradingState,
+    ) -> Result<(), EngineError> {
+        if self.markets.contains_key(&pair) {
+            return Err(EngineError::MarketAlreadyExists(pair));
+        }
+        self.insert_market(pair, precision, state);
+        Ok(())
+    }
+
+    /// Rebuilds the books from orders loaded at startup and resumes
+    /// numbering events after `sequence`, the last one already persisted.
+    ///
+    /// Markets must be restored first. Orders must come in time priority;
+    /// each rests at its own price without matching. Orders with no market,
+    /// or one that is not listed, are skipped. A restart ends every session,
+    /// so orders entered on one are cancelled as if it had disconnected
+    /// rather than left resting without a session to guard them. When funds
+    /// are checked, orders whose owner can no longer cover them are cancelled
+    /// too.
+    pub fn restore(
+        &mut self,
+        orders: impl IntoIterator<Item = OrderRecord>,
+        sequence: u64,
+    ) -> Result<RestoredOrders, EngineError> {
+        self.sequence = self.sequence.max(sequence);
+        let mut restored = RestoredOrders::default();
+        for order in orders {
+            let Some(pair) = order
+                .pair
+                .clone()
+                .filter(|pair| self.markets.contains_key(pair))
+            else {
+                restored.skipped += 1;
+                continue;
+            };
+            if let Some(session_id) = order.session_id {
+                self.emit(EngineEvent::OrderCancelled {
+                    pair,
+                    order_id: order.id,
+                    remaining: order.remaining_size,
+                    reason: CancelReason::SessionDisconnected(session_id),
+                });
+                restored.cancelled += 1;
+                continue;
+            }
+            Self::check_notional(&pair, order.id, order.price, order.remaining_size)?;
+            let fee_rate = self.fee_reserve_rate(&pair, order.side);
+            let reserved = match self.accounts.as_mut() {
+                Some(accounts) => accounts.reserve_with_fee(
+                    &pair,
+                    order.id,
+                    order.user_id,
+                    order.side,
+                    order.price,
+                    order.remaining_size,
+                    fee_rate,
+                ),
+                None => Ok(()),
+            };
+            match reserved {
+                Ok(()) => {}
+                Err(BalanceError::InsufficientFunds { .. }) => {
+                    self.emit(EngineEvent::OrderCancelled {
+                        pair,
+                        order_id: order.id,
+                        remaining: order.remaining_size,
+                        reason: CancelReason::InsufficientFunds,
+                    });
+                    restored.cancelled += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            let price = order.price;
+            self.markets
+                .get_mut(&pair)
+                .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))?
+                .orderbook
+                .add_limit_order(price, order)?;
+            restored.resting += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Cancels every resting order, moves the market to `Delisted` and
+    /// archives its final state. The market stays listed so later commands
+    /// are rejected rather than reported as unknown.
+    pub fn delist_market(
+        &mut self,
+        pair: TradingPair,
+        reason: String,
+    ) -> Result<&ArchivedMarket, EngineError> {
+        let now = self.now;
+        let market = self.market_mut(&pair)?;
+        let from = market.state;
+        if !from.can_transition_to(TradingState::Delisted) {
+            return Err(EngineError::InvalidStateTransition {
+                pair,
+                from,
+                to: TradingState::Delisted,
+            });
+        }
+
+        let final_book = market.orderbook.snapshot();
+        let cancelled_orders = market.orderbook.cancel_all(now);
+        let last_trade_price = market.orderbook.last_trade_price();
+        market.state = TradingState::Delisted;
+
+        self.emit(EngineEvent::TradingStateChanged {
+            pair: pair.clone(),
+            from,
+            to: TradingState::Delisted,
+        });
+        for order in cancelled_orders.iter() {
+            self.release_funds(order.id);
+            self.emit(EngineEvent::OrderCancelled {
+                pair: pair.clone(),
+                order_id: order.id,
+                remaining: order.remaining_size,
+                reason: CancelReason::MarketDelisted(reason.clone()),
+            });
+        }
+        self.emit(EngineEvent::MarketDelisted {
+            pair: pair.clone(),
+            reason: reason.clone(),
+            cancelled_orders: cancelled_orders.len(),
+        });
+
+        let archive = ArchivedMarket {
+            pair: pair.clone(),
+            reason,
+            delisted_at: self.now,
+            sequence: self.sequence,
+            last_trade_price,
+            final_book,
+            cancelled_orders,
+        };
+        self.archived.insert(pair.clone(), archive);
+        Ok(&self.archived[&pair])
+    }
+
+    /// Delists the market if needed and drops it, so the pair can be listed again.
+    pub fn remove_market(
+        &mut self,
+        pair: TradingPair,
+        reason: String,
+    ) -> Result<ArchivedMarket, EngineError> {
+        let state = self.market_mut(&pair)?.state;
+        if state != TradingState::Delisted {
+            self.delist_market(pair.clone(), reason)?;
+        }
+        // The archive is the only record of the cancelled orders, so the
+        // market is kept unless there is one to hand back.
+        let archive = self
+            .archived
+            .remove(&pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))?;
+        self.markets.remove(&pair);
+        self.emit(EngineEvent::MarketRemoved { pair });
+        Ok(archive)
+    }
+
+    fn market_mut(&mut self, pair: &TradingPair) -> Result<&mut Market, EngineError> {
+        self.markets
+            .get_mut(pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))
+    }
+
+    /// Looks up the market's book, provided its current state accepts `action`.
+    fn orderbook_for(
+        &mut self,
+        pair: &TradingPair,
+        action: MarketAction,
+    ) -> Result<&mut OrderBook, EngineError> {
+        let market = self.market_mut(pair)?;
+        if !market.state.allows(action) {
+            return Err(EngineError::ActionNotAllowed {
+                pair: pair.clone(),
+                state: market.state,
+                action,
+            });
+        }
+        Ok(&mut market.orderbook)
+    }
+
+    /// Arms a volatility circuit breaker on the market.
+    pub fn set_circuit_breaker(
         &mut self,
         pair: TradingPair,
-        price: Decimal,
-        order: OrderRecord,
-    ) -> Result<(), String> {
-        match self.orderbooks.get_mut(&pair) {
-            Some(orderbook) => {
-                orderbook.add_limit_order(price, order);
-                println!("Placed market order at {}", price);
+        config: CircuitBreakerConfig,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?.circuit_breaker = Some(CircuitBreaker::new(config));
+        Ok(())
+    }
+
+    /// Turns on funds checks: from now on every order reserves what it can
+    /// spend and every trade settles against `gateway`. Orders already
+    /// resting reserve now, and those `gateway` cannot fund are cancelled.
+    pub fn set_payment_gateway(&mut self, gateway: PaymentGateway) {
+        self.accounts = Some(gateway);
+        let mut pairs = self.markets.keys().cloned().collect::<Vec<_>>();
+        pairs.sort_by_key(|pair| pair.to_string());
+        for pair in pairs {
+            self.reserve_resting(&pair);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn payment_gateway(&self) -> Option<&PaymentGateway> {
+        self.accounts.as_ref()
+    }
+
+    pub fn payment_gateway_mut(&mut self) -> Option<&mut PaymentGateway> {
+        self.accounts.as_mut()
+    }
+
+    /// Credits `amount` of `asset` to `user_id` from outside the exchange.
+    pub fn deposit(
+        &mut self,
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        self.accounts
+            .as_mut()
+            .ok_or(EngineError::AccountsDisabled)?
+            .deposit(user_id, &asset, amount)?;
+        self.emit_transfer(transfer_id, user_id, asset, amount);
+        Ok(())
+    }
+
+    /// Pays `amount` of `asset` out of what `user_id` has available.
+    pub fn withdraw(
+        &mut self,
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    ) -> Result<(), EngineError> {
+        self.accounts
+            .as_mut()
+            .ok_or(EngineError::AccountsDisabled)?
+            .withdraw(user_id, &asset, amount)?;
+        self.emit_transfer(transfer_id, user_id, asset, -amount);
+        Ok(())
+    }
+
+    fn emit_transfer(&mut self, id: Uuid, user_id: Uuid, asset: String, amount: Decimal) {
+        let transfer = Transfer {
+            id,
+            user_id,
+            asset,
+            amount,
+            transferred_at: self.now,
+        };
+        self.emit(EngineEvent::BalanceTransferred { transfer });
+    }
+
+    /// Share of an order's reservation set aside for fees: the most the
+    /// market can charge, when fees are paid in the asset the order reserves.
+    fn fee_reserve_rate(&self, pair: &TradingPair, side: OrderSide) -> Decimal {
+        let Some(schedule) = self
+            .markets
+            .get(pair)
+            .and_then(|market| market.fee_schedule.as_ref())
+        else {
+            return Decimal::ZERO;
+        };
+        match (side, schedule.fee_currency) {
+            (OrderSide::Bid, FeeCurrency::Quote) | (OrderSide::Ask, FeeCurrency::Base) => {
+                schedule.max_rate()
+            }
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// Replaces the hold of every order resting on `pair` with one for its
+    /// open quantity at the market's current fee rates, so holds keep
+    /// covering every trade. Orders whose owner cannot fund the new hold are
+    /// cancelled.
+    fn reserve_resting(&mut self, pair: &TradingPair) {
+        let bid_rate = self.fee_reserve_rate(pair, OrderSide::Bid);
+        let ask_rate = self.fee_reserve_rate(pair, OrderSide::Ask);
+        let now = self.now;
+        let (Some(accounts), Some(market)) = (self.accounts.as_mut(), self.markets.get_mut(pair))
+        else {
+            return;
+        };
+        let unfunded = market.orderbook.cancel_where(
+            |order| {
+                accounts.release(order.id);
+                let fee_rate = match order.side {
+                    OrderSide::Bid => bid_rate,
+                    OrderSide::Ask => ask_rate,
+                };
+                accounts
+                    .reserve_with_fee(
+                        pair,
+                        order.id,
+                        order.user_id,
+                        order.side,
+                        order.price,
+                        order.remaining_size,
+                        fee_rate,
+                    )
+                    .is_err()
+            },
+            now,
+        );
+        for order in unfunded {
+            self.emit(EngineEvent::OrderCancelled {
+                pair: pair.clone(),
+                order_id: order.id,
+                remaining: order.remaining_size,
+                reason: CancelReason::InsufficientFunds,
+            });
+        }
+    }
+
+    fn release_funds(&mut self, order_id: Uuid) {
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.release(order_id);
+        }
+    }
+
+    /// Charges maker and taker fees on the market's trades from now on.
+    ///
+    /// Resting orders reserve again for the fees they may now pay, and those
+    /// their owner cannot fund are cancelled.
+    pub fn set_fee_schedule(
+        &mut self,
+        pair: TradingPair,
+        schedule: FeeSchedule,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?.fee_schedule = Some(schedule);
+        self.reserve_resting(&pair);
+        Ok(())
+    }
+
+    /// Counts volume a user traded on `day` before a restart towards their
+    /// fee tier.
+    pub fn restore_fee_volume(&mut self, user_id: Uuid, day: NaiveDate, notional: Notional) {
+        self.fees.restore_volume(user_id, day, notional);
+    }
+
+    #[cfg(test)]
+    pub fn fee_engine(&self) -> &FeeEngine {
+        &self.fees
+    }
+
+    /// Turns on per-user and per-session request limits.
+    pub fn set_rate_limiter(&mut self, limiter: RateLimiter) {
+        self.rate_limiter = Some(limiter);
+    }
+
+    /// Runs before any other work so a flooding client costs as little as
+    /// possible.
+    fn check_rate_limit(
+        &mut self,
+        action: RateLimitedAction,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+    ) -> Result<(), EngineError> {
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            limiter.check(action, user_id, session_id, self.now)?;
+        }
+        Ok(())
+    }
+
+    /// Requests sent through a session must come from its user while it is
+    /// connected. Anything else could not be cancelled on disconnect, and
+    /// would dodge the session's rate limit.
+    fn check_session(&self, user_id: Uuid, session_id: Option<Uuid>) -> Result<(), EngineError> {
+        let Some(session_id) = session_id else {
+            return Ok(());
+        };
+        match self.sessions.get(session_id) {
+            Some(session) if session.user_id == user_id && session.lost_at(self.now).is_none() => {
                 Ok(())
             }
-            None => Err(format!(
-                "the orderbook for the given trading pair {} is not available",
-                pair.to_string()
-            )),
+            _ => Err(EngineError::SessionNotFound(session_id)),
+        }
+    }
+
+    /// Replaces the market's pre-trade checks.
+    pub fn set_risk_checks(
+        &mut self,
+        pair: TradingPair,
+        checks: RiskChain,
+    ) -> Result<(), EngineError> {
+        self.market_mut(&pair)?.risk = checks;
+        Ok(())
+    }
+
+    /// Rejects an order whose client order id the user has already used,
+    /// whether on a live order in any market or on one of their last
+    /// `CLIENT_ORDER_ID_WINDOW` orders. A gateway retrying a timed-out
+    /// submission gets the id of the order that did go through, even if it
+    /// has filled since.
+    fn check_client_order_id(&self, order: &OrderRecord) -> Result<(), EngineError> {
+        let Some(client_order_id) = order.client_order_id.as_deref() else {
+            return Ok(());
+        };
+        let recent = self
+            .client_order_ids
+            .get(&order.user_id)
+            .and_then(|recent| recent.get(client_order_id));
+        let existing = recent.or_else(|| {
+            self.markets.values().find_map(|market| {
+                market
+                    .orderbook
+                    .order_for_client_id(order.user_id, client_order_id)
+            })
+        });
+        match existing {
+            Some(existing_order_id) => Err(EngineError::DuplicateClientOrderId {
+                user_id: order.user_id,
+                client_order_id: client_order_id.to_string(),
+                existing_order_id,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Remembers an accepted order's client order id against reuse.
+    fn record_client_order_id(
+        &mut self,
+        order_id: Uuid,
+        user_id: Uuid,
+        client_order_id: Option<String>,
+    ) {
+        if let Some(client_order_id) = client_order_id {
+            self.client_order_ids
+                .entry(user_id)
+                .or_default()
+                .insert(client_order_id, order_id);
+        }
+    }
+
+    /// Finds the engine id of a live order in `pair`.
+    pub fn resolve_order(&self, pair: &TradingPair, order: &OrderRef) -> Result<Uuid, EngineError> {
+        let orderbook = self
+            .market(pair)
+            .ok_or_else(|| EngineError::MarketNotFound(pair.clone()))?
+            .orderbook();
+        let order_id = match order {
+            OrderRef::Id(order_id) => orderbook.handle_of(*order_id).map(|_| *order_id),
+            OrderRef::Client {
+                user_id,
+                client_order_id,
+            } => orderbook.order_for_client_id(*user_id, client_order_id),
+        };
+        order_id.ok_or_else(|| match order {
+            OrderRef::Id(order_id) => EngineError::OrderNotFound {
+                pair: pair.clone(),
+                order_id: *order_id,
+            },
+            OrderRef::Client {
+                user_id,
+                client_order_id,
+            } => EngineError::ClientOrderNotFound {
+                pair: pair.clone(),
+                user_id: *user_id,
+                client_order_id: client_order_id.clone(),
+            },
+        })
+    }
+
+    /// Rejects an order the book cannot take: no open quantity, more open
+    /// than it was entered for, or a limit price at or below zero. Commands
+    /// can be published straight onto the ring, so this runs whatever the
+    /// gateway checked.
+    fn check_order(
+        pair: &TradingPair,
+        order: &OrderRecord,
+        limit_price: Option<Price>,
+    ) -> Result<(), EngineError> {
+        let reason = if order.remaining_size <= Quantity::ZERO {
+            "quantity must be greater than zero"
+        } else if order.remaining_size > order.size {
+            "remaining quantity exceeds the order size"
+        } else if limit_price.is_some_and(|price| price <= Price::ZERO) {
+            "price must be greater than zero"
+        } else {
+            return Ok(());
+        };
+        Err(EngineError::InvalidOrder {
+            pair: pair.clone(),
+            order_id: order.id,
+            reason: reason.to_string(),
+        })
+    }
+
+    /// Rejects an order whose notional at `price` is too large to represent.
+    ///
+    /// Runs before anything multiplies price by quantity. A trade is never
+    /// worth more than both of its orders, so trades stay in range too.
+    fn check_notional(
+        pair: &TradingPair,
+        order_id: Uuid,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<(), EngineError> {
+        match price.checked_mul(quantity) {
+            Some(_) => Ok(()),
+            None => Err(EngineError::NotionalOutOfRange {
+                pair: pair.clone(),
+                order_id,
+                price,
+                quantity,
+            }),
+        }
+    }
+
+    /// Runs the market's pre-trade checks against an incoming order.
+    fn check_risk(
+        &mut self,
+        pair: &TradingPair,
+        order: &OrderRecord,
+        limit_price: Option<Price>,
+    ) -> Result<(), EngineError> {
+        let market = self.market_mut(pair)?;
+        if market.risk.is_empty() {
+            return Ok(());
+        }
+        let price = match limit_price {
+            Some(price) => Some(price),
+            None => market
+                .orderbook
+                .execution_price_range(order.side, None, order.remaining_size)?
+                .map(|(_, last)| last),
+        };
+        let reference_price = market
+            .circuit_breaker
+            .as_ref()
+            .and_then(|breaker| breaker.reference_price())
+            .or(market.orderbook.last_trade_price());
+        let context = OrderContext {
+            user_id: order.user_id,
+            side: order.side,
+            price,
+            quantity: order.remaining_size,
+            market_order: limit_price.is_none(),
+            // An amended order is already resting and should not count against itself.
+            open_orders: market.orderbook.open_orders_for(order.user_id)
+                - usize::from(market.orderbook.handle_of(order.id).is_some()),
+            position: market.position(order.user_id),
+            reference_price,
+        };
+        market
+            .risk
+            .check(&context)
+            .map_err(|reason| EngineError::RiskRejected {
+                pair: pair.clone(),
+                order_id: order.id,
+                reason,
+            })
+    }
+
+    /// Rejects an incoming order that would print outside the market's band,
+    /// and moves the market into a timed volatility auction.
+    fn check_circuit_breaker(
+        &mut self,
+        pair: &TradingPair,
+        side: OrderSide,
+        limit_price: Option<Price>,
+        quantity: Quantity,
+    ) -> Result<(), EngineError> {
+        let now = self.now;
+        let market = self.market_mut(pair)?;
+        let Some(breaker) = market.circuit_breaker.as_mut() else {
+            return Ok(());
+        };
+        if market.state != TradingState::Continuous {
+            return Ok(());
+        }
+        let (Some((lower, upper)), Some(reference)) = (breaker.band(), breaker.reference_price())
+        else {
+            return Ok(());
+        };
+        let Some((first, last)) =
+            market
+                .orderbook
+                .execution_price_range(side, limit_price, quantity)?
+        else {
+            return Ok(());
+        };
+        let trigger = [first, last]
+            .into_iter()
+            .find(|price| !breaker.within_band(*price));
+        let Some(trigger) = trigger else {
+            return Ok(());
+        };
+
+        let auction_ends_at = breaker.trip(now);
+        self.set_trading_state(pair.clone(), TradingState::Auction)?;
+        self.emit(EngineEvent::CircuitBreakerTripped {
+            pair: pair.clone(),
+            trigger_price: trigger,
+            reference_price: reference,
+            auction_ends_at,
+        });
+        Err(EngineError::CircuitBreakerTripped {
+            pair: pair.clone(),
+            price: trigger,
+            lower,
+            upper,
+        })
+    }
+
+    pub fn open_session(
+        &mut self,
+        session_id: Uuid,
+        user_id: Uuid,
+        kind: SessionKind,
+        config: SessionConfig,
+        now: DateTime<Utc>,
+    ) {
+        self.set_clock(now);
+        self.sessions
+            .open(session_id, user_id, kind, config, self.now);
+    }
+
+    /// Keeps a session alive, or revives it during its grace period.
+    pub fn heartbeat(&mut self, session_id: Uuid, now: DateTime<Utc>) -> Result<(), EngineError> {
+        self.set_clock(now);
+        if !self.sessions.heartbeat(session_id, self.now) {
+            return Err(EngineError::SessionNotFound(session_id));
+        }
+        Ok(())
+    }
+
+    /// Marks a session as gone. Its orders are cancelled now, or by `tick`
+    /// once the session's grace period is over.
+    pub fn disconnect_session(
+        &mut self,
+        session_id: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<(), EngineError> {
+        self.set_clock(now);
+        if !self.sessions.disconnect(session_id, self.now) {
+            return Err(EngineError::SessionNotFound(session_id));
+        }
+        self.expire_sessions();
+        Ok(())
+    }
+
+    /// Drops sessions that disconnected or missed heartbeats and are past
+    /// their grace period, cancelling their resting orders if they asked for it.
+    fn expire_sessions(&mut self) {
+        for session in self.sessions.take_expired(self.now) {
+            if let Some(limiter) = self.rate_limiter.as_mut() {
+                limiter.forget_session(session.id);
+            }
+            if !session.config.cancel_on_disconnect {
+                continue;
+            }
+            let mut pairs = self.markets.keys().cloned().collect::<Vec<_>>();
+            pairs.sort_by_key(|pair| pair.to_string());
+            for pair in pairs {
+                let now = self.now;
+                let cancelled = self.markets.get_mut(&pair).map(|market| {
+                    market
+                        .orderbook
+                        .cancel_where(|order| order.session_id == Some(session.id), now)
+                });
+                for order in cancelled.unwrap_or_default() {
+                    self.release_funds(order.id);
+                    self.emit(EngineEvent::OrderCancelled {
+                        pair: pair.clone(),
+                        order_id: order.id,
+                        remaining: order.remaining_size,
+                        reason: CancelReason::SessionDisconnected(session.id),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Drives timers: expires lost sessions and idle rate-limit buckets, rolls
+    /// old trades out of the tickers and ends volatility auctions whose call
+    /// period is over.
+    ///
+    /// Every due auction is attempted; one that cannot end stays in its call
+    /// period, and its error is returned without holding up the others.
+    #[must_use]
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Vec<EngineError> {
+        self.set_clock(now);
+        let now = self.now;
+        self.expire_sessions();
+        if let Some(limiter) = self.rate_limiter.as_mut() {
+            limiter.expire_idle(now);
+        }
+        for market in self.markets.values_mut() {
+            market.ticker.expire(now);
+        }
+        let due = self
+            .markets
+            .iter()
+            .filter(|(_, market)| {
+                market.state == TradingState::Auction
+                    && market
+                        .circuit_breaker
+                        .as_ref()
+                        .is_some_and(|breaker| breaker.auction_due(now))
+            })
+            .map(|(pair, _)| pair.clone())
+            .collect::<Vec<_>>();
+
+        due.into_iter()
+            .filter_map(|pair| self.set_trading_state(pair, TradingState::Continuous).err())
+            .collect()
+    }
+
+    /// Stamps `trades` with the engine's clock, feeds them to the market's
+    /// breaker, positions, candles, ticker and fees, and publishes them with
+    /// their settlements. The emptied buffer goes back to the book.
+    fn emit_trades(&mut self, pair: &TradingPair, mut trades: Vec<Trade>) {
+        for trade in trades.iter_mut() {
+            trade.executed_at = self.now;
+        }
+        if let Some(market) = self.markets.get_mut(pair) {
+            if let Some(breaker) = market.circuit_breaker.as_mut() {
+                for trade in trades.iter() {
+                    breaker.record_trade(trade.price, trade.executed_at);
+                }
+            }
+            for trade in trades.iter() {
+                *market.positions.entry(trade.buyer_id).or_default() += trade.quantity;
+                *market.positions.entry(trade.seller_id).or_default() -= trade.quantity;
+                market.candles.record_trade(trade);
+                market.ticker.record_trade(trade);
+            }
+            if let Some(schedule) = market.fee_schedule.as_ref() {
+                let precision = market.orderbook.precision();
+                for trade in trades.iter_mut() {
+                    self.fees.apply(schedule, pair, precision, trade);
+                }
+            }
+        }
+        // Settlement events follow every trade event, in the same order.
+        // Every order holds what its fills can debit, so a trade that does
+        // not settle means the ledger is broken; stop before publishing it.
+        let mut sequence = self.sequence + trades.len() as u64;
+        let settlements = match self.accounts.as_mut() {
+            Some(accounts) => trades
+                .iter()
+                .filter_map(|trade| {
+                    let settlement = accounts
+                        .settle_trade(pair, trade, sequence + 1)
+                        .unwrap_or_else(|e| {
+                            panic!("trade {} is not covered by its holds: {}", trade.id, e)
+                        })?;
+                    sequence += 1;
+                    Some(settlement)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        for trade in trades.drain(..) {
+            self.emit(EngineEvent::Trade {
+                pair: pair.clone(),
+                trade,
+            });
+        }
+        if let Some(market) = self.markets.get_mut(pair) {
+            market.orderbook.recycle_trades(trades);
+        }
+        for settlement in settlements {
+            self.emit(EngineEvent::TradeSettled {
+                pair: pair.clone(),
+                settlement,
+            });
+        }
+    }
+
+    pub fn place_limit_order(
+        &mut self,
+        pair: TradingPair,
+        price: Price,
+        mut order: OrderRecord,
+    ) -> Result<(), EngineError> {
+        self.check_session(order.user_id, order.session_id)?;
+        self.check_rate_limit(RateLimitedAction::Place, order.user_id, order.session_id)?;
+        self.orderbook_for(&pair, MarketAction::PlaceLimit)?;
+        order.pair = Some(pair.clone());
+        Self::check_order(&pair, &order, Some(price))?;
+        Self::check_notional(&pair, order.id, price, order.remaining_size)?;
+        self.check_client_order_id(&order)?;
+        self.check_risk(&pair, &order, Some(price))?;
+        self.check_circuit_breaker(&pair, order.side, Some(price), order.remaining_size)?;
+        let (order_id, side, size) = (order.id, order.side, order.size);
+        let (user_id, client_order_id) = (order.user_id, order.client_order_id.clone());
+        let accepted = EngineEvent::OrderAccepted {
+            pair: pair.clone(),
+            order_id,
+            user_id: order.user_id,
+            session_id: order.session_id,
+            client_order_id: order.client_order_id.clone(),
+            side,
+            price,
+            size,
+            created_at: order.created_at,
+        };
+        let fee_rate = self.fee_reserve_rate(&pair, side);
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.reserve_with_fee(
+                &pair,
+                order_id,
+                order.user_id,
+                side,
+                price,
+                order.remaining_size,
+                fee_rate,
+            )?;
+        }
+        let orderbook = self.orderbook_for(&pair, MarketAction::PlaceLimit)?;
+        let outcome = match orderbook.submit_limit_order(price, order) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                self.release_funds(order_id);
+                return Err(e.into());
+            }
+        };
+        let orderbook = self.orderbook_for(&pair, MarketAction::PlaceLimit)?;
+        let indication = match orderbook.mode() {
+            BookMode::Auction => Some(orderbook.indicative_auction()?),
+            BookMode::Continuous => None,
+        };
+
+        self.record_client_order_id(order_id, user_id, client_order_id);
+        self.emit(accepted);
+        self.emit_trades(&pair, outcome.trades);
+        if outcome.resting.is_none() {
+            self.release_funds(order_id);
+        }
+        if let Some(indication) = indication {
+            self.emit(EngineEvent::AuctionIndicative { pair, indication });
+        }
+        Ok(())
+    }
+
+    pub fn place_market_order(
+        &mut self,
+        pair: TradingPair,
+        mut order: OrderRecord,
+    ) -> Result<(), EngineError> {
+        self.check_session(order.user_id, order.session_id)?;
+        self.check_rate_limit(RateLimitedAction::Place, order.user_id, order.session_id)?;
+        self.orderbook_for(&pair, MarketAction::PlaceMarket)?;
+        order.pair = Some(pair.clone());
+        Self::check_order(&pair, &order, None)?;
+        // A market order has no limit, so it is valued at the worst price it
+        // would reach against the current book.
+        let worst = self
+            .orderbook_for(&pair, MarketAction::PlaceMarket)?
+            .execution_price_range(order.side, None, order.remaining_size)?
+            .map(|(_, last)| last)
+            .unwrap_or(Price::ZERO);
+        Self::check_notional(&pair, order.id, worst, order.remaining_size)?;
+        self.check_client_order_id(&order)?;
+        self.check_risk(&pair, &order, None)?;
+        self.check_circuit_breaker(&pair, order.side, None, order.remaining_size)?;
+        let fee_rate = self.fee_reserve_rate(&pair, order.side);
+        if let Some(accounts) = self.accounts.as_mut() {
+            accounts.reserve_with_fee(
+                &pair,
+                order.id,
+                order.user_id,
+                order.side,
+                worst,
+                order.remaining_size,
+                fee_rate,
+            )?;
+        }
+        let trades = self
+            .orderbook_for(&pair, MarketAction::PlaceMarket)?
+            .fill_market_order(&mut order);
+        let trades = match trades {
+            Ok(trades) => trades,
+            Err(e) => {
+                self.release_funds(order.id);
+                return Err(e.into());
+            }
+        };
+        self.record_client_order_id(order.id, order.user_id, order.client_order_id.clone());
+        let filled = order.size - order.remaining_size;
+        let average_price = trades
+            .iter()
+            .map(Trade::notional)
+            .sum::<Notional>()
+            .checked_div(filled)
+            .unwrap_or(Price::ZERO);
+        self.emit_trades(&pair, trades);
+        self.release_funds(order.id);
+        self.emit(EngineEvent::MarketOrderFilled {
+            pair,
+            order_id: order.id,
+            user_id: order.user_id,
+            session_id: order.session_id,
+            client_order_id: order.client_order_id,
+            side: order.side,
+            average_price,
+            filled,
+            remaining: order.remaining_size,
+            created_at: order.created_at,
+        });
+        Ok(())
+    }
+
+    /// Cancels one of `user_id`'s resting orders.
+    ///
+    /// The request is charged to the requesting user and session before the
+    /// order is looked up, so cancels for unknown ids are not free. Another
+    /// user's order is reported as not found.
+    pub fn cancel_order(
+        &mut self,
+        pair: TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+    ) -> Result<OrderRecord, EngineError> {
+        self.check_session(user_id, session_id)?;
+        self.check_rate_limit(RateLimitedAction::Cancel, user_id, session_id)?;
+        let now = self.now;
+        let orderbook = self.orderbook_for(&pair, MarketAction::Cancel)?;
+        let owned = orderbook
+            .handle_of(order_id)
+            .and_then(|handle| orderbook.get(handle))
+            .is_some_and(|resting| resting.order().user_id == user_id);
+        let order = owned
+            .then(|| orderbook.cancel_order(order_id, now))
+            .flatten()
+            .ok_or_else(|| EngineError::OrderNotFound {
+                pair: pair.clone(),
+                order_id,
+            })?;
+        self.release_funds(order_id);
+        self.emit(EngineEvent::OrderCancelled {
+            pair,
+            order_id,
+            remaining: order.remaining_size,
+            reason: CancelReason::Requested,
+        });
+        Ok(order)
+    }
+
+    pub fn cancel_by_client_order_id(
+        &mut self,
+        pair: TradingPair,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        client_order_id: String,
+    ) -> Result<OrderRecord, EngineError> {
+        let order_id = self.resolve_order(
+            &pair,
+            &OrderRef::Client {
+                user_id,
+                client_order_id,
+            },
+        );
+        let order_id = match order_id {
+            Ok(order_id) => order_id,
+            Err(e) => {
+                // A miss costs the same as a cancel.
+                self.check_session(user_id, session_id)?;
+                self.check_rate_limit(RateLimitedAction::Cancel, user_id, session_id)?;
+                return Err(e);
+            }
+        };
+        self.cancel_order(pair, order_id, user_id, session_id)
+    }
+
+    /// Changes the price and/or open quantity of one of `user_id`'s resting
+    /// orders.
+    ///
+    /// `remaining` is the quantity left open after the amend, not the order's
+    /// original size. Only a quantity reduction at the same price keeps time
+    /// priority; anything else is a cancel-replace under the same order id
+    /// and goes through the risk, circuit breaker and funds checks again.
+    ///
+    /// Like a cancel, the request is charged to the requesting user and
+    /// session before the order is looked up, and another user's order is
+    /// reported as not found.
+    pub fn amend_order(
+        &mut self,
+        pair: TradingPair,
+        order: OrderRef,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        price: Option<Price>,
+        remaining: Option<Quantity>,
+    ) -> Result<(), EngineError> {
+        self.check_session(user_id, session_id)?;
+        self.check_rate_limit(RateLimitedAction::Amend, user_id, session_id)?;
+        let order_id = self.resolve_order(&pair, &order)?;
+        let orderbook = self.orderbook_for(&pair, MarketAction::Amend)?;
+        let (handle, current) = orderbook
+            .handle_of(order_id)
+            .and_then(|handle| Some((handle, orderbook.open_order(handle)?.clone())))
+            .filter(|(_, current)| current.user_id == user_id)
+            .ok_or_else(|| EngineError::OrderNotFound {
+                pair: pair.clone(),
+                order_id,
+            })?;
+
+        let price = price.unwrap_or(current.price);
+        let remaining = remaining.unwrap_or(current.remaining_size);
+        if remaining <= Quantity::ZERO {
+            return Err(EngineError::InvalidAmend {
+                pair,
+                order_id,
+                reason: "remaining quantity must be greater than zero".to_string(),
+            });
+        }
+        let keeps_priority = price == current.price && remaining <= current.remaining_size;
+        if !keeps_priority {
+            Self::check_notional(&pair, order_id, price, remaining)?;
+            let mut replacement = current.clone();
+            replacement.remaining_size = remaining;
+            self.check_risk(&pair, &replacement, Some(price))?;
+            self.check_circuit_breaker(&pair, current.side, Some(price), remaining)?;
+        }
+        let fee_rate = self.fee_reserve_rate(&pair, current.side);
+        self.rereserve(
+            &pair,
+            &current,
+            (current.price, current.remaining_size),
+            (price, remaining),
+            fee_rate,
+        )?;
+
+        let now = self.now;
+        let outcome = match self
+            .orderbook_for(&pair, MarketAction::Amend)
+            .and_then(|orderbook| Ok(orderbook.amend(handle, price, remaining, now)?))
+        {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                let _ = self.rereserve(
+                    &pair,
+                    &current,
+                    (price, remaining),
+                    (current.price, current.remaining_size),
+                    fee_rate,
+                );
+                return Err(e);
+            }
+        };
+        self.emit(EngineEvent::OrderAmended {
+            pair: pair.clone(),
+            order_id,
+            price,
+            remaining,
+            lost_priority: !keeps_priority,
+        });
+        self.emit_trades(&pair, outcome.trades);
+        if outcome.resting.is_none() {
+            self.release_funds(order_id);
+        }
+        Ok(())
+    }
+
+    /// Moves `order`'s hold from what it reserved at `from` to what it needs
+    /// at `to`, each a price and open quantity. If the new hold does not fit,
+    /// the old one is put back, which fits since it was just released.
+    fn rereserve(
+        &mut self,
+        pair: &TradingPair,
+        order: &OrderRecord,
+        from: (Price, Quantity),
+        to: (Price, Quantity),
+        fee_rate: Decimal,
+    ) -> Result<(), EngineError> {
+        let Some(accounts) = self.accounts.as_mut() else {
+            return Ok(());
+        };
+        accounts.release(order.id);
+        let (price, quantity) = to;
+        let reserved = accounts.reserve_with_fee(
+            pair,
+            order.id,
+            order.user_id,
+            order.side,
+            price,
+            quantity,
+            fee_rate,
+        );
+        if let Err(e) = reserved {
+            let (price, quantity) = from;
+            let _ = accounts.reserve_with_fee(
+                pair,
+                order.id,
+                order.user_id,
+                order.side,
+                price,
+                quantity,
+                fee_rate,
+            );
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Cancels every resting order matching `filter` in one pass over each
+    /// book, emitting an `OrderCancelled` per order.
+    ///
+    /// Markets that do not currently accept cancels are skipped, unless the
+    /// filter names one, in which case that is an error.
+    pub fn mass_cancel(
+        &mut self,
+        filter: MassCancelFilter,
+    ) -> Result<Vec<(TradingPair, OrderRecord)>, EngineError> {
+        let pairs = match filter.pair.clone() {
+            Some(pair) => {
+                self.orderbook_for(&pair, MarketAction::Cancel)?;
+                vec![pair]
+            }
+            None => {
+                let mut pairs = self
+                    .markets
+                    .iter()
+                    .filter(|(_, market)| market.state.allows(MarketAction::Cancel))
+                    .map(|(pair, _)| pair.clone())
+                    .collect::<Vec<_>>();
+                pairs.sort_by_key(|pair| pair.to_string());
+                pairs
+            }
+        };
+
+        let (mut cancelled, now) = (Vec::new(), self.now);
+        for pair in pairs {
+            let orders = self.market_mut(&pair)?.orderbook.cancel_in_range(
+                filter.side,
+                filter.price_range,
+                |order| {
+                    filter
+                        .user_id
+                        .is_none_or(|user_id| order.user_id == user_id)
+                },
+                now,
+            );
+            for order in orders {
+                self.release_funds(order.id);
+                self.emit(EngineEvent::OrderCancelled {
+                    pair: pair.clone(),
+                    order_id: order.id,
+                    remaining: order.remaining_size,
+                    reason: CancelReason::MassCancel,
+                });
+                cancelled.push((pair.clone(), order));
+            }
         }
+        Ok(cancelled)
+    }
+
+    /// Admin operation: moves a market to `state`.
+    ///
+    /// Entering `Auction` starts a call period. Leaving a call period for any
+    /// other state uncrosses the book first, so moving from `Auction` to
+    /// `Closed` runs the closing match and no state is left with a crossed
+    /// book.
+    pub fn set_trading_state(
+        &mut self,
+        pair: TradingPair,
+        state: TradingState,
+    ) -> Result<(), EngineError> {
+        let now = self.now;
+        let market = self.market_mut(&pair)?;
+        let from = market.state;
+        // Delisting has to cancel and archive the book, which only
+        // `delist_market` does.
+        if !from.can_transition_to(state) || state == TradingState::Delisted {
+            return Err(EngineError::InvalidStateTransition {
+                pair,
+                from,
+                to: state,
+            });
+        }
+
+        let mut trades = None;
+        let mut indication = None;
+        match state {
+            TradingState::Auction => {
+                if market.orderbook.mode() != BookMode::Auction {
+                    market.orderbook.start_auction();
+                }
+                indication = Some(market.orderbook.indicative_auction()?);
+            }
+            _ if market.orderbook.mode() == BookMode::Auction => {
+                trades = Some(market.orderbook.uncross()?);
+            }
+            _ => {}
+        }
+        if from == TradingState::Auction {
+            if let Some(breaker) = market.circuit_breaker.as_mut() {
+                let uncross_price = trades
+                    .as_ref()
+                    .and_then(|trades| trades.first())
+                    .map(|trade| trade.price);
+                breaker.end_auction(uncross_price, now);
+            }
+        }
+        market.state = state;
+
+        self.emit(EngineEvent::TradingStateChanged {
+            pair: pair.clone(),
+            from,
+            to: state,
+        });
+        if let Some(indication) = indication {
+            self.emit(EngineEvent::AuctionIndicative {
+                pair: pair.clone(),
+                indication,
+            });
+        }
+        if let Some(trades) = trades {
+            let price = trades.first().map(|trade| trade.price);
+            let volume = trades.iter().map(|trade| trade.quantity).sum();
+            self.emit_trades(&pair, trades);
+            self.emit(EngineEvent::AuctionUncrossed {
+                pair,
+                price,
+                volume,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::orderbook::OrderBookError;
+    use crate::matching_engine::types::order::OrderStatus;
+    use chrono::Duration;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[tokio::test]
+    async fn trading_pairs_parse_from_their_display_form() {
+        assert_eq!(btc_usd().to_string().parse::<TradingPair>(), Ok(btc_usd()));
+        for invalid in ["BTC", "BTC/", "/USD", "BTC/USD/EUR"] {
+            assert_eq!(
+                invalid.parse::<TradingPair>(),
+                Err(EngineError::InvalidTradingPair(invalid.to_string()))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn restored_orders_keep_time_priority_and_the_sequence_resumes() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .restore_market(btc_usd(), Precision::default(), TradingState::Continuous)
+            .unwrap();
+        let resting = |size| {
+            let mut order = OrderRecord::new(OrderSide::Ask, Quantity(size)).with_pair(btc_usd());
+            order.price = Price(dec!(100));
+            order
+        };
+        let (first, second) = (resting(dec!(1)), resting(dec!(2)));
+        let first_id = first.id;
+        let mut legacy = resting(dec!(3));
+        legacy.pair = None;
+        let unlisted = resting(dec!(4)).with_pair("ETH/USD".parse().unwrap());
+
+        let restored = engine
+            .restore(vec![first, second, legacy, unlisted], 40)
+            .unwrap();
+        assert_eq!(
+            restored,
+            RestoredOrders {
+                resting: 2,
+                cancelled: 0,
+                skipped: 2
+            }
+        );
+        assert!(engine.market(&"ETH/USD".parse().unwrap()).is_none());
+        assert_eq!(engine.sequence(), 40);
+        assert_eq!(engine.drain_events().count(), 0);
+
+        engine
+            .place_market_order(
+                btc_usd(),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+            )
+            .unwrap();
+        let events = engine.drain_events().collect::<Vec<_>>();
+        assert_eq!(events[0].sequence, 41);
+        match &events[0].event {
+            EngineEvent::Trade { trade, .. } => assert_eq!(trade.sell_order_id, first_id),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn markets_are_restored_as_they_were_left() {
+        let mut engine = MatchingEngine::new();
+        let precision = Precision::new(2, 4).unwrap();
+        engine
+            .restore_market(btc_usd(), precision, TradingState::Auction)
+            .unwrap();
+        assert_eq!(
+            engine.restore_market(btc_usd(), precision, TradingState::Auction),
+            Err(EngineError::MarketAlreadyExists(btc_usd()))
+        );
+        let market = engine.market(&btc_usd()).unwrap();
+        assert_eq!(market.state(), TradingState::Auction);
+        assert_eq!(market.orderbook().mode(), BookMode::Auction);
+        assert_eq!(market.orderbook().precision(), precision);
+
+        // Crossing orders rest untouched until the auction uncrosses.
+        let mut bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_pair(btc_usd());
+        bid.price = Price(dec!(101.25));
+        let mut ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_pair(btc_usd());
+        ask.price = Price(dec!(100));
+        engine.restore(vec![bid, ask], 0).unwrap();
+        assert!(engine.drain_events().next().is_none());
+        assert_eq!(engine.market(&btc_usd()).unwrap().orderbook().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn orders_of_ended_sessions_are_cancelled_on_restore() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .restore_market(btc_usd(), Precision::default(), TradingState::Continuous)
+            .unwrap();
+        let session_id = Uuid::new_v4();
+        let mut order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(2))).with_pair(btc_usd());
+        order.price = Price(dec!(100));
+        order.session_id = Some(session_id);
+        let order_id = order.id;
+
+        let restored = engine.restore(vec![order], 7).unwrap();
+        assert_eq!(restored.cancelled, 1);
+        assert!(engine.market(&btc_usd()).unwrap().orderbook().is_empty());
+        let cancelled = engine.drain_events().next().unwrap();
+        assert_eq!(cancelled.sequence, 8);
+        assert_eq!(
+            cancelled.event,
+            EngineEvent::OrderCancelled {
+                pair: btc_usd(),
+                order_id,
+                remaining: Quantity(dec!(2)),
+                reason: CancelReason::SessionDisconnected(session_id),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn accepted_orders_carry_their_market() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let order = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1)));
+        let order_id = order.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), order.clone())
+            .unwrap();
+        assert_eq!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), order),
+            Err(EngineError::OrderBook(OrderBookError::DuplicateOrderId(
+                order_id
+            )))
+        );
+
+        // Only the owner can cancel.
+        assert!(matches!(
+            engine.cancel_order(btc_usd(), order_id, Uuid::new_v4(), None),
+            Err(EngineError::OrderNotFound { .. })
+        ));
+        let cancelled = engine
+            .cancel_order(btc_usd(), order_id, Uuid::nil(), None)
+            .unwrap();
+        assert_eq!(cancelled.pair, Some(btc_usd()));
+    }
+
+    #[tokio::test]
+    async fn opening_auction_publishes_indicative_price_then_uncrosses() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .add_market_in_state(btc_usd(), Precision::default(), TradingState::PreOpen)
+            .unwrap();
+        engine
+            .set_trading_state(btc_usd(), TradingState::Auction)
+            .unwrap();
+
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(101)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(4))),
+            )
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(99)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(3))),
+            )
+            .unwrap();
+
+        let indications = engine
+            .drain_events()
+            .filter_map(|event| match event.event {
+                EngineEvent::AuctionIndicative { indication, .. } => Some(indication),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(indications.len(), 3);
+        assert_eq!(indications[0], None);
+        assert_eq!(indications[1], None);
+        let last = indications[2].unwrap();
+        assert_eq!(last.volume, Quantity(dec!(3)));
+
+        engine
+            .set_trading_state(btc_usd(), TradingState::Continuous)
+            .unwrap();
+        let trades = engine
+            .drain_events()
+            .filter_map(|event| match event.event {
+                EngineEvent::Trade { trade, .. } => Some(trade),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, last.price);
+        assert_eq!(
+            engine.market(&btc_usd()).unwrap().orderbook().mode(),
+            BookMode::Continuous
+        );
+    }
+
+    #[tokio::test]
+    async fn closing_an_auction_runs_the_closing_match() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        for next in [TradingState::Closed, TradingState::Halted] {
+            engine
+                .set_trading_state(btc_usd(), TradingState::Auction)
+                .unwrap();
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(dec!(101)),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                )
+                .unwrap();
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(dec!(99)),
+                    OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+                )
+                .unwrap();
+            engine.drain_events().count();
+
+            engine.set_trading_state(btc_usd(), next).unwrap();
+            assert!(engine.drain_events().any(|event| matches!(
+                event.event,
+                EngineEvent::AuctionUncrossed { volume, .. } if volume == Quantity(dec!(1))
+            )));
+            let book = engine.market(&btc_usd()).unwrap().orderbook();
+            assert_eq!(book.mode(), BookMode::Continuous);
+            assert!(book.is_empty());
+            if next == TradingState::Closed {
+                engine
+                    .set_trading_state(btc_usd(), TradingState::PreOpen)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn halted_market_only_accepts_cancels() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let resting = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)));
+        let resting_id = resting.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), resting)
+            .unwrap();
+
+        engine
+            .set_trading_state(btc_usd(), TradingState::Halted)
+            .unwrap();
+
+        let rejected = engine.place_market_order(
+            btc_usd(),
+            OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+        );
+        assert_eq!(
+            rejected,
+            Err(EngineError::ActionNotAllowed {
+                pair: btc_usd(),
+                state: TradingState::Halted,
+                action: MarketAction::PlaceMarket,
+            })
+        );
+        assert!(engine
+            .cancel_order(btc_usd(), resting_id, Uuid::nil(), None)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_state_transitions() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        // A halted market reopens through an auction, never straight away.
+        engine
+            .set_trading_state(btc_usd(), TradingState::Halted)
+            .unwrap();
+        assert_eq!(
+            engine.set_trading_state(btc_usd(), TradingState::Continuous),
+            Err(EngineError::InvalidStateTransition {
+                pair: btc_usd(),
+                from: TradingState::Halted,
+                to: TradingState::Continuous,
+            })
+        );
+        // Delisting must go through `delist_market` so the book is cancelled.
+        assert!(matches!(
+            engine.set_trading_state(btc_usd(), TradingState::Delisted),
+            Err(EngineError::InvalidStateTransition { .. })
+        ));
+        engine
+            .delist_market(btc_usd(), "retired".to_string())
+            .unwrap();
+        assert!(matches!(
+            engine.set_trading_state(btc_usd(), TradingState::Continuous),
+            Err(EngineError::InvalidStateTransition { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn circuit_breaker_rejects_and_runs_a_volatility_auction() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine
+            .set_circuit_breaker(
+                btc_usd(),
+                CircuitBreakerConfig {
+                    band_bps: 1_000,
+                    reference_window: Duration::minutes(5),
+                    auction_duration: Duration::seconds(30),
+                },
+            )
+            .unwrap();
+
+        // Establish a reference price of 100.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+        engine
+            .place_market_order(
+                btc_usd(),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+            )
+            .unwrap();
+
+        // Thin book: the only ask is 50% away.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(150)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+        engine.drain_events().for_each(drop);
+
+        let rejected = engine.place_market_order(
+            btc_usd(),
+            OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+        );
+        assert!(matches!(
+            rejected,
+            Err(EngineError::CircuitBreakerTripped { price, .. }) if price == Price(dec!(150))
+        ));
+        let market = engine.market(&btc_usd()).unwrap();
+        assert_eq!(market.state(), TradingState::Auction);
+        // The triggering order did not execute.
+        assert_eq!(market.orderbook().len(), 1);
+        let ends_at = market.circuit_breaker().unwrap().auction_ends_at().unwrap();
+        assert!(engine
+            .drain_events()
+            .any(|event| matches!(event.event, EngineEvent::CircuitBreakerTripped { .. })));
+
+        assert!(engine.tick(ends_at - Duration::seconds(1)).is_empty());
+        assert_eq!(
+            engine.market(&btc_usd()).unwrap().state(),
+            TradingState::Auction
+        );
+        assert!(engine.tick(ends_at).is_empty());
+        let market = engine.market(&btc_usd()).unwrap();
+        assert_eq!(market.state(), TradingState::Continuous);
+        assert_eq!(market.circuit_breaker().unwrap().auction_ends_at(), None);
+    }
+
+    #[tokio::test]
+    async fn adding_a_market_twice_is_an_error() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let resting = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)));
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), resting)
+            .unwrap();
+
+        assert_eq!(
+            engine.add_market(btc_usd()),
+            Err(EngineError::MarketAlreadyExists(btc_usd()))
+        );
+        assert_eq!(engine.market(&btc_usd()).unwrap().orderbook().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delisting_cancels_everything_and_archives_the_book() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        for (side, price) in [
+            (OrderSide::Bid, dec!(99)),
+            (OrderSide::Bid, dec!(98)),
+            (OrderSide::Ask, dec!(101)),
+        ] {
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(price),
+                    OrderRecord::new(side, Quantity(dec!(2))),
+                )
+                .unwrap();
+        }
+        engine.drain_events().for_each(drop);
+
+        let archive = engine
+            .delist_market(btc_usd(), "token migration".to_string())
+            .unwrap();
+        assert_eq!(archive.cancelled_orders.len(), 3);
+        assert_eq!(archive.final_book.bids.len(), 2);
+        assert_eq!(archive.final_book.asks[0].price, Price(dec!(101)));
+        assert!(archive
+            .cancelled_orders
+            .iter()
+            .all(|order| order.status == OrderStatus::Cancelled));
+
+        let cancellations = engine
+            .drain_events()
+            .filter(|event| {
+                matches!(
+                    &event.event,
+                    EngineEvent::OrderCancelled {
+                        reason: CancelReason::MarketDelisted(reason),
+                        ..
+                    } if reason == "token migration"
+                )
+            })
+            .count();
+        assert_eq!(cancellations, 3);
+        assert!(engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+            )
+            .is_err());
+
+        let archive = engine
+            .remove_market(btc_usd(), "token migration".to_string())
+            .unwrap();
+        assert_eq!(archive.cancelled_orders.len(), 3);
+        assert!(engine.market(&btc_usd()).is_none());
+        assert!(engine.add_market(btc_usd()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn trades_carry_maker_and_taker_fees() {
+        use crate::matching_engine::fees::{FeeCurrency, FeeTier, Liquidity};
+        use crate::matching_engine::types::decimal::Notional;
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let tier = FeeTier {
+            min_volume: Notional::ZERO,
+            maker_rate: dec!(0.001),
+            taker_rate: dec!(0.002),
+        };
+        engine
+            .set_fee_schedule(btc_usd(), FeeSchedule::new(vec![tier], FeeCurrency::Quote))
+            .unwrap();
+        let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(maker),
+            )
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(taker),
+            )
+            .unwrap();
+
+        let trade = engine
+            .drain_events()
+            .find_map(|event| match event.event {
+                EngineEvent::Trade { trade, .. } => Some(trade),
+                _ => None,
+            })
+            .unwrap();
+        let buyer_fee = trade.buyer_fee.unwrap();
+        assert_eq!(buyer_fee.user_id, taker);
+        assert_eq!(buyer_fee.liquidity, Liquidity::Taker);
+        assert_eq!(buyer_fee.amount, dec!(0.2));
+        let seller_fee = trade.seller_fee.unwrap();
+        assert_eq!(seller_fee.user_id, maker);
+        assert_eq!(seller_fee.amount, dec!(0.1));
+        assert_eq!(
+            engine.fee_engine().rolling_volume(maker, engine.now()),
+            Notional(dec!(100))
+        );
+    }
+
+    #[tokio::test]
+    async fn maker_rebates_are_paid_out_of_the_takers_fee() {
+        use crate::matching_engine::fees::FeeTier;
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let tier = FeeTier {
+            min_volume: Notional::ZERO,
+            maker_rate: dec!(-0.001),
+            taker_rate: dec!(0.002),
+        };
+        engine
+            .set_fee_schedule(btc_usd(), FeeSchedule::new(vec![tier], FeeCurrency::Quote))
+            .unwrap();
+        let fee_account = Uuid::new_v4();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut gateway = PaymentGateway::with_fee_account(fee_account);
+        gateway.deposit(buyer, "USD", dec!(1_000)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(2)).unwrap();
+        engine.set_payment_gateway(gateway);
+
+        // The resting buyer is the maker and the fee account starts empty.
+        let place = |engine: &mut MatchingEngine, side, user| {
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(dec!(100)),
+                    OrderRecord::new(side, Quantity(dec!(1))).with_user(user),
+                )
+                .unwrap();
+        };
+        place(&mut engine, OrderSide::Bid, buyer);
+        place(&mut engine, OrderSide::Ask, seller);
+        let settled = |engine: &mut MatchingEngine| {
+            engine
+                .drain_events()
+                .filter(|event| matches!(event.event, EngineEvent::TradeSettled { .. }))
+                .count()
+        };
+        assert_eq!(settled(&mut engine), 1);
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.balance(buyer, "USD").available, dec!(900.1));
+        assert_eq!(accounts.balance(seller, "USD").available, dec!(99.8));
+        assert_eq!(accounts.balance(fee_account, "USD").available, dec!(0.1));
+
+        // Nobody takes liquidity in an auction, so nobody earns a rebate.
+        engine
+            .set_trading_state(btc_usd(), TradingState::Auction)
+            .unwrap();
+        place(&mut engine, OrderSide::Bid, buyer);
+        place(&mut engine, OrderSide::Ask, seller);
+        engine
+            .set_trading_state(btc_usd(), TradingState::Continuous)
+            .unwrap();
+        assert_eq!(settled(&mut engine), 1);
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.balance(buyer, "USD").available, dec!(800.1));
+        assert_eq!(accounts.balance(fee_account, "USD").available, dec!(0.1));
+    }
+
+    #[tokio::test]
+    async fn orders_reserve_the_fees_they_may_pay() {
+        use crate::matching_engine::fees::FeeTier;
+        use crate::services::payment_gateway::BalanceError;
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let tier = FeeTier {
+            min_volume: Notional::ZERO,
+            maker_rate: dec!(-0.001),
+            taker_rate: dec!(0.002),
+        };
+        engine
+            .set_fee_schedule(btc_usd(), FeeSchedule::new(vec![tier], FeeCurrency::Quote))
+            .unwrap();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut gateway = PaymentGateway::new();
+        gateway.deposit(buyer, "USD", dec!(100)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(1)).unwrap();
+        engine.set_payment_gateway(gateway);
+
+        let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(buyer);
+        assert!(matches!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), bid),
+            Err(EngineError::Balance(BalanceError::InsufficientFunds { .. }))
+        ));
+
+        // Asks pay their fee out of the quote they receive.
+        let ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller);
+        let ask_id = ask.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), ask)
+            .unwrap();
+        assert_eq!(
+            engine.payment_gateway().unwrap().held_for(ask_id),
+            Some(dec!(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn new_fee_schedules_reserve_again_for_resting_orders() {
+        use crate::matching_engine::fees::FeeTier;
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let (short, funded) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut gateway = PaymentGateway::new();
+        gateway.deposit(short, "USD", dec!(100)).unwrap();
+        gateway.deposit(funded, "USD", dec!(200)).unwrap();
+        engine.set_payment_gateway(gateway);
+        let bid = |user| OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user);
+        let (short_bid, funded_bid) = (bid(short), bid(funded));
+        let (short_id, funded_id) = (short_bid.id, funded_bid.id);
+        for order in [short_bid, funded_bid] {
+            engine
+                .place_limit_order(btc_usd(), Price(dec!(100)), order)
+                .unwrap();
+        }
+        engine.drain_events().for_each(drop);
+
+        let tier = FeeTier {
+            min_volume: Notional::ZERO,
+            maker_rate: dec!(0.001),
+            taker_rate: dec!(0.002),
+        };
+        engine
+            .set_fee_schedule(btc_usd(), FeeSchedule::new(vec![tier], FeeCurrency::Quote))
+            .unwrap();
+
+        // Only one of the bids can also cover the fee it may now pay.
+        let events = engine.drain_events().map(|e| e.event).collect::<Vec<_>>();
+        assert!(matches!(
+            events.as_slice(),
+            [EngineEvent::OrderCancelled {
+                order_id,
+                reason: CancelReason::InsufficientFunds,
+                ..
+            }] if *order_id == short_id
+        ));
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.held_for(short_id), None);
+        assert_eq!(accounts.balance(short, "USD").available, dec!(100));
+        assert_eq!(accounts.held_for(funded_id), Some(dec!(100.2)));
+    }
+
+    #[tokio::test]
+    async fn orders_must_be_funded_and_fills_move_balances() {
+        use crate::services::payment_gateway::BalanceError;
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut gateway = PaymentGateway::new();
+        gateway.deposit(buyer, "USD", dec!(250)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(1)).unwrap();
+        engine.set_payment_gateway(gateway);
+
+        let oversell = OrderRecord::new(OrderSide::Ask, Quantity(dec!(2))).with_user(seller);
+        assert!(matches!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), oversell),
+            Err(EngineError::Balance(BalanceError::InsufficientFunds { .. }))
+        ));
+        assert!(engine.market(&btc_usd()).unwrap().orderbook().is_empty());
+
+        let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(2))).with_user(buyer);
+        let bid_id = bid.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(120)), bid)
+            .unwrap();
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.balance(buyer, "USD").held, dec!(240));
+
+        let ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller);
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(110)), ask)
+            .unwrap();
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.balance(seller, "USD").available, dec!(120));
+        assert_eq!(accounts.balance(seller, "BTC").total(), Decimal::ZERO);
+        assert_eq!(accounts.balance(buyer, "BTC").available, dec!(1));
+        assert_eq!(accounts.balance(buyer, "USD").held, dec!(120));
+
+        engine.cancel_order(btc_usd(), bid_id, buyer, None).unwrap();
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(
+            accounts.balance(buyer, "USD"),
+            crate::services::payment_gateway::Balance {
+                available: dec!(130),
+                held: Decimal::ZERO
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_too_large_to_value_are_rejected() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .add_market_with_precision(btc_usd(), Precision::new(0, 0).unwrap())
+            .unwrap();
+        engine.set_payment_gateway(PaymentGateway::new());
+
+        let huge = Quantity(dec!(1_000_000_000_000_000));
+        let bid = OrderRecord::new(OrderSide::Bid, huge);
+        let bid_id = bid.id;
+        assert_eq!(
+            engine.place_limit_order(btc_usd(), Price(dec!(1_000_000_000_000_000)), bid),
+            Err(EngineError::NotionalOutOfRange {
+                pair: btc_usd(),
+                order_id: bid_id,
+                price: Price(dec!(1_000_000_000_000_000)),
+                quantity: huge,
+            })
+        );
+
+        // A market order is valued at the worst price it would reach.
+        let seller = Uuid::new_v4();
+        engine
+            .payment_gateway_mut()
+            .unwrap()
+            .deposit(seller, "BTC", dec!(1))
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(1_000_000_000_000)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.place_market_order(
+                btc_usd(),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1_000_000_000_000_000_000)))
+            ),
+            Err(EngineError::NotionalOutOfRange { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn orders_without_a_positive_size_or_price_are_rejected() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+
+        let negative = OrderRecord::new(OrderSide::Bid, Quantity(dec!(-1)));
+        assert!(matches!(
+            engine.place_market_order(btc_usd(), negative.clone()),
+            Err(EngineError::InvalidOrder { order_id, .. }) if order_id == negative.id
+        ));
+        assert!(matches!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), negative),
+            Err(EngineError::InvalidOrder { .. })
+        ));
+        let mut overfilled = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)));
+        overfilled.remaining_size = Quantity(dec!(2));
+        assert!(matches!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), overfilled),
+            Err(EngineError::InvalidOrder { .. })
+        ));
+        for price in [Price::ZERO, Price(dec!(-5))] {
+            assert!(matches!(
+                engine.place_limit_order(
+                    btc_usd(),
+                    price,
+                    OrderRecord::new(OrderSide::Ask, Quantity(dec!(1)))
+                ),
+                Err(EngineError::InvalidOrder { .. })
+            ));
+        }
+
+        let book = engine.market(&btc_usd()).unwrap().orderbook();
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.best_ask().unwrap().total_volume().lots(), 10i64.pow(8));
+    }
+
+    #[tokio::test]
+    async fn risk_checks_reject_before_the_book() {
+        use crate::matching_engine::risk::{RiskCheck, RiskReject};
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine
+            .set_risk_checks(
+                btc_usd(),
+                RiskChain::new(vec![
+                    RiskCheck::OpenOrdersLimit(1),
+                    RiskCheck::PriceBand { bps: 500 },
+                ]),
+            )
+            .unwrap();
+        let user = Uuid::new_v4();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user),
+            )
+            .unwrap();
+
+        let second = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user);
+        let second_id = second.id;
+        assert_eq!(
+            engine.place_limit_order(btc_usd(), Price(dec!(99)), second),
+            Err(EngineError::RiskRejected {
+                pair: btc_usd(),
+                order_id: second_id,
+                reason: RiskReject::TooManyOpenOrders {
+                    user_id: user,
+                    max: 1
+                },
+            })
+        );
+
+        // The first trade sets the reference for the fat-finger check.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+        let market = engine.market(&btc_usd()).unwrap();
+        assert_eq!(market.position(user), Quantity(dec!(1)));
+        assert_eq!(market.orderbook().open_orders_for(user), 0);
+        assert!(matches!(
+            engine.place_limit_order(
+                btc_usd(),
+                Price(dec!(90)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1)))
+            ),
+            Err(EngineError::RiskRejected {
+                reason: RiskReject::PriceTooFarFromReference { .. },
+                ..
+            })
+        ));
+        assert!(engine.market(&btc_usd()).unwrap().orderbook().is_empty());
+    }
+
+    #[tokio::test]
+    async fn flooding_client_is_rate_limited() {
+        use crate::matching_engine::rate_limit::{
+            ActionLimits, RateLimit, RateLimitKey, TierLimits, UserTier,
+        };
+
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let limit = RateLimit {
+            burst: 2,
+            per_second: 1,
+        };
+        let limits = ActionLimits {
+            place: limit,
+            cancel: limit,
+            amend: limit,
+        };
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits,
+                per_session: limits,
+            },
+        );
+        engine.set_rate_limiter(limiter);
+
+        let (bot, session) = (Uuid::new_v4(), Uuid::new_v4());
+        engine.open_session(
+            session,
+            bot,
+            SessionKind::Tcp,
+            SessionConfig::default(),
+            Utc::now(),
+        );
+        let order = || {
+            OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                .with_user(bot)
+                .with_session(session)
+        };
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(99)), order())
+            .unwrap();
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(98)), order())
+            .unwrap();
+        match engine.place_limit_order(btc_usd(), Price(dec!(97)), order()) {
+            Err(EngineError::RateLimited(limited)) => {
+                assert_eq!(limited.key, RateLimitKey::User(bot));
+                assert!(limited.retry_after > Duration::zero());
+            }
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+        assert_eq!(engine.market(&btc_usd()).unwrap().orderbook().len(), 2);
+
+        // Cancels for ids that are not resting still cost a token.
+        for _ in 0..2 {
+            assert!(matches!(
+                engine.cancel_order(btc_usd(), Uuid::new_v4(), bot, Some(session)),
+                Err(EngineError::OrderNotFound { .. })
+            ));
+        }
+        assert!(matches!(
+            engine.cancel_order(btc_usd(), Uuid::new_v4(), bot, Some(session)),
+            Err(EngineError::RateLimited(_))
+        ));
+
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(97)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(Uuid::new_v4()),
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lost_sessions_cancel_their_orders_after_the_grace_period() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let maker = Uuid::new_v4();
+        let now = Utc::now();
+        let config = SessionConfig {
+            grace_period: Duration::seconds(5),
+            ..SessionConfig::default()
+        };
+        let (session, other) = (Uuid::new_v4(), Uuid::new_v4());
+        engine.open_session(session, maker, SessionKind::Tcp, config, now);
+        engine.open_session(other, maker, SessionKind::Tcp, config, now);
+        for (session, price) in [(session, dec!(99)), (other, dec!(98))] {
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(price),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                        .with_user(maker)
+                        .with_session(session),
+                )
+                .unwrap();
+        }
+        engine.drain_events().for_each(drop);
+
+        // Orders may only use a session of their own user.
+        for (user, session) in [(Uuid::new_v4(), session), (maker, Uuid::new_v4())] {
+            assert_eq!(
+                engine.place_limit_order(
+                    btc_usd(),
+                    Price(dec!(97)),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                        .with_user(user)
+                        .with_session(session),
+                ),
+                Err(EngineError::SessionNotFound(session))
+            );
+        }
+
+        engine.disconnect_session(session, now).unwrap();
+        engine.heartbeat(other, now + Duration::seconds(4)).unwrap();
+        assert!(engine.tick(now + Duration::seconds(4)).is_empty());
+        assert_eq!(engine.market(&btc_usd()).unwrap().orderbook().len(), 2);
+
+        assert!(engine.tick(now + Duration::seconds(5)).is_empty());
+        let book = engine.market(&btc_usd()).unwrap().orderbook();
+        assert_eq!(book.len(), 1);
+        assert_eq!(book.best_bid().unwrap().price().ticks(), 98 * 10i64.pow(8));
+        assert!(engine.drain_events().any(|event| matches!(
+            event.event,
+            EngineEvent::OrderCancelled {
+                reason: CancelReason::SessionDisconnected(id),
+                ..
+            } if id == session
+        )));
+        assert_eq!(
+            engine.heartbeat(session, now + Duration::seconds(6)),
+            Err(EngineError::SessionNotFound(session))
+        );
+        assert_eq!(
+            engine.place_limit_order(
+                btc_usd(),
+                Price(dec!(97)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                    .with_user(maker)
+                    .with_session(session),
+            ),
+            Err(EngineError::SessionNotFound(session))
+        );
+
+        // Missing heartbeats counts as a disconnect too.
+        assert!(engine.tick(now + Duration::seconds(40)).is_empty());
+        assert!(engine.market(&btc_usd()).unwrap().orderbook().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mass_cancel_filters_by_owner_market_side_and_price() {
+        let eth_usd = TradingPair::new("ETH".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine.add_market(eth_usd.clone()).unwrap();
+        let (trader, other) = (Uuid::new_v4(), Uuid::new_v4());
+        for (pair, user, side, price) in [
+            (btc_usd(), trader, OrderSide::Bid, dec!(95)),
+            (btc_usd(), trader, OrderSide::Bid, dec!(90)),
+            (btc_usd(), trader, OrderSide::Bid, dec!(80)),
+            (btc_usd(), trader, OrderSide::Ask, dec!(105)),
+            (btc_usd(), other, OrderSide::Bid, dec!(92)),
+            (eth_usd.clone(), trader, OrderSide::Bid, dec!(91)),
+        ] {
+            engine
+                .place_limit_order(
+                    pair,
+                    Price(price),
+                    OrderRecord::new(side, Quantity(dec!(1))).with_user(user),
+                )
+                .unwrap();
+        }
+        engine.drain_events().for_each(drop);
+
+        let cancelled = engine
+            .mass_cancel(MassCancelFilter {
+                user_id: Some(trader),
+                pair: Some(btc_usd()),
+                side: Some(OrderSide::Bid),
+                price_range: Some((Price(dec!(85)), Price(dec!(95)))),
+            })
+            .unwrap();
+        let prices = cancelled
+            .iter()
+            .map(|(_, order)| order.price)
+            .collect::<Vec<_>>();
+        assert_eq!(prices, vec![Price(dec!(95)), Price(dec!(90))]);
+        assert_eq!(
+            engine
+                .drain_events()
+                .filter(|event| matches!(
+                    event.event,
+                    EngineEvent::OrderCancelled {
+                        reason: CancelReason::MassCancel,
+                        ..
+                    }
+                ))
+                .count(),
+            2
+        );
+
+        let cancelled = engine
+            .mass_cancel(MassCancelFilter {
+                user_id: Some(trader),
+                ..MassCancelFilter::default()
+            })
+            .unwrap();
+        assert_eq!(cancelled.len(), 3);
+        assert_eq!(engine.market(&btc_usd()).unwrap().orderbook().len(), 1);
+        assert!(engine.market(&eth_usd).unwrap().orderbook().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mass_cancel_rounds_price_bounds_to_each_market_tick() {
+        let eth_usd = TradingPair::new("ETH".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine
+            .add_market_with_precision(eth_usd.clone(), Precision::new(2, 8).unwrap())
+            .unwrap();
+        for (pair, price) in [
+            (btc_usd(), dec!(90.005)),
+            (eth_usd.clone(), dec!(90.00)),
+            (eth_usd.clone(), dec!(91.00)),
+        ] {
+            engine
+                .place_limit_order(
+                    pair,
+                    Price(price),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                )
+                .unwrap();
+        }
+
+        // Too fine for ETH/USD; there the range is 90.01 - 91.00.
+        let cancelled = engine
+            .mass_cancel(MassCancelFilter {
+                price_range: Some((Price(dec!(90.001)), Price(dec!(91.005)))),
+                ..MassCancelFilter::default()
+            })
+            .unwrap();
+        let cancelled = cancelled
+            .into_iter()
+            .map(|(pair, order)| (pair, order.price))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            cancelled,
+            vec![
+                (btc_usd(), Price(dec!(90.005))),
+                (eth_usd.clone(), Price(dec!(91.00))),
+            ]
+        );
+        assert_eq!(engine.market(&eth_usd).unwrap().orderbook().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn client_order_ids_are_unique_and_address_cancels_and_amends() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let user = Uuid::new_v4();
+        let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(2)))
+            .with_user(user)
+            .with_client_order_id("bid-1");
+        let order_id = order.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(99)), order.clone())
+            .unwrap();
+
+        // A retried submission is rejected and points at the live order.
+        let retry = OrderRecord {
+            id: Uuid::new_v4(),
+            ..order
+        };
+        assert_eq!(
+            engine.place_limit_order(btc_usd(), Price(dec!(99)), retry),
+            Err(EngineError::DuplicateClientOrderId {
+                user_id: user,
+                client_order_id: "bid-1".to_string(),
+                existing_order_id: order_id,
+            })
+        );
+        // Another user may reuse the id.
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(98)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                    .with_user(Uuid::new_v4())
+                    .with_client_order_id("bid-1"),
+            )
+            .unwrap();
+
+        let by_client = OrderRef::Client {
+            user_id: user,
+            client_order_id: "bid-1".to_string(),
+        };
+        engine
+            .amend_order(
+                btc_usd(),
+                by_client.clone(),
+                user,
+                None,
+                Some(Price(dec!(101))),
+                None,
+            )
+            .unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(101)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+        let book = engine.market(&btc_usd()).unwrap().orderbook();
+        let resting = book.get(book.handle_of(order_id).unwrap()).unwrap();
+        assert_eq!(resting.order().price, Price(dec!(101)));
+        assert!(engine.drain_events().any(|event| matches!(
+            event.event,
+            EngineEvent::OrderAmended { order_id: id, .. } if id == order_id
+        )));
+
+        let cancelled = engine
+            .cancel_by_client_order_id(btc_usd(), user, None, "bid-1".to_string())
+            .unwrap();
+        assert_eq!(cancelled.id, order_id);
+        assert_eq!(cancelled.remaining_size, Quantity(dec!(1)));
+        assert!(matches!(
+            engine.amend_order(
+                btc_usd(),
+                by_client,
+                user,
+                None,
+                None,
+                Some(Quantity(dec!(1)))
+            ),
+            Err(EngineError::ClientOrderNotFound { .. })
+        ));
+
+        // The id stays taken after the order is gone, so a late retry
+        // cannot place it a second time.
+        assert_eq!(
+            engine.place_limit_order(
+                btc_usd(),
+                Price(dec!(97)),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+                    .with_user(user)
+                    .with_client_order_id("bid-1"),
+            ),
+            Err(EngineError::DuplicateClientOrderId {
+                user_id: user,
+                client_order_id: "bid-1".to_string(),
+                existing_order_id: order_id,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn growing_a_partly_filled_order_loses_priority() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        let owner = Uuid::new_v4();
+        let first = OrderRecord::new(OrderSide::Ask, Quantity(dec!(2))).with_user(owner);
+        let second = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(Uuid::new_v4());
+        let (first_id, second_id) = (first.id, second.id);
+        for order in [first, second] {
+            engine
+                .place_limit_order(btc_usd(), Price(dec!(100)), order)
+                .unwrap();
+        }
+        let buy = |engine: &mut MatchingEngine| {
+            let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(Uuid::new_v4());
+            engine.place_market_order(btc_usd(), bid).unwrap();
+        };
+        buy(&mut engine);
+        engine.drain_events().for_each(drop);
+
+        // 1.5 is less than the order's size but more than it has left open.
+        engine
+            .amend_order(
+                btc_usd(),
+                OrderRef::Id(first_id),
+                owner,
+                None,
+                None,
+                Some(Quantity(dec!(1.5))),
+            )
+            .unwrap();
+        assert!(engine.drain_events().any(|event| matches!(
+            event.event,
+            EngineEvent::OrderAmended {
+                lost_priority: true,
+                ..
+            }
+        )));
+
+        buy(&mut engine);
+        let maker = engine.drain_events().find_map(|event| match event.event {
+            EngineEvent::Trade { trade, .. } => Some(trade.sell_order_id),
+            _ => None,
+        });
+        assert_eq!(maker, Some(second_id));
+    }
+
+    #[tokio::test]
+    async fn amends_are_checked_against_the_requester_and_keep_the_hold_on_failure() {
+        let mut engine = MatchingEngine::new();
+        engine
+            .add_market_with_precision(btc_usd(), Precision::new(2, 4).unwrap())
+            .unwrap();
+        let user = Uuid::new_v4();
+        let mut gateway = PaymentGateway::new();
+        gateway.deposit(user, "USD", dec!(1_000)).unwrap();
+        engine.set_payment_gateway(gateway);
+        let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user);
+        let order_id = order.id;
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), order)
+            .unwrap();
+
+        // Knowing the id is not enough to touch someone else's order.
+        assert_eq!(
+            engine.amend_order(
+                btc_usd(),
+                OrderRef::Id(order_id),
+                Uuid::new_v4(),
+                None,
+                Some(Price(dec!(90))),
+                None,
+            ),
+            Err(EngineError::OrderNotFound {
+                pair: btc_usd(),
+                order_id,
+            })
+        );
+        assert!(matches!(
+            engine.amend_order(
+                btc_usd(),
+                OrderRef::Id(order_id),
+                user,
+                Some(Uuid::new_v4()),
+                Some(Price(dec!(90))),
+                None,
+            ),
+            Err(EngineError::SessionNotFound(_))
+        ));
+
+        // The book refuses a price finer than the tick after the new hold
+        // was taken, so the old one is put back.
+        assert!(matches!(
+            engine.amend_order(
+                btc_usd(),
+                OrderRef::Id(order_id),
+                user,
+                None,
+                Some(Price(dec!(50.005))),
+                None,
+            ),
+            Err(EngineError::OrderBook(_))
+        ));
+        let accounts = engine.payment_gateway().unwrap();
+        assert_eq!(accounts.held_for(order_id), Some(dec!(100)));
+        assert_eq!(accounts.balance(user, "USD").available, dec!(900));
+        let book = engine.market(&btc_usd()).unwrap().orderbook();
+        let resting = book.get(book.handle_of(order_id).unwrap()).unwrap();
+        assert_eq!(resting.order().price, Price(dec!(100)));
+    }
+
+    #[tokio::test]
+    async fn client_order_ids_of_filled_orders_cannot_be_reused() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        engine
+            .place_limit_order(
+                btc_usd(),
+                Price(dec!(100)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(5))),
+            )
+            .unwrap();
+        let user = Uuid::new_v4();
+
+        // Both fill completely, so neither is left in the book to match.
+        let limit = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+            .with_user(user)
+            .with_client_order_id("limit-1");
+        let market = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+            .with_user(user)
+            .with_client_order_id("market-1");
+        engine
+            .place_limit_order(btc_usd(), Price(dec!(100)), limit.clone())
+            .unwrap();
+        engine
+            .place_market_order(btc_usd(), market.clone())
+            .unwrap();
+
+        let retried_limit = OrderRecord {
+            id: Uuid::new_v4(),
+            ..limit.clone()
+        };
+        assert_eq!(
+            engine.place_limit_order(btc_usd(), Price(dec!(100)), retried_limit),
+            Err(EngineError::DuplicateClientOrderId {
+                user_id: user,
+                client_order_id: "limit-1".to_string(),
+                existing_order_id: limit.id,
+            })
+        );
+        let retried_market = OrderRecord {
+            id: Uuid::new_v4(),
+            ..market.clone()
+        };
+        assert!(matches!(
+            engine.place_market_order(btc_usd(), retried_market),
+            Err(EngineError::DuplicateClientOrderId { existing_order_id, .. })
+                if existing_order_id == market.id
+        ));
+        let book = engine.market(&btc_usd()).unwrap().orderbook();
+        assert_eq!(
+            book.best_ask().unwrap().total_volume().lots(),
+            3 * 10i64.pow(8)
+        );
+    }
+
+    #[tokio::test]
+    async fn trades_update_the_ticker_until_they_age_out() {
+        let mut engine = MatchingEngine::new();
+        engine.add_market(btc_usd()).unwrap();
+        assert_eq!(engine.ticker(&btc_usd()), None);
+
+        for price in [dec!(100), dec!(104)] {
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(price),
+                    OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+                )
+                .unwrap();
+            engine
+                .place_limit_order(
+                    btc_usd(),
+                    Price(price),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                )
+                .unwrap();
+        }
+        let stats = engine.ticker(&btc_usd()).unwrap();
+        assert_eq!(stats.open, Price(dec!(100)));
+        assert_eq!(stats.last, Price(dec!(104)));
+        assert_eq!(stats.trades, 2);
+        assert_eq!(stats.vwap, Price(dec!(102)));
+
+        assert!(engine.tick(Utc::now() + Duration::hours(25)).is_empty());
+        assert_eq!(engine.ticker(&btc_usd()), None);
     }
 }
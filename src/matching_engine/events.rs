@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::engine::TradingPair;
+use super::market_state::TradingState;
+use super::orderbook::AuctionIndication;
+use super::types::decimal::{Price, Quantity};
+use super::types::fixed::Precision;
+use super::types::order::OrderSide;
+use super::types::trade::Trade;
+use crate::errors::engine_error::EngineError;
+use crate::services::settlement::{Settlement, Transfer};
+
+/// Why an order left the book without filling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CancelReason {
+    Requested,
+    MarketDelisted(String),
+    SessionDisconnected(Uuid),
+    MassCancel,
+    /// Restored after a restart, but its owner's balance no longer covers
+    /// its hold.
+    InsufficientFunds,
+}
+
+/// Something the matching engine did, published on the outbound ring.
+/// Trades are the bulk of the traffic, so they travel inline rather than boxed.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    MarketAdded {
+        pair: TradingPair,
+        precision: Precision,
+        state: TradingState,
+    },
+    OrderAccepted {
+        pair: TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        side: OrderSide,
+        price: Price,
+        size: Quantity,
+        created_at: DateTime<Utc>,
+    },
+    /// Published after the order's trades. Market orders never rest, so
+    /// this is the only event that describes the order itself.
+    MarketOrderFilled {
+        pair: TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        client_order_id: Option<String>,
+        side: OrderSide,
+        /// Volume-weighted price of the fills; zero if nothing filled.
+        average_price: Price,
+        filled: Quantity,
+        remaining: Quantity,
+        created_at: DateTime<Utc>,
+    },
+    Trade {
+        pair: TradingPair,
+        trade: Trade,
+    },
+    /// Balance movements for a trade, published after the trade itself.
+    TradeSettled {
+        pair: TradingPair,
+        settlement: Settlement,
+    },
+    /// A deposit or withdrawal, already applied to the user's balance.
+    BalanceTransferred {
+        transfer: Transfer,
+    },
+    TradingStateChanged {
+        pair: TradingPair,
+        from: TradingState,
+        to: TradingState,
+    },
+    /// Published during a call period; `None` while the book does not cross.
+    AuctionIndicative {
+        pair: TradingPair,
+        indication: Option<AuctionIndication>,
+    },
+    AuctionUncrossed {
+        pair: TradingPair,
+        price: Option<Price>,
+        volume: Quantity,
+    },
+    CircuitBreakerTripped {
+        pair: TradingPair,
+        trigger_price: Price,
+        reference_price: Price,
+        auction_ends_at: DateTime<Utc>,
+    },
+    OrderAmended {
+        pair: TradingPair,
+        order_id: Uuid,
+        price: Price,
+        remaining: Quantity,
+        /// The order moved to the back of the queue at its new price.
+        lost_priority: bool,
+    },
+    OrderCancelled {
+        pair: TradingPair,
+        order_id: Uuid,
+        remaining: Quantity,
+        reason: CancelReason,
+    },
+    MarketDelisted {
+        pair: TradingPair,
+        reason: String,
+        cancelled_orders: usize,
+    },
+    /// The delisted market is gone and its pair can be listed again.
+    MarketRemoved {
+        pair: TradingPair,
+    },
+    CommandRejected {
+        reason: EngineError,
+    },
+}
+
+/// An event stamped with the engine's monotonically increasing sequence number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequencedEvent {
+    pub sequence: u64,
+    /// The engine's clock when the event happened, which is what the
+    /// database records as its time.
+    pub emitted_at: DateTime<Utc>,
+    pub event: EngineEvent,
+}
@@ -0,0 +1,337 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+use super::engine::TradingPair;
+use super::types::decimal::Notional;
+use super::types::fixed::Precision;
+use super::types::order::OrderSide;
+use super::types::trade::Trade;
+
+/// Length of the rolling window that decides a user's fee tier.
+pub const VOLUME_WINDOW_DAYS: i64 = 30;
+
+/// Which asset of the pair fees are charged in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeCurrency {
+    Base,
+    Quote,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Rates that apply once a user's 30-day traded notional reaches `min_volume`.
+///
+/// Rates are fractions, so 0.001 is 10 bps. A negative maker rate is a rebate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: Notional,
+    pub maker_rate: Decimal,
+    pub taker_rate: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeTier>,
+    pub fee_currency: FeeCurrency,
+}
+
+impl FeeSchedule {
+    pub fn new(mut tiers: Vec<FeeTier>, fee_currency: FeeCurrency) -> FeeSchedule {
+        tiers.sort_by_key(|tier| tier.min_volume);
+        FeeSchedule {
+            tiers,
+            fee_currency,
+        }
+    }
+
+    /// The most any tier charges per unit traded, as maker or taker; zero if
+    /// every rate is a rebate.
+    pub fn max_rate(&self) -> Decimal {
+        self.tiers
+            .iter()
+            .flat_map(|tier| [tier.maker_rate, tier.taker_rate])
+            .fold(Decimal::ZERO, Decimal::max)
+    }
+
+    /// The highest tier whose threshold `volume` reaches.
+    pub fn tier_for(&self, volume: Notional) -> Option<&FeeTier> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| volume >= tier.min_volume)
+    }
+}
+
+/// The fee one side of a trade pays, or receives when `amount` is negative.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fee {
+    pub user_id: Uuid,
+    pub liquidity: Liquidity,
+    pub rate: Decimal,
+    pub amount: Decimal,
+    pub asset: String,
+}
+
+/// Tracks each user's traded notional per day and prices executions.
+///
+/// Volume is the quote notional summed across every market the user trades.
+#[derive(Debug, Default)]
+pub struct FeeEngine {
+    volumes: HashMap<Uuid, VecDeque<(NaiveDate, Notional)>>,
+}
+
+impl FeeEngine {
+    pub fn new() -> FeeEngine {
+        FeeEngine::default()
+    }
+
+    /// Traded notional over the 30 days up to and including `now`.
+    pub fn rolling_volume(&self, user_id: Uuid, now: DateTime<Utc>) -> Notional {
+        let since = (now - Duration::days(VOLUME_WINDOW_DAYS - 1)).date_naive();
+        self.volumes
+            .get(&user_id)
+            .map(|days| {
+                days.iter()
+                    .filter(|(day, _)| *day >= since)
+                    .map(|(_, volume)| *volume)
+                    .sum()
+            })
+            .unwrap_or(Notional::ZERO)
+    }
+
+    /// Adds volume traded on `day` from before a restart. Days must arrive
+    /// oldest first for each user, as they did when the trades executed.
+    pub fn restore_volume(&mut self, user_id: Uuid, day: NaiveDate, notional: Notional) {
+        self.record_volume(user_id, notional, day);
+    }
+
+    fn record_volume(&mut self, user_id: Uuid, notional: Notional, day: NaiveDate) {
+        let days = self.volumes.entry(user_id).or_default();
+        match days.back_mut() {
+            Some((last, volume)) if *last == day => *volume += notional,
+            _ => days.push_back((day, notional)),
+        }
+        let since = day - Duration::days(VOLUME_WINDOW_DAYS - 1);
+        while days.front().is_some_and(|(day, _)| *day < since) {
+            days.pop_front();
+        }
+    }
+
+    fn fee(
+        &self,
+        schedule: &FeeSchedule,
+        pair: &TradingPair,
+        precision: Precision,
+        trade: &Trade,
+        user_id: Uuid,
+        liquidity: Liquidity,
+    ) -> Fee {
+        let volume = self.rolling_volume(user_id, trade.executed_at);
+        let rate = schedule
+            .tier_for(volume)
+            .map(|tier| match liquidity {
+                Liquidity::Maker => tier.maker_rate,
+                Liquidity::Taker => tier.taker_rate,
+            })
+            .unwrap_or(Decimal::ZERO);
+        let (base, asset, scale) = match schedule.fee_currency {
+            FeeCurrency::Base => (trade.quantity.0, pair.base(), precision.quantity_scale()),
+            FeeCurrency::Quote => (trade.notional().0, pair.quote(), precision.price_scale()),
+        };
+        // Towards zero, so a charge never exceeds what the order reserved and
+        // a rebate never exceeds what the fee account collected.
+        let amount = (base * rate).round_dp_with_strategy(scale, RoundingStrategy::ToZero);
+        Fee {
+            user_id,
+            liquidity,
+            rate,
+            amount,
+            asset: asset.to_string(),
+        }
+    }
+
+    /// Attaches buyer and seller fees to `trade`, rounded to the scale of the
+    /// asset they are charged in, and counts it towards both users' volume.
+    ///
+    /// Auction executions have no aggressor, so both sides pay the maker rate.
+    /// Nobody takes liquidity to fund a rebate there, so a negative maker
+    /// rate is charged as zero.
+    pub fn apply(
+        &mut self,
+        schedule: &FeeSchedule,
+        pair: &TradingPair,
+        precision: Precision,
+        trade: &mut Trade,
+    ) {
+        let (buyer, seller) = match trade.aggressor {
+            Some(OrderSide::Bid) => (Liquidity::Taker, Liquidity::Maker),
+            Some(OrderSide::Ask) => (Liquidity::Maker, Liquidity::Taker),
+            None => (Liquidity::Maker, Liquidity::Maker),
+        };
+        let mut buyer_fee = self.fee(schedule, pair, precision, trade, trade.buyer_id, buyer);
+        let mut seller_fee = self.fee(schedule, pair, precision, trade, trade.seller_id, seller);
+        if trade.aggressor.is_none() {
+            for fee in [&mut buyer_fee, &mut seller_fee] {
+                if fee.rate < Decimal::ZERO {
+                    fee.rate = Decimal::ZERO;
+                    fee.amount = Decimal::ZERO;
+                }
+            }
+        }
+        // The fee account pays a rebate out of what the other side was
+        // charged on the same trade, so it never pays out more.
+        let (buyer_cap, seller_cap) = (seller_fee.amount, buyer_fee.amount);
+        for (fee, cap) in [(&mut buyer_fee, buyer_cap), (&mut seller_fee, seller_cap)] {
+            fee.amount = fee.amount.max(-cap.max(Decimal::ZERO));
+        }
+        trade.buyer_fee = Some(buyer_fee);
+        trade.seller_fee = Some(seller_fee);
+
+        let (notional, day) = (trade.notional(), trade.executed_at.date_naive());
+        self.record_volume(trade.buyer_id, notional, day);
+        if trade.seller_id != trade.buyer_id {
+            self.record_volume(trade.seller_id, notional, day);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use rust_decimal_macros::dec;
+
+    fn schedule(fee_currency: FeeCurrency) -> FeeSchedule {
+        FeeSchedule::new(
+            vec![
+                FeeTier {
+                    min_volume: Notional(dec!(1_000)),
+                    maker_rate: dec!(-0.0001),
+                    taker_rate: dec!(0.0005),
+                },
+                FeeTier {
+                    min_volume: Notional::ZERO,
+                    maker_rate: dec!(0.0002),
+                    taker_rate: dec!(0.001),
+                },
+            ],
+            fee_currency,
+        )
+    }
+
+    fn trade(buyer: Uuid, seller: Uuid, price: Price, quantity: Quantity) -> Trade {
+        Trade::new(
+            (Uuid::new_v4(), buyer),
+            (Uuid::new_v4(), seller),
+            Some(OrderSide::Bid),
+            price,
+            quantity,
+        )
+    }
+
+    #[tokio::test]
+    async fn charges_taker_and_maker_rates_in_the_quote_asset() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut fees = FeeEngine::new();
+        let mut trade = trade(buyer, seller, Price(dec!(100)), Quantity(dec!(2)));
+
+        fees.apply(
+            &schedule(FeeCurrency::Quote),
+            &pair,
+            Precision::default(),
+            &mut trade,
+        );
+
+        let buyer_fee = trade.buyer_fee.unwrap();
+        assert_eq!(buyer_fee.liquidity, Liquidity::Taker);
+        assert_eq!(buyer_fee.amount, dec!(0.2));
+        assert_eq!(buyer_fee.asset, "USD");
+        let seller_fee = trade.seller_fee.unwrap();
+        assert_eq!(seller_fee.liquidity, Liquidity::Maker);
+        assert_eq!(seller_fee.amount, dec!(0.04));
+    }
+
+    #[tokio::test]
+    async fn higher_tier_pays_less_and_makers_earn_a_rebate() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let schedule = schedule(FeeCurrency::Base);
+        let mut fees = FeeEngine::new();
+
+        let mut first = trade(buyer, seller, Price(dec!(100)), Quantity(dec!(10)));
+        fees.apply(&schedule, &pair, Precision::default(), &mut first);
+        assert_eq!(first.buyer_fee.unwrap().rate, dec!(0.001));
+
+        let mut second = trade(buyer, seller, Price(dec!(100)), Quantity(dec!(1)));
+        fees.apply(&schedule, &pair, Precision::default(), &mut second);
+        let buyer_fee = second.buyer_fee.unwrap();
+        assert_eq!(buyer_fee.rate, dec!(0.0005));
+        assert_eq!(buyer_fee.asset, "BTC");
+        assert_eq!(second.seller_fee.unwrap().amount, dec!(-0.0001));
+    }
+
+    #[tokio::test]
+    async fn rebates_never_exceed_the_takers_fee() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let schedule = FeeSchedule::new(
+            vec![FeeTier {
+                min_volume: Notional::ZERO,
+                maker_rate: dec!(-0.001),
+                taker_rate: dec!(0.0005),
+            }],
+            FeeCurrency::Quote,
+        );
+        let mut fees = FeeEngine::new();
+        let mut trade = trade(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Price(dec!(100)),
+            Quantity(dec!(2)),
+        );
+
+        fees.apply(&schedule, &pair, Precision::default(), &mut trade);
+
+        assert_eq!(trade.buyer_fee.unwrap().amount, dec!(0.1));
+        assert_eq!(trade.seller_fee.unwrap().amount, dec!(-0.1));
+    }
+
+    #[tokio::test]
+    async fn volume_older_than_thirty_days_drops_out() {
+        let user = Uuid::new_v4();
+        let mut fees = FeeEngine::new();
+        let now = Utc::now();
+        let day = now.date_naive();
+        fees.restore_volume(user, day - Duration::days(31), Notional(dec!(500)));
+        fees.restore_volume(user, day - Duration::days(3), Notional(dec!(200)));
+        fees.restore_volume(user, day, Notional(dec!(100)));
+
+        assert_eq!(fees.rolling_volume(user, now), Notional(dec!(300)));
+    }
+
+    #[tokio::test]
+    async fn fees_are_rounded_towards_zero_to_the_asset_scale() {
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut fees = FeeEngine::new();
+        // A notional of 1234.5678, so an unrounded taker fee of 1.2345678.
+        let mut trade = trade(buyer, seller, Price(dec!(123.45678)), Quantity(dec!(10)));
+
+        fees.apply(
+            &schedule(FeeCurrency::Quote),
+            &pair,
+            Precision::new(2, 4).unwrap(),
+            &mut trade,
+        );
+
+        assert_eq!(trade.buyer_fee.unwrap().amount, dec!(1.23));
+        assert_eq!(trade.seller_fee.unwrap().amount, dec!(0.24));
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Lifecycle state of a single market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TradingState {
+    /// Listed but not yet open. Only cancels are accepted.
+    PreOpen,
+    /// Call period: limit orders accumulate and uncross on leaving the state.
+    Auction,
+    /// Normal matching.
+    Continuous,
+    /// Paused by an operator or a circuit breaker. Only cancels are accepted.
+    Halted,
+    /// Session over. Only cancels are accepted.
+    Closed,
+    /// Permanently removed. Nothing is accepted.
+    Delisted,
+}
+
+/// A command the engine can be asked to perform against a market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MarketAction {
+    PlaceLimit,
+    PlaceMarket,
+    Cancel,
+    Amend,
+}
+
+impl TradingState {
+    pub fn allows(self, action: MarketAction) -> bool {
+        match self {
+            TradingState::Continuous => true,
+            TradingState::Auction => {
+                matches!(
+                    action,
+                    MarketAction::PlaceLimit | MarketAction::Cancel | MarketAction::Amend
+                )
+            }
+            TradingState::PreOpen | TradingState::Halted | TradingState::Closed => {
+                action == MarketAction::Cancel
+            }
+            TradingState::Delisted => false,
+        }
+    }
+
+    /// Continuous trading is only ever entered from a call period, so a
+    /// market opens, and reopens after a halt, with an auction.
+    pub fn can_transition_to(self, next: TradingState) -> bool {
+        use TradingState::*;
+        match (self, next) {
+            (from, to) if from == to => false,
+            (Delisted, _) => false,
+            (_, Delisted) => true,
+            (Closed, PreOpen) => true,
+            (Closed, _) => false,
+            (_, PreOpen) => false,
+            (from, Continuous) => from == Auction,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for TradingState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TradingState::PreOpen => "pre-open",
+            TradingState::Auction => "auction",
+            TradingState::Continuous => "continuous",
+            TradingState::Halted => "halted",
+            TradingState::Closed => "closed",
+            TradingState::Delisted => "delisted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for TradingState {
+    type Err = String;
+
+    /// Parses the `Display` form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pre-open" => Ok(TradingState::PreOpen),
+            "auction" => Ok(TradingState::Auction),
+            "continuous" => Ok(TradingState::Continuous),
+            "halted" => Ok(TradingState::Halted),
+            "closed" => Ok(TradingState::Closed),
+            "delisted" => Ok(TradingState::Delisted),
+            _ => Err(format!("unknown trading state {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for MarketAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MarketAction::PlaceLimit => "place limit order",
+            MarketAction::PlaceMarket => "place market order",
+            MarketAction::Cancel => "cancel order",
+            MarketAction::Amend => "amend order",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_continuous_accepts_market_orders() {
+        for state in [
+            TradingState::PreOpen,
+            TradingState::Auction,
+            TradingState::Halted,
+            TradingState::Closed,
+            TradingState::Delisted,
+        ] {
+            assert!(!state.allows(MarketAction::PlaceMarket));
+        }
+        assert!(TradingState::Continuous.allows(MarketAction::PlaceMarket));
+        assert!(TradingState::Auction.allows(MarketAction::PlaceLimit));
+        assert!(TradingState::Halted.allows(MarketAction::Cancel));
+        assert!(!TradingState::Delisted.allows(MarketAction::Cancel));
+    }
+
+    #[tokio::test]
+    async fn delisting_is_terminal_and_reopening_goes_through_pre_open() {
+        assert!(TradingState::Continuous.can_transition_to(TradingState::Halted));
+        assert!(TradingState::Halted.can_transition_to(TradingState::Auction));
+        assert!(TradingState::Closed.can_transition_to(TradingState::PreOpen));
+        assert!(!TradingState::Closed.can_transition_to(TradingState::Continuous));
+        assert!(!TradingState::Halted.can_transition_to(TradingState::PreOpen));
+        assert!(!TradingState::Delisted.can_transition_to(TradingState::PreOpen));
+        assert!(!TradingState::Continuous.can_transition_to(TradingState::Continuous));
+    }
+
+    #[tokio::test]
+    async fn trading_resumes_only_through_an_auction() {
+        assert!(!TradingState::Halted.can_transition_to(TradingState::Continuous));
+        assert!(!TradingState::PreOpen.can_transition_to(TradingState::Continuous));
+        assert!(TradingState::PreOpen.can_transition_to(TradingState::Auction));
+        assert!(TradingState::Auction.can_transition_to(TradingState::Continuous));
+    }
+}
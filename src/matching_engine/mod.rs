@@ -1,3 +1,16 @@
+pub mod auction;
+pub mod candles;
+pub mod circuit_breaker;
 pub mod engine;
+pub mod events;
+pub mod fees;
+pub mod market_state;
 pub mod orderbook;
+pub mod pipeline;
+pub mod rate_limit;
+pub mod ring_buffer;
+pub mod risk;
+pub mod session;
+pub mod slab;
+pub mod ticker;
 pub mod types;
@@ -1,252 +1,1416 @@
-#![allow(dead_code)]
+use super::auction::{self, Equilibrium};
+#[cfg(test)]
+use super::engine::TradingPair;
+use super::slab::{Handle, Slab};
+use super::types::decimal::{Price, Quantity};
+use super::types::fixed::{self, FixedPointError, Precision};
 use super::types::order::{OrderRecord, OrderSide, OrderStatus};
-use chrono::Utc;
-use rust_decimal::prelude::*;
+use super::types::trade::Trade;
+use chrono::{DateTime, Utc};
 use rust_decimal_macros::dec;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use uuid::Uuid;
-#[derive(Debug)]
-pub struct Order {
-    side: OrderSide,
-    size: f64,
+/// Initial number of order slots and index entries reserved per book.
+const DEFAULT_ORDER_CAPACITY: usize = 1024;
+
+/// Whether incoming orders match immediately or accumulate for an uncross.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookMode {
+    Continuous,
+    Auction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderBookError {
+    FixedPoint(FixedPointError),
+    MarketOrderDuringAuction,
+    NotInAuction,
+    DuplicateOrderId(Uuid),
+}
+
+impl fmt::Display for OrderBookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderBookError::FixedPoint(e) => write!(f, "{}", e),
+            OrderBookError::MarketOrderDuringAuction => {
+                write!(f, "market orders cannot be placed during an auction call")
+            }
+            OrderBookError::NotInAuction => write!(f, "the order book is not in an auction"),
+            OrderBookError::DuplicateOrderId(order_id) => {
+                write!(f, "order {} is already in the book", order_id)
+            }
+        }
+    }
 }
 
-impl Order {
-    fn new(side: OrderSide, size: f64) -> Order {
-        Order { side, size }
+impl std::error::Error for OrderBookError {}
+
+impl From<FixedPointError> for OrderBookError {
+    fn from(err: FixedPointError) -> OrderBookError {
+        OrderBookError::FixedPoint(err)
     }
 }
+
+/// What happened to a limit order submitted through `OrderBook::submit_limit_order`.
+#[derive(Debug)]
+pub struct LimitOrderOutcome {
+    pub trades: Vec<Trade>,
+    /// Handle of the unfilled remainder, if any of the order rests.
+    pub resting: Option<OrderHandle>,
+}
+
+/// Indicative auction result published while orders accumulate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuctionIndication {
+    pub price: Price,
+    pub volume: Quantity,
+    pub imbalance: Quantity,
+    pub imbalance_side: Option<OrderSide>,
+}
+
+/// Aggregated depth at one price.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelSnapshot {
+    pub price: Price,
+    pub volume: Quantity,
+    pub order_count: usize,
+}
+
+/// Point-in-time depth of both sides, best prices first.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub bids: Vec<LevelSnapshot>,
+    pub asks: Vec<LevelSnapshot>,
+}
+
+/// A quantity taken from the front of a price level.
+#[derive(Debug, Clone, Copy)]
+struct Fill {
+    order_id: Uuid,
+    user_id: Uuid,
+    quantity: fixed::Quantity,
+}
+
 #[derive(Debug)]
 pub struct OrderBook {
-    asks: HashMap<Decimal, Limit>,
-    bids: HashMap<Decimal, Limit>,
+    precision: Precision,
+    mode: BookMode,
+    asks: BTreeMap<fixed::Price, Limit>,
+    bids: BTreeMap<fixed::Price, Limit>,
+    orders: Slab<RestingOrder>,
+    order_index: OrderIndex,
+    last_trade_price: Option<fixed::Price>,
+    fills: Vec<Fill>,
+    /// Scratch buffers reused across calls so matching and mass cancels do
+    /// not allocate once they have grown to the book's usual size.
+    trades: Vec<Trade>,
+    handles: Vec<OrderHandle>,
 }
 
 impl OrderBook {
+    #[cfg(test)]
     pub fn new() -> OrderBook {
+        OrderBook::with_precision(Precision::default())
+    }
+
+    pub fn with_precision(precision: Precision) -> OrderBook {
+        OrderBook::with_capacity(precision, DEFAULT_ORDER_CAPACITY)
+    }
+
+    /// Creates a book that can hold `capacity` resting orders before it allocates.
+    pub fn with_capacity(precision: Precision, capacity: usize) -> OrderBook {
         OrderBook {
-            asks: HashMap::new(),
-            bids: HashMap::new(),
+            precision,
+            mode: BookMode::Continuous,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            orders: Slab::with_capacity(capacity),
+            order_index: OrderIndex::with_capacity(capacity),
+            last_trade_price: None,
+            fills: Vec::new(),
+            trades: Vec::new(),
+            handles: Vec::new(),
         }
     }
 
-    pub async fn add_limit_order_with_db(
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    pub fn mode(&self) -> BookMode {
+        self.mode
+    }
+
+    pub fn last_trade_price(&self) -> Option<Price> {
+        self.last_trade_price
+            .map(|price| Price(price.to_decimal(self.precision)))
+    }
+
+    /// Number of orders resting on both sides.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn fill_market_order(
         &mut self,
-        price: Decimal,
-        order: Order,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let order_record = OrderRecord {
-            id: Uuid::new_v4(),
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
-            price,
-            size: Decimal::from_f64(order.size).unwrap(),
-            remaining_size: Decimal::from_f64(order.size).unwrap(),
-            side: order.side,
-            status: OrderStatus::New,
-        };
+        market_order: &mut OrderRecord,
+    ) -> Result<Vec<Trade>, OrderBookError> {
+        if self.mode == BookMode::Auction {
+            return Err(OrderBookError::MarketOrderDuringAuction);
+        }
+        let mut remaining =
+            fixed::Quantity::from_decimal(market_order.remaining_size.0, self.precision)?;
 
-        //save to database
-        // db.save_order(&order_record).await?;
+        let trades = self.match_incoming(market_order, None, &mut remaining)?;
 
-        //Add to in-memory orderbook
-        self.add_limit_order(price, order_record);
-        Ok(())
+        // Convert back to the boundary representation once, not per fill.
+        market_order.remaining_size = Quantity(remaining.to_decimal(self.precision));
+        Ok(trades)
     }
-    pub fn fill_market_order(&mut self, market_order: &mut OrderRecord) {
-        // If it's a sell market order, look at bids
-        // If it's a buy market order, look at asks
-        let limits = match market_order.side {
-            OrderSide::Ask => self.bid_limits(),
-            OrderSide::Bid => self.ask_limits(),
+
+    /// Matches a limit order against the opposite side and rests the remainder.
+    ///
+    /// During an auction call nothing matches; the whole order rests.
+    pub fn submit_limit_order(
+        &mut self,
+        price: Price,
+        mut order: OrderRecord,
+    ) -> Result<LimitOrderOutcome, OrderBookError> {
+        // Checked before matching so a rejected order leaves no trades behind.
+        if self.order_index.get(order.id).is_some() {
+            return Err(OrderBookError::DuplicateOrderId(order.id));
+        }
+        let limit_price = fixed::Price::from_decimal(price.0, self.precision)?;
+        let mut remaining = fixed::Quantity::from_decimal(order.remaining_size.0, self.precision)?;
+
+        let trades = match self.mode {
+            BookMode::Continuous => {
+                self.match_incoming(&order, Some(limit_price), &mut remaining)?
+            }
+            BookMode::Auction => Vec::new(),
         };
 
-        for limit_order in limits {
-            limit_order.fill_order(market_order);
+        order.remaining_size = Quantity(remaining.to_decimal(self.precision));
+        let resting = match order.remaining_size.is_zero() {
+            true => None,
+            false => Some(self.rest(limit_price, order)?),
+        };
+
+        Ok(LimitOrderOutcome { trades, resting })
+    }
+
+    /// Hands back a trade buffer this book returned, emptied, so the next
+    /// match reuses its capacity.
+    pub fn recycle_trades(&mut self, mut trades: Vec<Trade>) {
+        if trades.capacity() > self.trades.capacity() {
+            trades.clear();
+            self.trades = trades;
+        }
+    }
+
+    /// The scratch trade buffer, empty.
+    fn take_trades(&mut self) -> Vec<Trade> {
+        let mut trades = std::mem::take(&mut self.trades);
+        trades.clear();
+        trades
+    }
+
+    /// Walks the opposite side in price-time priority, up to `limit_price` if given.
+    fn match_incoming(
+        &mut self,
+        incoming: &OrderRecord,
+        limit_price: Option<fixed::Price>,
+        remaining: &mut fixed::Quantity,
+    ) -> Result<Vec<Trade>, OrderBookError> {
+        let mut trades = self.take_trades();
+
+        while !remaining.is_zero() {
+            // If it's a sell order, look at bids
+            // If it's a buy order, look at asks
+            let best = match incoming.side {
+                OrderSide::Ask => self.bids.last_entry(),
+                OrderSide::Bid => self.asks.first_entry(),
+            };
+            let Some(mut best) = best else {
+                break;
+            };
 
-            if market_order.is_filled() {
+            let level_price = *best.key();
+            let crosses = match (incoming.side, limit_price) {
+                (_, None) => true,
+                (OrderSide::Bid, Some(limit)) => level_price <= limit,
+                (OrderSide::Ask, Some(limit)) => level_price >= limit,
+            };
+            if !crosses {
                 break;
             }
+
+            let limit = best.get_mut();
+            self.fills.clear();
+            limit.fill_order(
+                &mut self.orders,
+                &mut self.order_index,
+                remaining,
+                &mut self.fills,
+            )?;
+            if limit.is_empty() {
+                best.remove();
+            }
+
+            let price = Price(level_price.to_decimal(self.precision));
+            for fill in self.fills.iter() {
+                let taker = (incoming.id, incoming.user_id);
+                let maker = (fill.order_id, fill.user_id);
+                let (buy, sell) = match incoming.side {
+                    OrderSide::Bid => (taker, maker),
+                    OrderSide::Ask => (maker, taker),
+                };
+                trades.push(Trade::new(
+                    buy,
+                    sell,
+                    Some(incoming.side),
+                    price,
+                    Quantity(fill.quantity.to_decimal(self.precision)),
+                ));
+            }
+            self.last_trade_price = Some(level_price);
         }
+
+        Ok(trades)
     }
 
-    pub fn ask_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits = self.asks.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| a.price.cmp(&b.price));
-        limits
+    /// Stops matching; orders accumulate until `uncross`.
+    pub fn start_auction(&mut self) {
+        self.mode = BookMode::Auction;
     }
 
-    pub fn bid_limits(&mut self) -> Vec<&mut Limit> {
-        let mut limits = self.bids.values_mut().collect::<Vec<&mut Limit>>();
-        limits.sort_by(|a, b| b.price.cmp(&a.price));
+    fn levels(limits: &BTreeMap<fixed::Price, Limit>) -> Vec<(fixed::Price, fixed::Quantity)> {
         limits
+            .iter()
+            .map(|(price, limit)| (*price, limit.total_volume()))
+            .collect()
     }
-    pub fn add_limit_order(&mut self, price: Decimal, order: OrderRecord) {
-        match order.side {
-            OrderSide::Bid => match self.bids.get_mut(&price) {
-                Some(limit) => {
-                    limit.add_order(order);
-                }
-                None => {
-                    let mut limit = Limit::new(price);
-                    limit.add_order(order);
-                    self.bids.insert(price, limit);
-                }
-            },
-            OrderSide::Ask => match self.asks.get_mut(&price) {
-                Some(limit) => {
-                    limit.add_order(order);
+
+    fn equilibrium(&self) -> Result<Option<Equilibrium>, FixedPointError> {
+        auction::find_equilibrium(
+            &Self::levels(&self.bids),
+            &Self::levels(&self.asks),
+            self.last_trade_price,
+        )
+    }
+
+    /// The price and volume the auction would uncross at right now.
+    pub fn indicative_auction(&self) -> Result<Option<AuctionIndication>, OrderBookError> {
+        if self.mode != BookMode::Auction {
+            return Err(OrderBookError::NotInAuction);
+        }
+        Ok(self.equilibrium()?.map(|equilibrium| AuctionIndication {
+            price: Price(equilibrium.price.to_decimal(self.precision)),
+            volume: Quantity(equilibrium.volume.to_decimal(self.precision)),
+            imbalance: Quantity(equilibrium.imbalance.to_decimal(self.precision)),
+            imbalance_side: equilibrium.imbalance_side,
+        }))
+    }
+
+    /// Executes every crossing order at the equilibrium price and resumes
+    /// continuous matching.
+    pub fn uncross(&mut self) -> Result<Vec<Trade>, OrderBookError> {
+        if self.mode != BookMode::Auction {
+            return Err(OrderBookError::NotInAuction);
+        }
+        let mut trades = self.take_trades();
+
+        if let Some(equilibrium) = self.equilibrium()? {
+            let price = Price(equilibrium.price.to_decimal(self.precision));
+            let mut volume = equilibrium.volume;
+
+            while !volume.is_zero() {
+                let (Some(mut bid), Some(mut ask)) =
+                    (self.bids.last_entry(), self.asks.first_entry())
+                else {
+                    break;
+                };
+                let head_volume = |limit: &Limit, orders: &Slab<RestingOrder>| {
+                    limit
+                        .head
+                        .and_then(|head| orders.get(head))
+                        .map(|order| order.remaining)
+                        .unwrap_or(fixed::Quantity::ZERO)
+                };
+                let quantity = volume
+                    .min(head_volume(bid.get(), &self.orders))
+                    .min(head_volume(ask.get(), &self.orders));
+
+                let buy =
+                    bid.get_mut()
+                        .fill_head(&mut self.orders, &mut self.order_index, quantity)?;
+                let sell =
+                    ask.get_mut()
+                        .fill_head(&mut self.orders, &mut self.order_index, quantity)?;
+                if bid.get().is_empty() {
+                    bid.remove();
                 }
-                None => {
-                    let mut limit = Limit::new(price);
-                    limit.add_order(order);
-                    self.asks.insert(price, limit);
+                if ask.get().is_empty() {
+                    ask.remove();
                 }
-            },
+
+                let (Some(buy), Some(sell)) = (buy, sell) else {
+                    break;
+                };
+                trades.push(Trade::new(
+                    (buy.order_id, buy.user_id),
+                    (sell.order_id, sell.user_id),
+                    None,
+                    price,
+                    Quantity(quantity.to_decimal(self.precision)),
+                ));
+                volume = volume.checked_sub(quantity)?;
+            }
+            self.last_trade_price = Some(equilibrium.price);
+        }
+
+        self.mode = BookMode::Continuous;
+        Ok(trades)
+    }
+
+    #[cfg(test)]
+    pub fn best_ask(&self) -> Option<&Limit> {
+        self.asks.values().next()
+    }
+
+    #[cfg(test)]
+    pub fn best_bid(&self) -> Option<&Limit> {
+        self.bids.values().next_back()
+    }
+
+    /// First and last price an incoming order would execute at, without
+    /// touching the book. `None` if it would not trade at all.
+    pub fn execution_price_range(
+        &self,
+        side: OrderSide,
+        limit_price: Option<Price>,
+        quantity: Quantity,
+    ) -> Result<Option<(Price, Price)>, OrderBookError> {
+        let limit_price = limit_price
+            .map(|price| fixed::Price::from_decimal(price.0, self.precision))
+            .transpose()?;
+        let remaining = fixed::Quantity::from_decimal(quantity.0, self.precision)?;
+
+        let range = match side {
+            OrderSide::Bid => Self::crossed_range(self.asks.iter(), side, limit_price, remaining)?,
+            OrderSide::Ask => {
+                Self::crossed_range(self.bids.iter().rev(), side, limit_price, remaining)?
+            }
+        };
+
+        Ok(range.map(|(first, last)| {
+            (
+                Price(first.to_decimal(self.precision)),
+                Price(last.to_decimal(self.precision)),
+            )
+        }))
+    }
+
+    fn crossed_range<'a>(
+        levels: impl Iterator<Item = (&'a fixed::Price, &'a Limit)>,
+        side: OrderSide,
+        limit_price: Option<fixed::Price>,
+        mut remaining: fixed::Quantity,
+    ) -> Result<Option<(fixed::Price, fixed::Price)>, FixedPointError> {
+        let mut range: Option<(fixed::Price, fixed::Price)> = None;
+        for (&price, limit) in levels {
+            let crosses = match (side, limit_price) {
+                (_, None) => true,
+                (OrderSide::Bid, Some(limit)) => price <= limit,
+                (OrderSide::Ask, Some(limit)) => price >= limit,
+            };
+            if remaining.is_zero() || !crosses {
+                break;
+            }
+            range = Some((range.map_or(price, |(first, _)| first), price));
+            remaining = remaining.checked_sub(remaining.min(limit.total_volume()))?;
+        }
+        Ok(range)
+    }
+
+    /// Rests `order` at `price` without matching it.
+    pub fn add_limit_order(
+        &mut self,
+        price: Price,
+        order: OrderRecord,
+    ) -> Result<OrderHandle, OrderBookError> {
+        let price = fixed::Price::from_decimal(price.0, self.precision)?;
+        self.rest(price, order)
+    }
+
+    /// Ids must be unique within the book; a second order under the same id
+    /// would shadow the first in the index.
+    fn rest(
+        &mut self,
+        price: fixed::Price,
+        order: OrderRecord,
+    ) -> Result<OrderHandle, OrderBookError> {
+        if self.order_index.get(order.id).is_some() {
+            return Err(OrderBookError::DuplicateOrderId(order.id));
+        }
+        let resting = RestingOrder::new(order, price, self.precision)?;
+        let side = resting.order.side;
+
+        let limits = match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+        let limit = limits.entry(price).or_insert_with(|| Limit::new(price));
+        let handle = self.orders.insert(resting);
+        if let Err(e) = limit.push_back(&mut self.orders, handle) {
+            self.orders.remove(handle);
+            if limit.is_empty() {
+                limits.remove(&price);
+            }
+            return Err(e.into());
+        }
+        if let Some(resting) = self.orders.get(handle) {
+            self.order_index.insert(&resting.order, handle);
+        }
+        Ok(handle)
+    }
+
+    /// Number of orders `user_id` has resting in this book.
+    pub fn open_orders_for(&self, user_id: Uuid) -> usize {
+        self.order_index.open_orders_for(user_id)
+    }
+
+    pub fn handle_of(&self, order_id: Uuid) -> Option<OrderHandle> {
+        self.order_index.get(order_id)
+    }
+
+    pub fn get(&self, handle: OrderHandle) -> Option<&RestingOrder> {
+        self.orders.get(handle)
+    }
+
+    /// The order behind `handle` with the quantity it still has open.
+    pub fn open_order(&mut self, handle: OrderHandle) -> Option<&OrderRecord> {
+        let precision = self.precision;
+        let resting = self.orders.get_mut(handle)?;
+        resting.sync_remaining(precision);
+        Some(&resting.order)
+    }
+
+    /// Resting order id for a client order id, if that order is still live.
+    pub fn order_for_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Uuid> {
+        self.order_index
+            .order_for_client_id(user_id, client_order_id)
+    }
+
+    /// Removes a resting order in O(1) and returns it marked as cancelled at
+    /// `now`.
+    pub fn cancel(&mut self, handle: OrderHandle, now: DateTime<Utc>) -> Option<OrderRecord> {
+        let mut order = self.take(handle, now)?;
+        order.status = OrderStatus::Cancelled;
+        Some(order)
+    }
+
+    /// Changes a resting order's price and open quantity.
+    ///
+    /// Reducing the quantity at the same price keeps the order's place in the
+    /// queue. Any other change re-enters it at the back of its new level, and
+    /// it may trade if the new price crosses.
+    pub fn amend(
+        &mut self,
+        handle: OrderHandle,
+        price: Price,
+        remaining: Quantity,
+        now: DateTime<Utc>,
+    ) -> Result<LimitOrderOutcome, OrderBookError> {
+        let new_price = fixed::Price::from_decimal(price.0, self.precision)?;
+        let new_remaining = fixed::Quantity::from_decimal(remaining.0, self.precision)?;
+        let Some(resting) = self.orders.get(handle) else {
+            return Ok(LimitOrderOutcome {
+                trades: Vec::new(),
+                resting: None,
+            });
+        };
+        let (old_price, old_remaining, side) =
+            (resting.price, resting.remaining, resting.order.side);
+
+        if new_price == old_price && new_remaining <= old_remaining && !new_remaining.is_zero() {
+            let limits = match side {
+                OrderSide::Bid => &mut self.bids,
+                OrderSide::Ask => &mut self.asks,
+            };
+            if let Some(limit) = limits.get_mut(&old_price) {
+                limit.volume = limit
+                    .volume
+                    .checked_sub(old_remaining.checked_sub(new_remaining)?)?;
+            }
+            if let Some(resting) = self.orders.get_mut(handle) {
+                resting.remaining = new_remaining;
+                resting.order.remaining_size = remaining;
+                resting.order.updated_at = now;
+            }
+            return Ok(LimitOrderOutcome {
+                trades: Vec::new(),
+                resting: Some(handle),
+            });
+        }
+
+        let Some(mut order) = self.take(handle, now) else {
+            return Ok(LimitOrderOutcome {
+                trades: Vec::new(),
+                resting: None,
+            });
+        };
+        order.remaining_size = remaining;
+        self.submit_limit_order(price, order)
+    }
+
+    /// Unlinks a resting order and frees its slot.
+    fn take(&mut self, handle: OrderHandle, now: DateTime<Utc>) -> Option<OrderRecord> {
+        let (price, side) = {
+            let resting = self.orders.get(handle)?;
+            (resting.price, resting.order.side)
+        };
+        let limits = match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+        let limit = limits.get_mut(&price)?;
+        limit.unlink(&mut self.orders, handle);
+        if limit.is_empty() {
+            limits.remove(&price);
+        }
+
+        let resting = self.orders.remove(handle)?;
+        self.order_index.remove(&resting.order);
+        Some(resting.into_record(self.precision, now))
+    }
+
+    pub fn cancel_order(&mut self, order_id: Uuid, now: DateTime<Utc>) -> Option<OrderRecord> {
+        let handle = self.handle_of(order_id)?;
+        self.cancel(handle, now)
+    }
+
+    /// Cancels every resting order, bids then asks, each in price-time priority.
+    pub fn cancel_all(&mut self, now: DateTime<Utc>) -> Vec<OrderRecord> {
+        self.cancel_where(|_| true, now)
+    }
+
+    /// Cancels every resting order `predicate` selects, in the same order as
+    /// `cancel_all`.
+    pub fn cancel_where<F>(&mut self, predicate: F, now: DateTime<Utc>) -> Vec<OrderRecord>
+    where
+        F: FnMut(&OrderRecord) -> bool,
+    {
+        let mut handles = std::mem::take(&mut self.handles);
+        handles.extend(
+            self.bids
+                .values()
+                .rev()
+                .chain(self.asks.values())
+                .flat_map(|limit| limit.handles(&self.orders)),
+        );
+        self.cancel_selected(handles, predicate, now)
+    }
+
+    /// Like `cancel_where`, but only visits the levels on `side` (both when
+    /// `None`) whose price lies in the inclusive `price_range`.
+    ///
+    /// Bounds finer than the book's tick are rounded inwards rather than
+    /// rejected, so one range can be applied to books of any precision.
+    pub fn cancel_in_range<F>(
+        &mut self,
+        side: Option<OrderSide>,
+        price_range: Option<(Price, Price)>,
+        predicate: F,
+        now: DateTime<Utc>,
+    ) -> Vec<OrderRecord>
+    where
+        F: Fn(&OrderRecord) -> bool,
+    {
+        let (low, high) = match price_range {
+            Some((low, high)) => (
+                fixed::Price::ceil_from_decimal(low.0, self.precision),
+                fixed::Price::floor_from_decimal(high.0, self.precision),
+            ),
+            None => (fixed::Price(i64::MIN), fixed::Price(i64::MAX)),
+        };
+        if low > high {
+            return Vec::new();
+        }
+        let mut handles = std::mem::take(&mut self.handles);
+        if side != Some(OrderSide::Ask) {
+            for limit in self.bids.range(low..=high).rev().map(|(_, limit)| limit) {
+                handles.extend(limit.handles(&self.orders));
+            }
+        }
+        if side != Some(OrderSide::Bid) {
+            for limit in self.asks.range(low..=high).map(|(_, limit)| limit) {
+                handles.extend(limit.handles(&self.orders));
+            }
+        }
+        self.cancel_selected(handles, predicate, now)
+    }
+
+    /// Cancels the orders in `handles` that `predicate` selects, then keeps
+    /// `handles` as the book's scratch buffer.
+    fn cancel_selected<F>(
+        &mut self,
+        mut handles: Vec<OrderHandle>,
+        mut predicate: F,
+        now: DateTime<Utc>,
+    ) -> Vec<OrderRecord>
+    where
+        F: FnMut(&OrderRecord) -> bool,
+    {
+        let precision = self.precision;
+        handles.retain(|handle| {
+            self.orders.get_mut(*handle).is_some_and(|resting| {
+                resting.sync_remaining(precision);
+                predicate(&resting.order)
+            })
+        });
+        let cancelled = handles
+            .iter()
+            .filter_map(|handle| self.cancel(*handle, now))
+            .collect();
+        handles.clear();
+        self.handles = handles;
+        cancelled
+    }
+
+    pub fn snapshot(&self) -> BookSnapshot {
+        let level = |limit: &Limit| LevelSnapshot {
+            price: Price(limit.price.to_decimal(self.precision)),
+            volume: Quantity(limit.volume.to_decimal(self.precision)),
+            order_count: limit.len,
+        };
+        BookSnapshot {
+            bids: self.bids.values().rev().map(level).collect(),
+            asks: self.asks.values().map(level).collect(),
         }
     }
 }
 
+pub type OrderHandle = Handle;
+
+/// An order resting in the book, linked into its price level's FIFO queue.
+#[derive(Debug)]
+pub struct RestingOrder {
+    order: OrderRecord,
+    price: fixed::Price,
+    remaining: fixed::Quantity,
+    prev: Option<OrderHandle>,
+    next: Option<OrderHandle>,
+}
+
+impl RestingOrder {
+    fn new(
+        mut order: OrderRecord,
+        price: fixed::Price,
+        precision: Precision,
+    ) -> Result<RestingOrder, FixedPointError> {
+        let remaining = fixed::Quantity::from_decimal(order.remaining_size.0, precision)?;
+        order.price = Price(price.to_decimal(precision));
+        Ok(RestingOrder {
+            order,
+            price,
+            remaining,
+            prev: None,
+            next: None,
+        })
+    }
+
+    pub fn order(&self) -> &OrderRecord {
+        &self.order
+    }
+
+    pub fn is_filled(&self) -> bool {
+        self.remaining.is_zero()
+    }
+
+    /// Fills only count down the fixed-point `remaining`, so the record's
+    /// `remaining_size` is what the order rested with until it is synced.
+    fn sync_remaining(&mut self, precision: Precision) {
+        self.order.remaining_size = Quantity(self.remaining.to_decimal(precision));
+    }
+
+    /// Converts back to the boundary representation when the order leaves the book.
+    fn into_record(mut self, precision: Precision, now: DateTime<Utc>) -> OrderRecord {
+        self.sync_remaining(precision);
+        let mut order = self.order;
+        order.updated_at = now;
+        order
+    }
+}
+
+/// A price level: an intrusive doubly linked FIFO of orders stored in the book's slab.
 #[derive(Debug)]
 pub struct Limit {
-    price: Decimal,
-    orders: Vec<OrderRecord>,
+    price: fixed::Price,
+    head: Option<OrderHandle>,
+    tail: Option<OrderHandle>,
+    len: usize,
+    volume: fixed::Quantity,
 }
 
 impl Limit {
-    fn new(price: Decimal) -> Limit {
+    fn new(price: fixed::Price) -> Limit {
         Limit {
             price,
-            orders: Vec::new(),
+            head: None,
+            tail: None,
+            len: 0,
+            volume: fixed::Quantity::ZERO,
         }
     }
-    fn add_order(&mut self, order: OrderRecord) {
-        self.orders.push(order);
+
+    #[cfg(test)]
+    pub fn price(&self) -> fixed::Price {
+        self.price
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    fn total_volume(&self) -> Decimal {
-        self.orders.iter().map(|order| order.size).sum()
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
-    fn fill_order(&mut self, market_order: &mut OrderRecord) {
-        for limit_order in self.orders.iter_mut() {
-            match market_order.size >= limit_order.size {
-                true => {
-                    market_order.size -= limit_order.size;
-                    limit_order.size = dec!(0.0)
-                }
+    pub fn total_volume(&self) -> fixed::Quantity {
+        self.volume
+    }
 
-                false => {
-                    limit_order.size -= market_order.size;
-                    market_order.size = dec!(0.0)
-                }
+    /// Handles of the level's orders in time priority.
+    fn handles<'a>(
+        &self,
+        orders: &'a Slab<RestingOrder>,
+    ) -> impl Iterator<Item = OrderHandle> + 'a {
+        std::iter::successors(self.head, |handle| {
+            orders.get(*handle).and_then(|order| order.next)
+        })
+    }
+
+    /// Iterates the level's orders in time priority.
+    #[cfg(test)]
+    pub fn iter<'a>(&self, orders: &'a Slab<RestingOrder>) -> LimitIter<'a> {
+        LimitIter {
+            orders,
+            next: self.head,
+        }
+    }
+
+    fn push_back(
+        &mut self,
+        orders: &mut Slab<RestingOrder>,
+        handle: OrderHandle,
+    ) -> Result<(), FixedPointError> {
+        let remaining = orders.get(handle).map(|order| order.remaining);
+        self.volume = self
+            .volume
+            .checked_add(remaining.unwrap_or(fixed::Quantity::ZERO))?;
+
+        if let Some(node) = orders.get_mut(handle) {
+            node.prev = self.tail;
+            node.next = None;
+        }
+        match self.tail.and_then(|tail| orders.get_mut(tail)) {
+            Some(tail) => tail.next = Some(handle),
+            None => self.head = Some(handle),
+        }
+        self.tail = Some(handle);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn unlink(&mut self, orders: &mut Slab<RestingOrder>, handle: OrderHandle) {
+        let Some(node) = orders.get_mut(handle) else {
+            return;
+        };
+        let (prev, next, remaining) = (node.prev.take(), node.next.take(), node.remaining);
+
+        match prev.and_then(|prev| orders.get_mut(prev)) {
+            Some(prev) => prev.next = next,
+            None => self.head = next,
+        }
+        match next.and_then(|next| orders.get_mut(next)) {
+            Some(next) => next.prev = prev,
+            None => self.tail = prev,
+        }
+        // The level volume is the sum of its orders, so this cannot underflow.
+        self.volume = fixed::Quantity(self.volume.lots() - remaining.lots());
+        self.len -= 1;
+    }
+
+    /// Takes up to `quantity` from the order at the front of the level,
+    /// releasing it back to the slab once it is completely filled.
+    fn fill_head(
+        &mut self,
+        orders: &mut Slab<RestingOrder>,
+        order_index: &mut OrderIndex,
+        quantity: fixed::Quantity,
+    ) -> Result<Option<Fill>, FixedPointError> {
+        let Some(head) = self.head else {
+            return Ok(None);
+        };
+        let Some(limit_order) = orders.get_mut(head) else {
+            return Ok(None);
+        };
+        let fill = quantity.min(limit_order.remaining);
+        limit_order.remaining = limit_order.remaining.checked_sub(fill)?;
+        self.volume = self.volume.checked_sub(fill)?;
+        let (order_id, user_id) = (limit_order.order.id, limit_order.order.user_id);
+
+        if limit_order.is_filled() {
+            self.unlink(orders, head);
+            if let Some(filled) = orders.remove(head) {
+                order_index.remove(&filled.order);
             }
+        }
+        Ok(Some(Fill {
+            order_id,
+            user_id,
+            quantity: fill,
+        }))
+    }
 
-            if market_order.is_filled() {
+    fn fill_order(
+        &mut self,
+        orders: &mut Slab<RestingOrder>,
+        order_index: &mut OrderIndex,
+        remaining: &mut fixed::Quantity,
+        fills: &mut Vec<Fill>,
+    ) -> Result<(), FixedPointError> {
+        while !remaining.is_zero() {
+            let Some(fill) = self.fill_head(orders, order_index, *remaining)? else {
                 break;
+            };
+            *remaining = remaining.checked_sub(fill.quantity)?;
+            fills.push(fill);
+        }
+        Ok(())
+    }
+}
+
+/// Looks up resting orders by id or client order id, and counts them per user.
+#[derive(Debug, Default)]
+struct OrderIndex {
+    by_id: HashMap<Uuid, OrderHandle>,
+    /// Keyed by user first so a lookup can borrow the client order id.
+    by_client_id: HashMap<Uuid, HashMap<String, Uuid>>,
+    per_user: HashMap<Uuid, usize>,
+}
+
+impl OrderIndex {
+    fn with_capacity(capacity: usize) -> OrderIndex {
+        OrderIndex {
+            by_id: HashMap::with_capacity(capacity),
+            by_client_id: HashMap::new(),
+            per_user: HashMap::new(),
+        }
+    }
+
+    fn get(&self, order_id: Uuid) -> Option<OrderHandle> {
+        self.by_id.get(&order_id).copied()
+    }
+
+    fn insert(&mut self, order: &OrderRecord, handle: OrderHandle) {
+        self.by_id.insert(order.id, handle);
+        if let Some(client_order_id) = order.client_order_id.clone() {
+            self.by_client_id
+                .entry(order.user_id)
+                .or_default()
+                .insert(client_order_id, order.id);
+        }
+        *self.per_user.entry(order.user_id).or_default() += 1;
+    }
+
+    fn remove(&mut self, order: &OrderRecord) {
+        if self.by_id.remove(&order.id).is_none() {
+            return;
+        }
+        if let (Some(client_order_id), Some(ids)) = (
+            order.client_order_id.as_deref(),
+            self.by_client_id.get_mut(&order.user_id),
+        ) {
+            ids.remove(client_order_id);
+            if ids.is_empty() {
+                self.by_client_id.remove(&order.user_id);
+            }
+        }
+        if let Some(count) = self.per_user.get_mut(&order.user_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_user.remove(&order.user_id);
             }
         }
     }
+
+    fn open_orders_for(&self, user_id: Uuid) -> usize {
+        self.per_user.get(&user_id).copied().unwrap_or(0)
+    }
+
+    fn order_for_client_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Uuid> {
+        self.by_client_id
+            .get(&user_id)
+            .and_then(|ids| ids.get(client_order_id))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+pub struct LimitIter<'a> {
+    orders: &'a Slab<RestingOrder>,
+    next: Option<OrderHandle>,
+}
+
+#[cfg(test)]
+impl<'a> Iterator for LimitIter<'a> {
+    type Item = &'a RestingOrder;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let order = self.orders.get(self.next?)?;
+        self.next = order.next;
+        Some(order)
+    }
 }
 
 impl OrderRecord {
-    pub fn new(side: OrderSide, size: Decimal) -> OrderRecord {
+    pub fn new(side: OrderSide, size: Quantity) -> OrderRecord {
         OrderRecord {
             id: Uuid::new_v4(),
+            pair: None,
+            user_id: Uuid::nil(),
+            session_id: None,
+            client_order_id: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
             side,
             size,
-            price: dec!(100),
-            remaining_size: dec!(0.0),
+            price: Price(dec!(100)),
+            remaining_size: size,
             status: OrderStatus::New,
         }
     }
 
+    #[cfg(test)]
+    pub fn with_pair(mut self, pair: TradingPair) -> OrderRecord {
+        self.pair = Some(pair);
+        self
+    }
+
+    pub fn with_user(mut self, user_id: Uuid) -> OrderRecord {
+        self.user_id = user_id;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_session(mut self, session_id: Uuid) -> OrderRecord {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_client_order_id(mut self, client_order_id: impl Into<String>) -> OrderRecord {
+        self.client_order_id = Some(client_order_id.into());
+        self
+    }
+
+    #[cfg(test)]
     pub fn is_filled(&self) -> bool {
-        self.size == dec!(0.0)
+        self.remaining_size.is_zero()
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
 
+    fn price(value: Decimal) -> fixed::Price {
+        fixed::Price::from_decimal(value, Precision::default()).unwrap()
+    }
+
+    fn quantity(value: Decimal) -> fixed::Quantity {
+        fixed::Quantity::from_decimal(value, Precision::default()).unwrap()
+    }
+
+    fn resting(
+        orders: &mut Slab<RestingOrder>,
+        limit: &mut Limit,
+        side: OrderSide,
+        size: Decimal,
+    ) -> OrderHandle {
+        let order = RestingOrder::new(
+            OrderRecord::new(side, Quantity(size)),
+            limit.price,
+            Precision::default(),
+        )
+        .unwrap();
+        let handle = orders.insert(order);
+        limit.push_back(orders, handle).unwrap();
+        handle
+    }
+
     #[tokio::test]
     async fn orderbook_should_fill_market_buy_order_complete() {
         let mut orderbook = OrderBook::new();
-        orderbook.add_limit_order(dec!(300), OrderRecord::new(OrderSide::Ask, dec!(20.0)));
-        orderbook.add_limit_order(dec!(200), OrderRecord::new(OrderSide::Ask, dec!(30.0)));
-        orderbook.add_limit_order(dec!(100), OrderRecord::new(OrderSide::Ask, dec!(10.0)));
-        orderbook.add_limit_order(dec!(600), OrderRecord::new(OrderSide::Ask, dec!(40.0)));
-        orderbook.add_limit_order(dec!(400), OrderRecord::new(OrderSide::Ask, dec!(50.0)));
+        for (price, size) in [
+            (dec!(300), dec!(20.0)),
+            (dec!(200), dec!(30.0)),
+            (dec!(100), dec!(10.0)),
+            (dec!(600), dec!(40.0)),
+            (dec!(400), dec!(50.0)),
+        ] {
+            orderbook
+                .add_limit_order(
+                    Price(price),
+                    OrderRecord::new(OrderSide::Ask, Quantity(size)),
+                )
+                .unwrap();
+        }
 
-        let mut market_order = OrderRecord::new(OrderSide::Bid, dec!(10.0));
+        let mut market_order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(10.0)));
 
-        orderbook.fill_market_order(&mut market_order);
+        orderbook.fill_market_order(&mut market_order).unwrap();
 
-        let ask_limits = orderbook.ask_limits();
-        let matched_limit = ask_limits.get(0).unwrap();
-        assert_eq!(matched_limit.price, dec!(100.0));
-        assert_eq!(market_order.is_filled(), true);
+        assert!(market_order.is_filled());
+        // The 100 level was fully consumed and its only order left the book.
+        assert_eq!(orderbook.len(), 4);
+        assert_eq!(orderbook.best_ask().unwrap().price, price(dec!(200.0)));
+    }
 
-        let matched_order = matched_limit.orders.get(0).unwrap();
-        assert_eq!(matched_order.is_filled(), true);
+    #[tokio::test]
+    async fn add_limit_order_rejects_prices_finer_than_precision() {
+        let mut orderbook = OrderBook::with_precision(Precision::new(2, 2).unwrap());
+        let result = orderbook.add_limit_order(
+            Price(dec!(100.001)),
+            OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+        );
+        assert!(matches!(
+            result,
+            Err(OrderBookError::FixedPoint(
+                FixedPointError::PrecisionLoss { .. }
+            ))
+        ));
+        assert!(orderbook.is_empty());
     }
+
     #[tokio::test]
-    async fn total_volume() {
-        let price = dec!(99.99);
-        let mut limit = Limit::new(price);
-        assert_eq!(limit.total_volume(), dec!(0.0));
+    async fn duplicate_order_ids_are_rejected() {
+        let mut orderbook = OrderBook::new();
+        let user = Uuid::new_v4();
+        let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(user);
+        let first = orderbook
+            .add_limit_order(Price(dec!(100)), order.clone())
+            .unwrap();
 
-        let limit_buy_order_1 = OrderRecord::new(OrderSide::Bid, dec!(200.0));
-        let limit_buy_order_2 = OrderRecord::new(OrderSide::Bid, dec!(300.0));
+        assert_eq!(
+            orderbook
+                .add_limit_order(Price(dec!(99)), order.clone())
+                .unwrap_err(),
+            OrderBookError::DuplicateOrderId(order.id)
+        );
+        orderbook
+            .add_limit_order(
+                Price(dec!(101)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+            )
+            .unwrap();
+        // Rejected before matching, so the ask above is untouched.
+        assert_eq!(
+            orderbook
+                .submit_limit_order(Price(dec!(101)), order.clone())
+                .unwrap_err(),
+            OrderBookError::DuplicateOrderId(order.id)
+        );
+        assert_eq!(orderbook.len(), 2);
+        assert_eq!(orderbook.handle_of(order.id), Some(first));
+        assert_eq!(orderbook.open_orders_for(user), 1);
+    }
 
-        limit.add_order(limit_buy_order_1);
-        assert_eq!(limit.total_volume(), dec!(200.0));
+    #[tokio::test]
+    async fn total_volume() {
+        let mut orders = Slab::new();
+        let mut index = OrderIndex::default();
+        let mut limit = Limit::new(price(dec!(99.99)));
+        assert_eq!(limit.total_volume(), quantity(dec!(0.0)));
+
+        resting(&mut orders, &mut limit, OrderSide::Bid, dec!(200.0));
+        assert_eq!(limit.total_volume(), quantity(dec!(200.0)));
 
-        limit.add_order(limit_buy_order_2);
-        assert_eq!(limit.total_volume(), dec!(500.0));
+        resting(&mut orders, &mut limit, OrderSide::Bid, dec!(300.0));
+        assert_eq!(limit.total_volume(), quantity(dec!(500.0)));
 
-        let mut market_sell_order = OrderRecord::new(OrderSide::Ask, dec!(100.0));
+        let mut market_sell_order = quantity(dec!(100.0));
 
-        limit.fill_order(&mut market_sell_order);
+        limit
+            .fill_order(
+                &mut orders,
+                &mut index,
+                &mut market_sell_order,
+                &mut Vec::new(),
+            )
+            .unwrap();
 
-        assert_eq!(limit.total_volume(), dec!(400.0));
+        assert_eq!(limit.total_volume(), quantity(dec!(400.0)));
     }
 
     #[tokio::test]
     async fn fill_limit_order_single() {
-        let price = dec!(99.99);
-        let mut limit = Limit::new(price);
-        let limit_buy_order = OrderRecord::new(OrderSide::Bid, dec!(100.0));
-        limit.add_order(limit_buy_order);
+        let mut orders = Slab::new();
+        let mut index = OrderIndex::default();
+        let mut limit = Limit::new(price(dec!(99.99)));
+        resting(&mut orders, &mut limit, OrderSide::Bid, dec!(100.0));
 
-        let mut market_sell_order = OrderRecord::new(OrderSide::Ask, dec!(99.0));
+        let mut market_sell_order = quantity(dec!(99.0));
 
-        limit.fill_order(&mut market_sell_order);
+        limit
+            .fill_order(
+                &mut orders,
+                &mut index,
+                &mut market_sell_order,
+                &mut Vec::new(),
+            )
+            .unwrap();
 
-        assert_eq!(market_sell_order.is_filled(), true);
-        assert_eq!(limit.orders.get(0).unwrap().size, dec!(1.0));
+        assert!(market_sell_order.is_zero());
+        let first = limit.iter(&orders).next().unwrap();
+        assert_eq!(first.remaining, quantity(dec!(1.0)));
     }
 
     #[tokio::test]
     async fn fill_limit_order_multi() {
-        let price = dec!(99.99);
-        let mut limit = Limit::new(price);
-        let limit_buy_order_1 = OrderRecord::new(OrderSide::Bid, dec!(100.0));
-        let limit_buy_order_2 = OrderRecord::new(OrderSide::Bid, dec!(100.0));
-        limit.add_order(limit_buy_order_1);
-        limit.add_order(limit_buy_order_2);
+        let mut orders = Slab::new();
+        let mut index = OrderIndex::default();
+        let mut limit = Limit::new(price(dec!(99.99)));
+        let first = resting(&mut orders, &mut limit, OrderSide::Bid, dec!(100.0));
+        let second = resting(&mut orders, &mut limit, OrderSide::Bid, dec!(100.0));
 
-        let mut market_sell_order = OrderRecord::new(OrderSide::Ask, dec!(199.0));
+        let mut market_sell_order = quantity(dec!(199.0));
 
-        limit.fill_order(&mut market_sell_order);
+        limit
+            .fill_order(
+                &mut orders,
+                &mut index,
+                &mut market_sell_order,
+                &mut Vec::new(),
+            )
+            .unwrap();
 
-        assert_eq!(market_sell_order.is_filled(), true);
-        assert_eq!(limit.orders.get(0).unwrap().is_filled(), true);
-        assert_eq!(limit.orders.get(1).unwrap().is_filled(), false);
-        assert_eq!(limit.orders.get(1).unwrap().size, dec!(1.0));
+        assert!(market_sell_order.is_zero());
+        // Filled orders are released back to the slab.
+        assert!(!orders.contains(first));
+        assert_eq!(limit.len(), 1);
+        let remaining = orders.get(second).unwrap();
+        assert!(!remaining.is_filled());
+        assert_eq!(remaining.remaining, quantity(dec!(1.0)));
+    }
+
+    #[tokio::test]
+    async fn cancel_by_handle_unlinks_from_the_middle_of_a_level() {
+        let mut orderbook = OrderBook::new();
+        let handles = (0..3)
+            .map(|_| {
+                orderbook
+                    .add_limit_order(
+                        Price(dec!(100)),
+                        OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let cancelled = orderbook.cancel(handles[1], Utc::now()).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert_eq!(cancelled.remaining_size, Quantity(dec!(1)));
+        assert!(orderbook.cancel(handles[1], Utc::now()).is_none());
+
+        let orders = &orderbook.orders;
+        let level = orderbook.best_bid().unwrap();
+        let queue = level
+            .iter(orders)
+            .map(|order| order.order.id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            queue,
+            vec![
+                orders.get(handles[0]).unwrap().order.id,
+                orders.get(handles[2]).unwrap().order.id
+            ]
+        );
+        assert_eq!(level.total_volume(), quantity(dec!(2)));
+    }
+
+    #[tokio::test]
+    async fn cancelling_the_last_order_removes_the_level() {
+        let mut orderbook = OrderBook::new();
+        let order = OrderRecord::new(OrderSide::Ask, Quantity(dec!(3)));
+        let order_id = order.id;
+        orderbook.add_limit_order(Price(dec!(101)), order).unwrap();
+
+        assert!(orderbook.cancel_order(order_id, Utc::now()).is_some());
+        assert!(orderbook.best_ask().is_none());
+        assert!(orderbook.handle_of(order_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn amend_down_keeps_priority_and_reprice_loses_it() {
+        let mut orderbook = OrderBook::new();
+        let first = OrderRecord::new(OrderSide::Bid, Quantity(dec!(5)));
+        let second = OrderRecord::new(OrderSide::Bid, Quantity(dec!(5)));
+        let (first_id, second_id) = (first.id, second.id);
+        let first = orderbook.add_limit_order(Price(dec!(100)), first).unwrap();
+        orderbook.add_limit_order(Price(dec!(100)), second).unwrap();
+
+        let outcome = orderbook
+            .amend(first, Price(dec!(100)), Quantity(dec!(2)), Utc::now())
+            .unwrap();
+        assert_eq!(outcome.resting, Some(first));
+        let level = orderbook.best_bid().unwrap();
+        assert_eq!(level.total_volume(), quantity(dec!(7)));
+        let queue = level
+            .iter(&orderbook.orders)
+            .map(|resting| resting.order().id)
+            .collect::<Vec<_>>();
+        assert_eq!(queue, vec![first_id, second_id]);
+
+        // Growing the order sends it to the back of the queue.
+        orderbook
+            .amend(first, Price(dec!(100)), Quantity(dec!(3)), Utc::now())
+            .unwrap();
+        let queue = orderbook
+            .best_bid()
+            .unwrap()
+            .iter(&orderbook.orders)
+            .map(|resting| resting.order().id)
+            .collect::<Vec<_>>();
+        assert_eq!(queue, vec![second_id, first_id]);
+    }
+
+    #[tokio::test]
+    async fn steady_state_reuses_order_slots() {
+        let mut orderbook = OrderBook::with_capacity(Precision::default(), 4);
+        for _ in 0..100 {
+            let handle = orderbook
+                .add_limit_order(
+                    Price(dec!(100)),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                )
+                .unwrap();
+            orderbook.cancel(handle, Utc::now()).unwrap();
+        }
+        assert_eq!(orderbook.orders.capacity(), 4);
+    }
+
+    #[tokio::test]
+    async fn recycled_trade_buffers_are_reused() {
+        let mut orderbook = OrderBook::new();
+        let mut buffer = None;
+        for _ in 0..10 {
+            orderbook
+                .add_limit_order(
+                    Price(dec!(100)),
+                    OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))),
+                )
+                .unwrap();
+            let outcome = orderbook
+                .submit_limit_order(
+                    Price(dec!(100)),
+                    OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+                )
+                .unwrap();
+            assert_eq!(outcome.trades.len(), 1);
+            let pointer = outcome.trades.as_ptr();
+            assert_eq!(*buffer.get_or_insert(pointer), pointer);
+            orderbook.recycle_trades(outcome.trades);
+        }
+        assert!(orderbook.is_empty());
+    }
+
+    #[tokio::test]
+    async fn crossing_limit_order_trades_at_resting_prices_and_rests_remainder() {
+        let mut orderbook = OrderBook::new();
+        let ask_1 = OrderRecord::new(OrderSide::Ask, Quantity(dec!(2)));
+        let ask_2 = OrderRecord::new(OrderSide::Ask, Quantity(dec!(2)));
+        let ask_1_id = ask_1.id;
+        orderbook.add_limit_order(Price(dec!(100)), ask_1).unwrap();
+        orderbook.add_limit_order(Price(dec!(102)), ask_2).unwrap();
+
+        let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(5)));
+        let bid_id = bid.id;
+        let outcome = orderbook.submit_limit_order(Price(dec!(101)), bid).unwrap();
+
+        assert_eq!(outcome.trades.len(), 1);
+        let trade = &outcome.trades[0];
+        assert_eq!(trade.price, Price(dec!(100)));
+        assert_eq!(trade.quantity, Quantity(dec!(2)));
+        assert_eq!(trade.buy_order_id, bid_id);
+        assert_eq!(trade.maker_order_id(), Some(ask_1_id));
+        let resting = orderbook.orders.get(outcome.resting.unwrap()).unwrap();
+        assert_eq!(resting.remaining, quantity(dec!(3)));
+        assert_eq!(orderbook.best_bid().unwrap().price(), price(dec!(101)));
+        assert_eq!(orderbook.best_ask().unwrap().price(), price(dec!(102)));
+    }
+
+    #[tokio::test]
+    async fn auction_accumulates_then_uncrosses_at_a_single_price() {
+        let mut orderbook = OrderBook::new();
+        orderbook.start_auction();
+
+        for (side, price, size) in [
+            (OrderSide::Bid, dec!(102), dec!(10)),
+            (OrderSide::Bid, dec!(101), dec!(10)),
+            (OrderSide::Ask, dec!(99), dec!(5)),
+            (OrderSide::Ask, dec!(100), dec!(10)),
+            (OrderSide::Ask, dec!(103), dec!(10)),
+        ] {
+            let outcome = orderbook
+                .submit_limit_order(Price(price), OrderRecord::new(side, Quantity(size)))
+                .unwrap();
+            assert!(outcome.trades.is_empty());
+        }
+        assert_eq!(orderbook.len(), 5);
+
+        let indication = orderbook.indicative_auction().unwrap().unwrap();
+        assert_eq!(indication.volume, Quantity(dec!(15)));
+        assert_eq!(indication.imbalance, Quantity(dec!(5)));
+        assert_eq!(indication.imbalance_side, Some(OrderSide::Bid));
+
+        let trades = orderbook.uncross().unwrap();
+        assert_eq!(orderbook.mode(), BookMode::Continuous);
+        assert!(trades.iter().all(|trade| trade.price == indication.price));
+        assert!(trades.iter().all(|trade| trade.aggressor.is_none()));
+        let executed: Quantity = trades.iter().map(|trade| trade.quantity).sum();
+        assert_eq!(executed, Quantity(dec!(15)));
+
+        // 5 of the bids stay, along with the ask that never crossed.
+        assert_eq!(
+            orderbook.best_bid().unwrap().total_volume(),
+            quantity(dec!(5))
+        );
+        assert_eq!(orderbook.best_ask().unwrap().price(), price(dec!(103)));
+        assert_eq!(orderbook.last_trade_price(), Some(indication.price));
+    }
+
+    #[tokio::test]
+    async fn market_orders_are_rejected_during_an_auction() {
+        let mut orderbook = OrderBook::new();
+        orderbook.start_auction();
+        let mut market_order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)));
+        assert_eq!(
+            orderbook.fill_market_order(&mut market_order),
+            Err(OrderBookError::MarketOrderDuringAuction)
+        );
     }
 }
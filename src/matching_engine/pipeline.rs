@@ -0,0 +1,466 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::errors::engine_error::EngineError;
+
+use super::engine::{ArchivedMarket, MassCancelFilter, MatchingEngine, OrderRef, TradingPair};
+use super::events::{EngineEvent, SequencedEvent};
+use super::market_state::TradingState;
+use super::ring_buffer::{RingBuffer, WaitStrategy};
+use super::session::{SessionConfig, SessionKind};
+use super::ticker::TickerStats;
+use super::types::decimal::{Price, Quantity};
+use super::types::order::OrderRecord;
+
+/// A request from a gateway to the matching thread.
+#[derive(Debug)]
+pub enum EngineCommand {
+    AddMarket {
+        pair: TradingPair,
+    },
+    DelistMarket {
+        pair: TradingPair,
+        reason: String,
+    },
+    /// Delists the market if it is not already, then drops it.
+    RemoveMarket {
+        pair: TradingPair,
+        reason: String,
+    },
+    PlaceLimitOrder {
+        pair: TradingPair,
+        price: Price,
+        order: OrderRecord,
+    },
+    PlaceMarketOrder {
+        pair: TradingPair,
+        order: OrderRecord,
+    },
+    /// Cancels are charged to the requesting user and session.
+    CancelOrder {
+        pair: TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+    },
+    CancelByClientOrderId {
+        pair: TradingPair,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        client_order_id: String,
+    },
+    /// Amends are charged to the requesting user and session, like cancels.
+    AmendOrder {
+        pair: TradingPair,
+        order: OrderRef,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        price: Option<Price>,
+        remaining: Option<Quantity>,
+    },
+    MassCancel {
+        filter: MassCancelFilter,
+    },
+    /// Funds arriving from outside the exchange. `transfer_id` identifies
+    /// the transfer in the journal.
+    Deposit {
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    },
+    Withdraw {
+        transfer_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: Decimal,
+    },
+    SetTradingState {
+        pair: TradingPair,
+        state: TradingState,
+    },
+    OpenSession {
+        session_id: Uuid,
+        user_id: Uuid,
+        kind: SessionKind,
+        config: SessionConfig,
+        now: DateTime<Utc>,
+    },
+    Heartbeat {
+        session_id: Uuid,
+        now: DateTime<Utc>,
+    },
+    DisconnectSession {
+        session_id: Uuid,
+        now: DateTime<Utc>,
+    },
+    /// Reads the market's rolling 24-hour statistics; changes nothing.
+    Ticker {
+        pair: TradingPair,
+    },
+    /// Published by a timer so the engine can end timed auctions and expire
+    /// lost sessions.
+    Tick {
+        now: DateTime<Utc>,
+    },
+    Shutdown,
+}
+
+/// What a command hands back besides its events.
+#[derive(Debug)]
+pub enum CommandOutput {
+    Done,
+    /// The final state of a market that was delisted or removed.
+    Archived(ArchivedMarket),
+    Ticker(Option<TickerStats>),
+}
+
+/// What the engine made of a command submitted with
+/// `CommandPublisher::try_submit`. Dropped unanswered if the engine stops
+/// first.
+pub type Reply = oneshot::Receiver<Result<CommandOutput, EngineError>>;
+
+/// A command and the time it was published, which the engine runs it at.
+struct StampedCommand {
+    issued_at: DateTime<Utc>,
+    command: EngineCommand,
+    /// Where the outcome goes when the publisher waits for one.
+    reply: Option<oneshot::Sender<Result<CommandOutput, EngineError>>>,
+}
+
+/// Gateway-side handle for publishing commands. Cheap to clone, one per gateway.
+#[derive(Clone)]
+pub struct CommandPublisher<W: WaitStrategy> {
+    ring: Arc<RingBuffer<StampedCommand>>,
+    wait: W,
+}
+
+impl<W: WaitStrategy> CommandPublisher<W> {
+    /// Publishes `command`, waiting while the inbound ring is full.
+    pub fn publish(&self, command: EngineCommand) {
+        self.ring.push(Self::stamp(command, None), &self.wait);
+    }
+
+    /// Publishes `command` and returns where its outcome will arrive, or
+    /// hands the command back if the inbound ring is full.
+    #[allow(clippy::result_large_err)]
+    pub fn try_submit(&self, command: EngineCommand) -> Result<Reply, EngineCommand> {
+        let (reply, outcome) = oneshot::channel();
+        self.ring
+            .try_push(Self::stamp(command, Some(reply)))
+            .map(|()| outcome)
+            .map_err(|stamped| stamped.command)
+    }
+
+    /// The gateway's clock is the only one read on the way in; the engine
+    /// works from the stamp, so replaying commands replays their timing.
+    fn stamp(
+        command: EngineCommand,
+        reply: Option<oneshot::Sender<Result<CommandOutput, EngineError>>>,
+    ) -> StampedCommand {
+        StampedCommand {
+            issued_at: Utc::now(),
+            command,
+            reply,
+        }
+    }
+}
+
+/// Consumer-side handle for the outbound event ring.
+#[derive(Clone)]
+pub struct EventSubscriber<W: WaitStrategy> {
+    ring: Arc<RingBuffer<SequencedEvent>>,
+    wait: W,
+}
+
+impl<W: WaitStrategy> EventSubscriber<W> {
+    /// Takes the next event, waiting while the outbound ring is empty.
+    pub fn next(&self) -> SequencedEvent {
+        self.ring.pop(&self.wait)
+    }
+
+    #[cfg(test)]
+    pub fn try_next(&self) -> Option<SequencedEvent> {
+        self.ring.try_pop()
+    }
+}
+
+/// Owns the `MatchingEngine` and drives it from the inbound ring.
+pub struct EngineRunner<W: WaitStrategy> {
+    engine: MatchingEngine,
+    commands: Arc<RingBuffer<StampedCommand>>,
+    events: Arc<RingBuffer<SequencedEvent>>,
+    wait: W,
+}
+
+/// Wires `engine` between a pre-allocated command ring and event ring.
+///
+/// Both capacities are rounded up to the next power of two. The same wait
+/// strategy is used on every side of both rings.
+pub fn pipeline<W: WaitStrategy>(
+    engine: MatchingEngine,
+    command_capacity: usize,
+    event_capacity: usize,
+    wait: W,
+) -> (CommandPublisher<W>, EventSubscriber<W>, EngineRunner<W>) {
+    let commands = Arc::new(RingBuffer::new(command_capacity));
+    let events = Arc::new(RingBuffer::new(event_capacity));
+
+    let publisher = CommandPublisher {
+        ring: commands.clone(),
+        wait: wait.clone(),
+    };
+    let subscriber = EventSubscriber {
+        ring: events.clone(),
+        wait: wait.clone(),
+    };
+    let runner = EngineRunner {
+        engine,
+        commands,
+        events,
+        wait,
+    };
+
+    (publisher, subscriber, runner)
+}
+
+impl<W: WaitStrategy> EngineRunner<W> {
+    /// Processes commands until `EngineCommand::Shutdown`, then returns the engine.
+    pub fn run(mut self) -> MatchingEngine {
+        loop {
+            let stamped = self.commands.pop(&self.wait);
+            if let EngineCommand::Shutdown = stamped.command {
+                return self.engine;
+            }
+            self.engine.set_clock(stamped.issued_at);
+            let outcome = self.process(stamped.command);
+            if let Some(reply) = stamped.reply {
+                // The publisher may have hung up; the events still stand.
+                let _ = reply.send(outcome);
+            }
+        }
+    }
+
+    /// Applies a single command at the engine's current time, publishes
+    /// every event it produced and returns what the command produced or why
+    /// it was rejected.
+    pub fn process(&mut self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        let result = self.apply(command);
+        if let Err(reason) = &result {
+            self.engine.emit(EngineEvent::CommandRejected {
+                reason: reason.clone(),
+            });
+        }
+
+        for event in self.engine.drain_events() {
+            self.events.push(event, &self.wait);
+        }
+        result
+    }
+
+    fn apply(&mut self, command: EngineCommand) -> Result<CommandOutput, EngineError> {
+        match command {
+            EngineCommand::AddMarket { pair } => {
+                self.engine.add_market(pair).map(|_| CommandOutput::Done)
+            }
+            EngineCommand::DelistMarket { pair, reason } => self
+                .engine
+                .delist_market(pair, reason)
+                .map(|archive| CommandOutput::Archived(archive.clone())),
+            EngineCommand::RemoveMarket { pair, reason } => self
+                .engine
+                .remove_market(pair, reason)
+                .map(CommandOutput::Archived),
+            EngineCommand::PlaceLimitOrder { pair, price, order } => self
+                .engine
+                .place_limit_order(pair, price, order)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::PlaceMarketOrder { pair, order } => self
+                .engine
+                .place_market_order(pair, order)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::CancelOrder {
+                pair,
+                order_id,
+                user_id,
+                session_id,
+            } => self
+                .engine
+                .cancel_order(pair, order_id, user_id, session_id)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::CancelByClientOrderId {
+                pair,
+                user_id,
+                session_id,
+                client_order_id,
+            } => self
+                .engine
+                .cancel_by_client_order_id(pair, user_id, session_id, client_order_id)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::AmendOrder {
+                pair,
+                order,
+                user_id,
+                session_id,
+                price,
+                remaining,
+            } => self
+                .engine
+                .amend_order(pair, order, user_id, session_id, price, remaining)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::MassCancel { filter } => {
+                self.engine.mass_cancel(filter).map(|_| CommandOutput::Done)
+            }
+            EngineCommand::Deposit {
+                transfer_id,
+                user_id,
+                asset,
+                amount,
+            } => self
+                .engine
+                .deposit(transfer_id, user_id, asset, amount)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::Withdraw {
+                transfer_id,
+                user_id,
+                asset,
+                amount,
+            } => self
+                .engine
+                .withdraw(transfer_id, user_id, asset, amount)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::SetTradingState { pair, state } => self
+                .engine
+                .set_trading_state(pair, state)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::OpenSession {
+                session_id,
+                user_id,
+                kind,
+                config,
+                now,
+            } => {
+                self.engine
+                    .open_session(session_id, user_id, kind, config, now);
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::Heartbeat { session_id, now } => self
+                .engine
+                .heartbeat(session_id, now)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::DisconnectSession { session_id, now } => self
+                .engine
+                .disconnect_session(session_id, now)
+                .map(|_| CommandOutput::Done),
+            EngineCommand::Ticker { pair } => match self.engine.market(&pair) {
+                Some(market) => Ok(CommandOutput::Ticker(market.ticker())),
+                None => Err(EngineError::MarketNotFound(pair)),
+            },
+            EngineCommand::Tick { now } => {
+                for reason in self.engine.tick(now) {
+                    self.engine.emit(EngineEvent::CommandRejected { reason });
+                }
+                Ok(CommandOutput::Done)
+            }
+            EngineCommand::Shutdown => Ok(CommandOutput::Done),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::ring_buffer::{BusySpinWait, YieldingWait};
+    use crate::matching_engine::types::decimal::Quantity;
+    use crate::matching_engine::types::fixed::Precision;
+    use crate::matching_engine::types::order::OrderSide;
+    use rust_decimal_macros::dec;
+    use std::thread;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[tokio::test]
+    async fn runner_publishes_sequenced_events() {
+        let (publisher, subscriber, runner) =
+            pipeline(MatchingEngine::new(), 16, 16, YieldingWait::default());
+        let handle = thread::spawn(move || runner.run());
+
+        let published_from = Utc::now();
+        publisher.publish(EngineCommand::AddMarket { pair: btc_usd() });
+        publisher.publish(EngineCommand::PlaceLimitOrder {
+            pair: btc_usd(),
+            price: Price(dec!(100)),
+            order: OrderRecord::new(OrderSide::Ask, Quantity(dec!(5))),
+        });
+        publisher.publish(EngineCommand::PlaceMarketOrder {
+            pair: btc_usd(),
+            order: OrderRecord::new(OrderSide::Bid, Quantity(dec!(2))),
+        });
+        let published_to = Utc::now();
+        publisher.publish(EngineCommand::Shutdown);
+
+        let added = subscriber.next();
+        assert_eq!(added.sequence, 1);
+        assert_eq!(
+            added.event,
+            EngineEvent::MarketAdded {
+                pair: btc_usd(),
+                precision: Precision::default(),
+                state: TradingState::Continuous,
+            }
+        );
+
+        let accepted = subscriber.next();
+        assert_eq!(accepted.sequence, 2);
+        assert!(matches!(accepted.event, EngineEvent::OrderAccepted { .. }));
+
+        // Trades carry the time their command was published.
+        let trade = subscriber.next();
+        assert_eq!(trade.sequence, 3);
+        match trade.event {
+            EngineEvent::Trade { trade, .. } => {
+                assert!((published_from..=published_to).contains(&trade.executed_at));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let filled = subscriber.next();
+        assert_eq!(filled.sequence, 4);
+        match filled.event {
+            EngineEvent::MarketOrderFilled {
+                filled, remaining, ..
+            } => {
+                assert_eq!(filled, Quantity(dec!(2)));
+                assert_eq!(remaining, Quantity::ZERO);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let engine = handle.join().unwrap();
+        assert_eq!(engine.sequence(), 4);
+    }
+
+    #[tokio::test]
+    async fn unknown_market_is_rejected_on_the_event_ring() {
+        let (_publisher, subscriber, mut runner) =
+            pipeline(MatchingEngine::new(), 4, 4, BusySpinWait);
+
+        let outcome = runner.process(EngineCommand::PlaceMarketOrder {
+            pair: btc_usd(),
+            order: OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))),
+        });
+        assert!(matches!(outcome, Err(EngineError::MarketNotFound(pair)) if pair == btc_usd()));
+
+        let rejected = subscriber.try_next().unwrap();
+        assert!(matches!(
+            rejected.event,
+            EngineEvent::CommandRejected { .. }
+        ));
+        assert!(subscriber.try_next().is_none());
+    }
+}
@@ -0,0 +1,409 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// Requests that draw from a rate limit. Each has its own bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitedAction {
+    Place,
+    Cancel,
+    Amend,
+}
+
+impl fmt::Display for RateLimitedAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RateLimitedAction::Place => "place",
+            RateLimitedAction::Cancel => "cancel",
+            RateLimitedAction::Amend => "amend",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Who a bucket belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    User(Uuid),
+    Session(Uuid),
+}
+
+impl fmt::Display for RateLimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimitKey::User(id) => write!(f, "user {}", id),
+            RateLimitKey::Session(id) => write!(f, "session {}", id),
+        }
+    }
+}
+
+/// Bursts of up to `burst` requests, refilled at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct ActionLimits {
+    pub place: RateLimit,
+    pub cancel: RateLimit,
+    pub amend: RateLimit,
+}
+
+impl ActionLimits {
+    fn get(&self, action: RateLimitedAction) -> RateLimit {
+        match action {
+            RateLimitedAction::Place => self.place,
+            RateLimitedAction::Cancel => self.cancel,
+            RateLimitedAction::Amend => self.amend,
+        }
+    }
+}
+
+/// Limits for one tier, applied to each user and separately to each of
+/// their sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct TierLimits {
+    pub per_user: ActionLimits,
+    pub per_session: ActionLimits,
+}
+
+impl Default for TierLimits {
+    fn default() -> Self {
+        let limits = |burst, per_second| ActionLimits {
+            place: RateLimit { burst, per_second },
+            cancel: RateLimit {
+                burst: burst * 2,
+                per_second: per_second * 2,
+            },
+            amend: RateLimit { burst, per_second },
+        };
+        TierLimits {
+            per_user: limits(50, 20),
+            per_session: limits(20, 10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+pub enum UserTier {
+    #[default]
+    Standard,
+    Professional,
+    MarketMaker,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimited {
+    pub key: RateLimitKey,
+    pub action: RateLimitedAction,
+    /// How long until the request would be accepted.
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is over its {} rate limit; retry after {} ms",
+            self.key,
+            self.action,
+            self.retry_after.num_milliseconds()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: DateTime<Utc>,
+    /// When the bucket will have refilled to its burst if left alone.
+    full_at: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: DateTime<Utc>) -> TokenBucket {
+        TokenBucket {
+            tokens: f64::from(limit.burst),
+            refilled_at: now,
+            full_at: now,
+        }
+    }
+
+    fn take(&mut self, limit: RateLimit) {
+        self.tokens -= 1.0;
+        let missing = f64::from(limit.burst) - self.tokens;
+        self.full_at = match limit.per_second {
+            0 => DateTime::<Utc>::MAX_UTC,
+            per_second => {
+                let seconds = missing / f64::from(per_second);
+                self.refilled_at + Duration::microseconds((seconds * 1e6).ceil() as i64)
+            }
+        };
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: DateTime<Utc>) {
+        let elapsed = (now - self.refilled_at)
+            .num_microseconds()
+            .unwrap_or(i64::MAX) as f64;
+        if elapsed > 0.0 {
+            self.tokens = (self.tokens + elapsed / 1e6 * f64::from(limit.per_second))
+                .min(f64::from(limit.burst));
+            self.refilled_at = now;
+        }
+    }
+
+    /// Time until a whole token is available, or `None` if one already is.
+    fn wait_for_token(&self, limit: RateLimit) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        if limit.per_second == 0 {
+            return Some(Duration::MAX);
+        }
+        let seconds = (1.0 - self.tokens) / f64::from(limit.per_second);
+        Some(Duration::microseconds((seconds * 1e6).ceil() as i64))
+    }
+}
+
+/// Token-bucket limits on order entry, keyed by user and by session.
+///
+/// A request must find a token in both its user's and its session's bucket;
+/// neither is drawn from unless both have one.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    tiers: HashMap<UserTier, TierLimits>,
+    user_tiers: HashMap<Uuid, UserTier>,
+    buckets: HashMap<(RateLimitKey, RateLimitedAction), TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    /// Tiers without explicit limits use `TierLimits::default()`.
+    pub fn set_tier_limits(&mut self, tier: UserTier, limits: TierLimits) {
+        self.tiers.insert(tier, limits);
+    }
+
+    pub fn set_user_tier(&mut self, user_id: Uuid, tier: UserTier) {
+        self.user_tiers.insert(user_id, tier);
+    }
+
+    pub fn user_tier(&self, user_id: Uuid) -> UserTier {
+        self.user_tiers.get(&user_id).copied().unwrap_or_default()
+    }
+
+    fn limits_for(&self, user_id: Uuid) -> TierLimits {
+        self.tiers
+            .get(&self.user_tier(user_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Takes a token for `action` from the user's bucket and, when the request
+    /// came through a session, from the session's bucket.
+    pub fn check(
+        &mut self,
+        action: RateLimitedAction,
+        user_id: Uuid,
+        session_id: Option<Uuid>,
+        now: DateTime<Utc>,
+    ) -> Result<(), RateLimited> {
+        let limits = self.limits_for(user_id);
+        let mut keys = vec![(RateLimitKey::User(user_id), limits.per_user.get(action))];
+        if let Some(session_id) = session_id {
+            keys.push((
+                RateLimitKey::Session(session_id),
+                limits.per_session.get(action),
+            ));
+        }
+
+        for (key, limit) in keys.iter() {
+            let bucket = self
+                .buckets
+                .entry((*key, action))
+                .or_insert_with(|| TokenBucket::full(*limit, now));
+            bucket.refill(*limit, now);
+            if let Some(retry_after) = bucket.wait_for_token(*limit) {
+                return Err(RateLimited {
+                    key: *key,
+                    action,
+                    retry_after,
+                });
+            }
+        }
+        for (key, limit) in keys {
+            if let Some(bucket) = self.buckets.get_mut(&(key, action)) {
+                bucket.take(limit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops buckets that have refilled completely. A new bucket starts full,
+    /// so this forgets nothing, and keeps idle users from piling up.
+    pub fn expire_idle(&mut self, now: DateTime<Utc>) {
+        self.buckets.retain(|_, bucket| bucket.full_at > now);
+    }
+
+    /// Drops the buckets of a session that has gone away.
+    pub fn forget_session(&mut self, session_id: Uuid) {
+        self.buckets
+            .retain(|(key, _), _| *key != RateLimitKey::Session(session_id));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn limits(burst: u32, per_second: u32) -> ActionLimits {
+        let limit = RateLimit { burst, per_second };
+        ActionLimits {
+            place: limit,
+            cancel: limit,
+            amend: limit,
+        }
+    }
+
+    #[tokio::test]
+    async fn bursts_then_refills_with_a_retry_hint() {
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits(2, 4),
+                per_session: limits(10, 10),
+            },
+        );
+        let user = Uuid::new_v4();
+        let now = Utc::now();
+
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, None, now)
+            .is_ok());
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, None, now)
+            .is_ok());
+        let rejected = limiter
+            .check(RateLimitedAction::Place, user, None, now)
+            .unwrap_err();
+        assert_eq!(rejected.key, RateLimitKey::User(user));
+        assert_eq!(rejected.retry_after, Duration::milliseconds(250));
+
+        // Cancels have their own bucket.
+        assert!(limiter
+            .check(RateLimitedAction::Cancel, user, None, now)
+            .is_ok());
+        let later = now + Duration::milliseconds(250);
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, None, later)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn sessions_are_limited_separately_from_their_user() {
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits(3, 1),
+                per_session: limits(1, 1),
+            },
+        );
+        let (user, first, second) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let now = Utc::now();
+
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, Some(first), now)
+            .is_ok());
+        let rejected = limiter
+            .check(RateLimitedAction::Place, user, Some(first), now)
+            .unwrap_err();
+        assert_eq!(rejected.key, RateLimitKey::Session(first));
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, Some(second), now)
+            .is_ok());
+        // The session rejection did not spend a user token.
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, None, now)
+            .is_ok());
+        assert!(limiter
+            .check(RateLimitedAction::Place, user, None, now)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn tiers_get_their_own_limits() {
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits(1, 1),
+                per_session: limits(1, 1),
+            },
+        );
+        limiter.set_tier_limits(
+            UserTier::MarketMaker,
+            TierLimits {
+                per_user: limits(100, 100),
+                per_session: limits(100, 100),
+            },
+        );
+        let (retail, maker) = (Uuid::new_v4(), Uuid::new_v4());
+        limiter.set_user_tier(maker, UserTier::MarketMaker);
+        let now = Utc::now();
+
+        for _ in 0..10 {
+            assert!(limiter
+                .check(RateLimitedAction::Place, maker, None, now)
+                .is_ok());
+        }
+        assert!(limiter
+            .check(RateLimitedAction::Place, retail, None, now)
+            .is_ok());
+        assert!(limiter
+            .check(RateLimitedAction::Place, retail, None, now)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn idle_buckets_are_dropped_once_full() {
+        let mut limiter = RateLimiter::new();
+        limiter.set_tier_limits(
+            UserTier::Standard,
+            TierLimits {
+                per_user: limits(2, 1),
+                per_session: limits(2, 1),
+            },
+        );
+        let (busy, idle) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = Utc::now();
+        for user in [busy, idle] {
+            limiter
+                .check(RateLimitedAction::Place, user, None, now)
+                .unwrap();
+        }
+        limiter
+            .check(
+                RateLimitedAction::Place,
+                busy,
+                None,
+                now + Duration::milliseconds(500),
+            )
+            .unwrap();
+
+        limiter.expire_idle(now + Duration::seconds(1));
+        assert_eq!(limiter.buckets.len(), 1);
+        assert!(limiter
+            .buckets
+            .contains_key(&(RateLimitKey::User(busy), RateLimitedAction::Place)));
+        limiter.expire_idle(now + Duration::seconds(3));
+        assert!(limiter.buckets.is_empty());
+    }
+}
@@ -0,0 +1,304 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Decides what a producer or consumer does while the ring is full or empty.
+///
+/// `attempt` counts the consecutive failed attempts, starting at zero, so a
+/// strategy can escalate from spinning to yielding or parking.
+pub trait WaitStrategy: Clone + Send {
+    fn wait(&self, attempt: u32);
+}
+
+/// Spins on the CPU. Lowest latency, burns a full core.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BusySpinWait;
+
+impl WaitStrategy for BusySpinWait {
+    fn wait(&self, _attempt: u32) {
+        std::hint::spin_loop();
+    }
+}
+
+/// Spins for a number of attempts, then yields the thread to the scheduler.
+#[derive(Debug, Clone, Copy)]
+pub struct YieldingWait {
+    pub spin_tries: u32,
+}
+
+impl Default for YieldingWait {
+    fn default() -> Self {
+        YieldingWait { spin_tries: 100 }
+    }
+}
+
+impl WaitStrategy for YieldingWait {
+    fn wait(&self, attempt: u32) {
+        if attempt < self.spin_tries {
+            std::hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Spins, then yields, then parks the thread for `park_for` at a time.
+///
+/// Nothing unparks a waiting thread, so once it parks it sleeps in
+/// `park_for` slices until the ring has room or an entry.
+#[derive(Debug, Clone, Copy)]
+pub struct ParkingWait {
+    pub spin_tries: u32,
+    pub yield_tries: u32,
+    pub park_for: Duration,
+}
+
+impl Default for ParkingWait {
+    fn default() -> Self {
+        ParkingWait {
+            spin_tries: 100,
+            yield_tries: 100,
+            park_for: Duration::from_micros(50),
+        }
+    }
+}
+
+impl WaitStrategy for ParkingWait {
+    fn wait(&self, attempt: u32) {
+        if attempt < self.spin_tries {
+            std::hint::spin_loop();
+        } else if attempt < self.spin_tries + self.yield_tries {
+            thread::yield_now();
+        } else {
+            thread::park_timeout(self.park_for);
+        }
+    }
+}
+
+/// Keeps the producer and consumer cursors on separate cache lines.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded, pre-allocated, lock-free multi-producer multi-consumer ring.
+///
+/// Every slot is allocated up front and carries its own sequence number, so
+/// publishing and consuming only ever touch atomics and never allocate.
+pub struct RingBuffer<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send> Send for RingBuffer<T> {}
+unsafe impl<T: Send> Sync for RingBuffer<T> {}
+
+impl<T> RingBuffer<T> {
+    /// Creates a ring with room for `capacity` entries, rounded up to the next
+    /// power of two.
+    pub fn new(capacity: usize) -> RingBuffer<T> {
+        let capacity = capacity.max(2).next_power_of_two();
+        let slots = (0..capacity)
+            .map(|i| Slot {
+                sequence: AtomicUsize::new(i),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+
+        RingBuffer {
+            slots,
+            mask: capacity - 1,
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Number of entries waiting to be consumed. Only a snapshot under
+    /// concurrent use.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail)
+    }
+
+    /// Publishes `value`, handing it back if the ring is full.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence as isize - pos as isize;
+
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(pos.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return Err(value);
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Takes the oldest entry, if any.
+    pub fn try_pop(&self) -> Option<T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence as isize - pos.wrapping_add(1) as isize;
+
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.sequence
+                            .store(pos.wrapping_add(self.mask + 1), Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Publishes `value`, waiting with `wait` while the ring is full.
+    pub fn push<W: WaitStrategy>(&self, mut value: T, wait: &W) {
+        let mut attempt = 0;
+        loop {
+            match self.try_push(value) {
+                Ok(()) => return,
+                Err(rejected) => {
+                    value = rejected;
+                    wait.wait(attempt);
+                    attempt = attempt.saturating_add(1);
+                }
+            }
+        }
+    }
+
+    /// Takes the oldest entry, waiting with `wait` while the ring is empty.
+    pub fn pop<W: WaitStrategy>(&self, wait: &W) -> T {
+        let mut attempt = 0;
+        loop {
+            if let Some(value) = self.try_pop() {
+                return value;
+            }
+            wait.wait(attempt);
+            attempt = attempt.saturating_add(1);
+        }
+    }
+}
+
+impl<T> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn capacity_is_rounded_up_to_power_of_two() {
+        let ring: RingBuffer<u64> = RingBuffer::new(5);
+        assert_eq!(ring.capacity(), 8);
+    }
+
+    #[tokio::test]
+    async fn push_and_pop_in_fifo_order() {
+        let ring = RingBuffer::new(4);
+        assert!(ring.try_push(1).is_ok());
+        assert!(ring.try_push(2).is_ok());
+        assert!(ring.try_push(3).is_ok());
+        assert_eq!(ring.len(), 3);
+
+        assert_eq!(ring.try_pop(), Some(1));
+        assert_eq!(ring.try_pop(), Some(2));
+        assert_eq!(ring.try_pop(), Some(3));
+        assert_eq!(ring.try_pop(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_push_when_full() {
+        let ring = RingBuffer::new(2);
+        assert!(ring.try_push(1).is_ok());
+        assert!(ring.try_push(2).is_ok());
+        assert_eq!(ring.try_push(3), Err(3));
+
+        assert_eq!(ring.try_pop(), Some(1));
+        assert!(ring.try_push(3).is_ok());
+        assert_eq!(ring.try_pop(), Some(2));
+        assert_eq!(ring.try_pop(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn multiple_producers_deliver_every_entry() {
+        let ring = Arc::new(RingBuffer::new(64));
+        let producers = (0..4u64)
+            .map(|producer| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    for i in 0..1_000u64 {
+                        ring.push(producer * 1_000 + i, &YieldingWait::default());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut received = Vec::with_capacity(4_000);
+        while received.len() < 4_000 {
+            received.push(ring.pop(&ParkingWait::default()));
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        received.sort_unstable();
+        assert_eq!(received, (0..4_000u64).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,276 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt;
+use uuid::Uuid;
+
+use super::types::decimal::{Notional, Price, Quantity};
+use super::types::order::OrderSide;
+
+/// Everything a pre-trade check may look at for one incoming order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderContext {
+    pub user_id: Uuid,
+    pub side: OrderSide,
+    /// The limit price, or for a market order the worst price it would reach.
+    /// `None` when a market order has nothing to trade against.
+    pub price: Option<Price>,
+    pub quantity: Quantity,
+    /// Market orders never rest, so they are not held to `OpenOrdersLimit`.
+    pub market_order: bool,
+    /// Orders the user already has resting in this market.
+    pub open_orders: usize,
+    /// Net base bought minus sold by the user in this market.
+    pub position: Quantity,
+    pub reference_price: Option<Price>,
+}
+
+/// A single pre-trade check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum RiskCheck {
+    OrderQuantityLimit(Quantity),
+    NotionalLimit(Notional),
+    OpenOrdersLimit(usize),
+    /// Limit on the absolute position the order would leave the user with if
+    /// it filled completely. Other open orders are not counted.
+    PositionLimit(Quantity),
+    /// Fat-finger guard: how far, in basis points, the order price may be
+    /// from the reference price.
+    PriceBand {
+        bps: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskReject {
+    QuantityTooLarge {
+        quantity: Quantity,
+        max: Quantity,
+    },
+    NotionalTooLarge {
+        notional: Notional,
+        max: Notional,
+    },
+    NotionalOutOfRange {
+        price: Price,
+        quantity: Quantity,
+    },
+    TooManyOpenOrders {
+        user_id: Uuid,
+        max: usize,
+    },
+    PositionLimit {
+        user_id: Uuid,
+        position: Quantity,
+        max: Quantity,
+    },
+    PriceTooFarFromReference {
+        price: Price,
+        reference: Price,
+        max_bps: u32,
+    },
+}
+
+impl fmt::Display for RiskReject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskReject::QuantityTooLarge { quantity, max } => write!(
+                f,
+                "order quantity {} exceeds the maximum of {}",
+                quantity.0, max.0
+            ),
+            RiskReject::NotionalTooLarge { notional, max } => write!(
+                f,
+                "order notional {} exceeds the maximum of {}",
+                notional.0, max.0
+            ),
+            RiskReject::NotionalOutOfRange { price, quantity } => write!(
+                f,
+                "order notional {} × {} is out of range",
+                price.0, quantity.0
+            ),
+            RiskReject::TooManyOpenOrders { user_id, max } => {
+                write!(f, "user {} already has {} open orders", user_id, max)
+            }
+            RiskReject::PositionLimit {
+                user_id,
+                position,
+                max,
+            } => write!(
+                f,
+                "order would take user {} to a position of {}, beyond {}",
+                user_id, position.0, max.0
+            ),
+            RiskReject::PriceTooFarFromReference {
+                price,
+                reference,
+                max_bps,
+            } => write!(
+                f,
+                "price {} is more than {} bps from the reference {}",
+                price.0, max_bps, reference.0
+            ),
+        }
+    }
+}
+
+impl RiskCheck {
+    pub fn check(&self, order: &OrderContext) -> Result<(), RiskReject> {
+        match *self {
+            RiskCheck::OrderQuantityLimit(max) => {
+                if order.quantity > max {
+                    return Err(RiskReject::QuantityTooLarge {
+                        quantity: order.quantity,
+                        max,
+                    });
+                }
+            }
+            RiskCheck::NotionalLimit(max) => {
+                let Some(price) = order.price else {
+                    return Ok(());
+                };
+                let Some(notional) = price.checked_mul(order.quantity) else {
+                    return Err(RiskReject::NotionalOutOfRange {
+                        price,
+                        quantity: order.quantity,
+                    });
+                };
+                if notional > max {
+                    return Err(RiskReject::NotionalTooLarge { notional, max });
+                }
+            }
+            RiskCheck::OpenOrdersLimit(max) => {
+                if !order.market_order && order.open_orders >= max {
+                    return Err(RiskReject::TooManyOpenOrders {
+                        user_id: order.user_id,
+                        max,
+                    });
+                }
+            }
+            RiskCheck::PositionLimit(max) => {
+                let position = match order.side {
+                    OrderSide::Bid => order.position + order.quantity,
+                    OrderSide::Ask => order.position - order.quantity,
+                };
+                if position.0.abs() > max.0 {
+                    return Err(RiskReject::PositionLimit {
+                        user_id: order.user_id,
+                        position,
+                        max,
+                    });
+                }
+            }
+            RiskCheck::PriceBand { bps } => {
+                let (Some(price), Some(reference)) = (order.price, order.reference_price) else {
+                    return Ok(());
+                };
+                let allowed = reference.0 * Decimal::from(bps) / Decimal::from(10_000);
+                if (price.0 - reference.0).abs() > allowed {
+                    return Err(RiskReject::PriceTooFarFromReference {
+                        price,
+                        reference,
+                        max_bps: bps,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ordered pre-trade checks; the first failure rejects the order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskChain {
+    checks: Vec<RiskCheck>,
+}
+
+impl RiskChain {
+    pub fn new(checks: Vec<RiskCheck>) -> RiskChain {
+        RiskChain { checks }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    pub fn check(&self, order: &OrderContext) -> Result<(), RiskReject> {
+        self.checks.iter().try_for_each(|check| check.check(order))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(side: OrderSide, price: Decimal, quantity: Decimal) -> OrderContext {
+        OrderContext {
+            user_id: Uuid::new_v4(),
+            side,
+            price: Some(Price(price)),
+            quantity: Quantity(quantity),
+            market_order: false,
+            open_orders: 0,
+            position: Quantity::ZERO,
+            reference_price: Some(Price(dec!(100))),
+        }
+    }
+
+    #[tokio::test]
+    async fn first_failing_check_is_reported() {
+        let chain = RiskChain::new(vec![
+            RiskCheck::OrderQuantityLimit(Quantity(dec!(10))),
+            RiskCheck::NotionalLimit(Notional(dec!(500))),
+        ]);
+        assert!(chain
+            .check(&order(OrderSide::Bid, dec!(40), dec!(10)))
+            .is_ok());
+        assert_eq!(
+            chain.check(&order(OrderSide::Bid, dec!(100), dec!(11))),
+            Err(RiskReject::QuantityTooLarge {
+                quantity: Quantity(dec!(11)),
+                max: Quantity(dec!(10)),
+            })
+        );
+        assert_eq!(
+            chain.check(&order(OrderSide::Bid, dec!(100), dec!(6))),
+            Err(RiskReject::NotionalTooLarge {
+                notional: Notional(dec!(600)),
+                max: Notional(dec!(500)),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn position_limit_counts_the_direction_of_the_order() {
+        let check = RiskCheck::PositionLimit(Quantity(dec!(5)));
+        let mut long = order(OrderSide::Bid, dec!(100), dec!(2));
+        long.position = Quantity(dec!(4));
+        assert!(matches!(
+            check.check(&long),
+            Err(RiskReject::PositionLimit { .. })
+        ));
+
+        long.side = OrderSide::Ask;
+        assert!(check.check(&long).is_ok());
+    }
+
+    #[tokio::test]
+    async fn fat_finger_prices_are_rejected() {
+        let check = RiskCheck::PriceBand { bps: 1_000 };
+        assert!(check
+            .check(&order(OrderSide::Bid, dec!(110), dec!(1)))
+            .is_ok());
+        assert_eq!(
+            check.check(&order(OrderSide::Ask, dec!(89), dec!(1))),
+            Err(RiskReject::PriceTooFarFromReference {
+                price: Price(dec!(89)),
+                reference: Price(dec!(100)),
+                max_bps: 1_000,
+            })
+        );
+
+        let mut no_reference = order(OrderSide::Ask, dec!(1), dec!(1));
+        no_reference.reference_price = None;
+        assert!(check.check(&no_reference).is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Transport a client is connected over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// A session that has not sent a heartbeat for this long is treated as
+    /// disconnected.
+    pub heartbeat_timeout: Duration,
+    pub cancel_on_disconnect: bool,
+    /// How long after a disconnect resting orders survive, giving the client
+    /// a chance to reconnect.
+    pub grace_period: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            heartbeat_timeout: Duration::seconds(30),
+            cancel_on_disconnect: true,
+            grace_period: Duration::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: SessionKind,
+    pub config: SessionConfig,
+    pub last_heartbeat: DateTime<Utc>,
+    pub disconnected_at: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    /// When the session dropped: an explicit disconnect, or the moment its
+    /// heartbeat timed out.
+    pub fn lost_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let timed_out_at = self.last_heartbeat + self.config.heartbeat_timeout;
+        match self.disconnected_at {
+            Some(at) => Some(at.min(timed_out_at)),
+            None if now >= timed_out_at => Some(timed_out_at),
+            None => None,
+        }
+    }
+
+    /// Whether the grace period after losing the session is over.
+    pub fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.lost_at(now)
+            .is_some_and(|lost_at| now >= lost_at + self.config.grace_period)
+    }
+}
+
+/// Gateway sessions known to the engine.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: HashMap<Uuid, Session>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> SessionRegistry {
+        SessionRegistry::default()
+    }
+
+    /// Registers a session under the id its gateway assigned. Opening an id
+    /// that is already registered starts it afresh.
+    pub fn open(
+        &mut self,
+        id: Uuid,
+        user_id: Uuid,
+        kind: SessionKind,
+        config: SessionConfig,
+        now: DateTime<Utc>,
+    ) {
+        self.sessions.insert(
+            id,
+            Session {
+                id,
+                user_id,
+                kind,
+                config,
+                last_heartbeat: now,
+                disconnected_at: None,
+            },
+        );
+    }
+
+    pub fn get(&self, session_id: Uuid) -> Option<&Session> {
+        self.sessions.get(&session_id)
+    }
+
+    /// Records a heartbeat. A session still inside its grace period is
+    /// reconnected; returns `false` for unknown or expired sessions.
+    pub fn heartbeat(&mut self, session_id: Uuid, now: DateTime<Utc>) -> bool {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) if !session.expired(now) => {
+                session.last_heartbeat = now;
+                session.disconnected_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn disconnect(&mut self, session_id: Uuid, now: DateTime<Utc>) -> bool {
+        match self.sessions.get_mut(&session_id) {
+            Some(session) => {
+                session.disconnected_at.get_or_insert(now);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns every session whose grace period has run out.
+    pub fn take_expired(&mut self, now: DateTime<Utc>) -> Vec<Session> {
+        let expired = self
+            .sessions
+            .values()
+            .filter(|session| session.expired(now))
+            .map(|session| session.id)
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|id| self.sessions.remove(&id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn missed_heartbeats_expire_a_session() {
+        let mut registry = SessionRegistry::new();
+        let now = Utc::now();
+        let config = SessionConfig {
+            heartbeat_timeout: Duration::seconds(10),
+            ..SessionConfig::default()
+        };
+        let id = Uuid::new_v4();
+        registry.open(id, Uuid::new_v4(), SessionKind::Tcp, config, now);
+
+        assert!(registry.heartbeat(id, now + Duration::seconds(8)));
+        assert!(registry
+            .take_expired(now + Duration::seconds(15))
+            .is_empty());
+        let expired = registry.take_expired(now + Duration::seconds(18));
+        assert_eq!(expired.len(), 1);
+        assert!(registry.get(id).is_none());
+    }
+
+    #[tokio::test]
+    async fn reconnecting_within_the_grace_period_keeps_the_session() {
+        let mut registry = SessionRegistry::new();
+        let now = Utc::now();
+        let config = SessionConfig {
+            grace_period: Duration::seconds(5),
+            ..SessionConfig::default()
+        };
+        let id = Uuid::new_v4();
+        registry.open(id, Uuid::new_v4(), SessionKind::Tcp, config, now);
+
+        registry.disconnect(id, now);
+        assert!(registry.take_expired(now + Duration::seconds(4)).is_empty());
+        assert!(registry.heartbeat(id, now + Duration::seconds(4)));
+        assert!(registry.take_expired(now + Duration::seconds(6)).is_empty());
+
+        registry.disconnect(id, now + Duration::seconds(6));
+        assert!(!registry.heartbeat(id, now + Duration::seconds(12)));
+        assert_eq!(registry.take_expired(now + Duration::seconds(12)).len(), 1);
+    }
+}
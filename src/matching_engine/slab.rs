@@ -0,0 +1,169 @@
+/// Stable reference to a slab entry.
+///
+/// The generation is bumped every time a slot is freed, so a handle to a
+/// removed entry never resolves to whatever reuses the slot afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Debug)]
+enum Entry<T> {
+    Occupied {
+        generation: u32,
+        value: T,
+    },
+    Vacant {
+        generation: u32,
+        next_free: Option<usize>,
+    },
+}
+
+/// A pre-allocated arena with a free list.
+///
+/// Inserting into a slot freed by `remove` does not allocate, so a book with
+/// stable depth reaches a steady state with no heap traffic per order.
+#[derive(Debug)]
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    free_head: Option<usize>,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Slab<T> {
+        Slab::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Slab<T> {
+        Slab {
+            entries: Vec::with_capacity(capacity),
+            free_head: None,
+            len: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.entries.capacity()
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle {
+        self.len += 1;
+        match self.free_head {
+            Some(index) => {
+                let generation = match self.entries[index] {
+                    Entry::Vacant {
+                        generation,
+                        next_free,
+                    } => {
+                        self.free_head = next_free;
+                        generation
+                    }
+                    Entry::Occupied { .. } => unreachable!("free list points at an occupied slot"),
+                };
+                self.entries[index] = Entry::Occupied { generation, value };
+                Handle { index, generation }
+            }
+            None => {
+                let index = self.entries.len();
+                self.entries.push(Entry::Occupied {
+                    generation: 0,
+                    value,
+                });
+                Handle {
+                    index,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        match self.entries.get(handle.index) {
+            Some(Entry::Occupied { generation, value }) if *generation == handle.generation => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        match self.entries.get_mut(handle.index) {
+            Some(Entry::Occupied { generation, value }) if *generation == handle.generation => {
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        if !self.contains(handle) {
+            return None;
+        }
+        let vacant = Entry::Vacant {
+            generation: handle.generation.wrapping_add(1),
+            next_free: self.free_head,
+        };
+        match std::mem::replace(&mut self.entries[handle.index], vacant) {
+            Entry::Occupied { value, .. } => {
+                self.free_head = Some(handle.index);
+                self.len -= 1;
+                Some(value)
+            }
+            Entry::Vacant { .. } => unreachable!("checked by contains"),
+        }
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Slab::new()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reuses_freed_slots_without_growing() {
+        let mut slab = Slab::with_capacity(2);
+        let a = slab.insert("a");
+        let b = slab.insert("b");
+        assert_eq!(slab.remove(a), Some("a"));
+
+        let c = slab.insert("c");
+        assert_eq!(slab.len(), 2);
+        assert_eq!(slab.capacity(), 2);
+        assert_eq!(slab.get(b), Some(&"b"));
+        assert_eq!(slab.get(c), Some(&"c"));
+    }
+
+    #[tokio::test]
+    async fn stale_handles_do_not_resolve() {
+        let mut slab = Slab::new();
+        let a = slab.insert(1);
+        slab.remove(a);
+        let b = slab.insert(2);
+
+        assert_eq!(slab.get(a), None);
+        assert_eq!(slab.remove(a), None);
+        assert_eq!(slab.get(b), Some(&2));
+    }
+}
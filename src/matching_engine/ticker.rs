@@ -0,0 +1,218 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use super::types::decimal::{Notional, Price, Quantity};
+use super::types::trade::Trade;
+
+/// Summary of a market's trading over the rolling window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickerStats {
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub last: Price,
+    pub volume: Quantity,
+    pub quote_volume: Notional,
+    /// `last - open`.
+    pub price_change: Decimal,
+    pub price_change_percent: Decimal,
+    pub trades: u64,
+    /// Volume-weighted average price.
+    pub vwap: Price,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TickerTrade {
+    index: u64,
+    executed_at: DateTime<Utc>,
+    price: Price,
+    quantity: Quantity,
+    notional: Notional,
+}
+
+/// Rolling statistics over the trades of the last `window`, 24 hours by
+/// default, updated one trade at a time.
+///
+/// Sums are adjusted as trades enter and leave the window; the high and low
+/// come from monotonic queues, so neither update nor query rescans the
+/// window.
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    window: Duration,
+    trades: VecDeque<TickerTrade>,
+    next_index: u64,
+    volume: Quantity,
+    quote_volume: Notional,
+    /// Prices in the window that no later trade has matched or beaten, so
+    /// the front is the high.
+    highs: VecDeque<(u64, Price)>,
+    lows: VecDeque<(u64, Price)>,
+}
+
+impl Default for Ticker {
+    fn default() -> Self {
+        Ticker::new(Duration::hours(24))
+    }
+}
+
+impl Ticker {
+    pub fn new(window: Duration) -> Ticker {
+        Ticker {
+            window,
+            trades: VecDeque::new(),
+            next_index: 0,
+            volume: Quantity::ZERO,
+            quote_volume: Notional::ZERO,
+            highs: VecDeque::new(),
+            lows: VecDeque::new(),
+        }
+    }
+
+    pub fn record_trade(&mut self, trade: &Trade) {
+        let entry = TickerTrade {
+            index: self.next_index,
+            executed_at: trade.executed_at,
+            price: trade.price,
+            quantity: trade.quantity,
+            notional: trade.notional(),
+        };
+        self.next_index += 1;
+
+        while self
+            .highs
+            .back()
+            .is_some_and(|(_, high)| *high <= entry.price)
+        {
+            self.highs.pop_back();
+        }
+        self.highs.push_back((entry.index, entry.price));
+        while self.lows.back().is_some_and(|(_, low)| *low >= entry.price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((entry.index, entry.price));
+
+        self.volume += entry.quantity;
+        self.quote_volume += entry.notional;
+        self.trades.push_back(entry);
+        self.expire(trade.executed_at);
+    }
+
+    /// Drops trades that have left the window as of `now`.
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while let Some(oldest) = self.trades.front().copied() {
+            if oldest.executed_at > cutoff {
+                break;
+            }
+            self.trades.pop_front();
+            self.volume -= oldest.quantity;
+            self.quote_volume -= oldest.notional;
+            if self
+                .highs
+                .front()
+                .is_some_and(|(index, _)| *index == oldest.index)
+            {
+                self.highs.pop_front();
+            }
+            if self
+                .lows
+                .front()
+                .is_some_and(|(index, _)| *index == oldest.index)
+            {
+                self.lows.pop_front();
+            }
+        }
+    }
+
+    /// Statistics as of the last trade or `expire`; `None` if nothing traded
+    /// in the window.
+    pub fn stats(&self) -> Option<TickerStats> {
+        let open = self.trades.front()?.price;
+        let last = self.trades.back()?.price;
+        let price_change = last.0 - open.0;
+        let price_change_percent = if open.0.is_zero() {
+            Decimal::ZERO
+        } else {
+            price_change * Decimal::ONE_HUNDRED / open.0
+        };
+        // Zero-quantity trades never reach the ticker, but the window must
+        // not panic if one does.
+        let vwap = self.quote_volume.checked_div(self.volume).unwrap_or(last);
+        Some(TickerStats {
+            open,
+            high: self.highs.front()?.1,
+            low: self.lows.front()?.1,
+            last,
+            volume: self.volume,
+            quote_volume: self.quote_volume,
+            price_change,
+            price_change_percent,
+            trades: self.trades.len() as u64,
+            vwap,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::matching_engine::types::trade::tests::trade_at as trade;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn stats_cover_the_trades_in_the_window() {
+        let start = Utc::now();
+        let mut ticker = Ticker::default();
+        assert_eq!(ticker.stats(), None);
+
+        ticker.record_trade(&trade(start, dec!(100), dec!(1)));
+        ticker.record_trade(&trade(start + Duration::hours(1), dec!(120), dec!(1)));
+        ticker.record_trade(&trade(start + Duration::hours(2), dec!(90), dec!(2)));
+        ticker.record_trade(&trade(start + Duration::hours(3), dec!(110), dec!(1)));
+
+        let stats = ticker.stats().unwrap();
+        assert_eq!(stats.open, Price(dec!(100)));
+        assert_eq!(stats.high, Price(dec!(120)));
+        assert_eq!(stats.low, Price(dec!(90)));
+        assert_eq!(stats.last, Price(dec!(110)));
+        assert_eq!(stats.volume, Quantity(dec!(5)));
+        assert_eq!(stats.quote_volume, Notional(dec!(510)));
+        assert_eq!(stats.price_change, dec!(10));
+        assert_eq!(stats.price_change_percent, dec!(10));
+        assert_eq!(stats.trades, 4);
+        assert_eq!(stats.vwap, Price(dec!(102)));
+    }
+
+    #[tokio::test]
+    async fn old_trades_roll_out_of_the_window() {
+        let start = Utc::now();
+        let mut ticker = Ticker::default();
+        ticker.record_trade(&trade(start, dec!(100), dec!(1)));
+        ticker.record_trade(&trade(start + Duration::hours(1), dec!(130), dec!(1)));
+        ticker.record_trade(&trade(start + Duration::hours(2), dec!(80), dec!(1)));
+        ticker.record_trade(&trade(start + Duration::hours(3), dec!(105), dec!(1)));
+
+        // The opening trade and the high have both left the window.
+        ticker.expire(start + Duration::hours(25) + Duration::minutes(30));
+        let stats = ticker.stats().unwrap();
+        assert_eq!(stats.open, Price(dec!(80)));
+        assert_eq!(stats.high, Price(dec!(105)));
+        assert_eq!(stats.low, Price(dec!(80)));
+        assert_eq!(stats.trades, 2);
+        assert_eq!(stats.volume, Quantity(dec!(2)));
+
+        ticker.expire(start + Duration::hours(28));
+        assert_eq!(ticker.stats(), None);
+    }
+
+    #[tokio::test]
+    async fn zero_volume_does_not_panic() {
+        let mut ticker = Ticker::default();
+        ticker.record_trade(&trade(Utc::now(), dec!(100), dec!(0)));
+        let stats = ticker.stats().unwrap();
+        assert_eq!(stats.volume, Quantity::ZERO);
+        assert_eq!(stats.vwap, Price(dec!(100)));
+    }
+}
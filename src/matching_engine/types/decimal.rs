@@ -1,48 +1,94 @@
 use bytes::BytesMut;
-use postgres_types::{FromSql, IsNull, ToSql, Type};
+use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct Price(pub Decimal);
 
-impl<'a> FromSql<'a> for Price {
-    fn from_sql(
-        ty: &Type,
-        raw: &'a [u8],
-    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
-        let s = String::from_sql(ty, raw)?;
-        let decimal = Decimal::from_str(&s)?;
-        Ok(Price(decimal))
-    }
+/// An amount of the base asset, e.g. 0.5 in "buy 0.5 BTC".
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Quantity(pub Decimal);
+
+/// An amount of the quote asset, i.e. price × quantity.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Notional(pub Decimal);
 
-    fn accepts(ty: &Type) -> bool {
-        matches!(ty.name(), "numeric" | "money")
+impl Price {
+    pub const ZERO: Price = Price(Decimal::ZERO);
+
+    /// Price × Quantity = Notional, or `None` if the product is out of range.
+    pub fn checked_mul(self, quantity: Quantity) -> Option<Notional> {
+        self.0.checked_mul(quantity.0).map(Notional)
     }
 }
 
-impl ToSql for Price {
-    fn to_sql(
-        &self,
-        ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
-        self.0.to_string().to_sql(ty, out)
+impl Quantity {
+    pub const ZERO: Quantity = Quantity(Decimal::ZERO);
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
     }
 
-    fn accepts(ty: &Type) -> bool {
-        matches!(ty.name(), "numeric" | "money")
+    pub fn min(self, other: Quantity) -> Quantity {
+        Quantity(self.0.min(other.0))
     }
+}
+
+impl Notional {
+    pub const ZERO: Notional = Notional(Decimal::ZERO);
 
-    fn to_sql_checked(
-        &self,
-        ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
-        self.to_sql(ty, out)
+    /// Notional / Price = Quantity and Notional / Quantity = Price, or `None`
+    /// if the divisor is zero or the quotient is out of range.
+    pub fn checked_div<D: NotionalDivisor>(self, divisor: D) -> Option<D::Output> {
+        D::divide(self, divisor)
     }
 }
 
+/// Stores the wrapped `Decimal` in `numeric` columns using rust_decimal's
+/// binary encoding.
+macro_rules! impl_numeric_sql {
+    ($name:ident) => {
+        impl<'a> FromSql<'a> for $name {
+            fn from_sql(
+                ty: &Type,
+                raw: &'a [u8],
+            ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+                Decimal::from_sql(ty, raw).map($name)
+            }
+
+            fn accepts(ty: &Type) -> bool {
+                <Decimal as FromSql>::accepts(ty)
+            }
+        }
+
+        impl ToSql for $name {
+            fn to_sql(
+                &self,
+                ty: &Type,
+                out: &mut BytesMut,
+            ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+                self.0.to_sql(ty, out)
+            }
+
+            fn accepts(ty: &Type) -> bool {
+                <Decimal as ToSql>::accepts(ty)
+            }
+
+            to_sql_checked!();
+        }
+    };
+}
+
+impl_numeric_sql!(Price);
+impl_numeric_sql!(Quantity);
+impl_numeric_sql!(Notional);
+
 // Common operations
 impl From<Decimal> for Price {
     fn from(decimal: Decimal) -> Self {
@@ -56,7 +102,7 @@ impl From<Price> for Decimal {
     }
 }
 
-// Add arithmetic operations if needed
+// Price ± Price is a price offset; multiplying or dividing prices is meaningless.
 impl std::ops::Add for Price {
     type Output = Self;
     fn add(self, other: Self) -> Self {
@@ -71,28 +117,133 @@ impl std::ops::Sub for Price {
     }
 }
 
-impl std::ops::Mul for Price {
+impl From<Decimal> for Quantity {
+    fn from(decimal: Decimal) -> Self {
+        Quantity(decimal)
+    }
+}
+
+impl From<Quantity> for Decimal {
+    fn from(quantity: Quantity) -> Self {
+        quantity.0
+    }
+}
+
+impl From<Decimal> for Notional {
+    fn from(decimal: Decimal) -> Self {
+        Notional(decimal)
+    }
+}
+
+impl From<Notional> for Decimal {
+    fn from(notional: Notional) -> Self {
+        notional.0
+    }
+}
+
+impl std::ops::Add for Quantity {
     type Output = Self;
-    fn mul(self, other: Self) -> Self {
-        Price(self.0 * other.0)
+    fn add(self, other: Self) -> Self {
+        Quantity(self.0 + other.0)
     }
 }
 
-impl std::ops::Div for Price {
+impl std::ops::Sub for Quantity {
     type Output = Self;
-    fn div(self, other: Self) -> Self {
-        Price(self.0 / other.0)
+    fn sub(self, other: Self) -> Self {
+        Quantity(self.0 - other.0)
+    }
+}
+
+impl std::ops::AddAssign for Quantity {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0;
     }
 }
 
-impl PartialEq for Price {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+impl std::ops::SubAssign for Quantity {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 -= other.0;
     }
 }
 
-impl PartialOrd for Price {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+impl std::iter::Sum for Quantity {
+    fn sum<I: Iterator<Item = Quantity>>(iter: I) -> Self {
+        Quantity(iter.map(|quantity| quantity.0).sum())
+    }
+}
+
+impl std::ops::Add for Notional {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Notional(self.0 + other.0)
+    }
+}
+
+impl std::ops::Sub for Notional {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Notional(self.0 - other.0)
+    }
+}
+
+impl std::ops::AddAssign for Notional {
+    fn add_assign(&mut self, other: Self) {
+        self.0 += other.0;
+    }
+}
+
+impl std::ops::SubAssign for Notional {
+    fn sub_assign(&mut self, other: Self) {
+        self.0 -= other.0;
+    }
+}
+
+impl std::iter::Sum for Notional {
+    fn sum<I: Iterator<Item = Notional>>(iter: I) -> Self {
+        Notional(iter.map(|notional| notional.0).sum())
+    }
+}
+
+/// Something a `Notional` can be divided by: a `Price` yields a `Quantity`
+/// and a `Quantity` yields a `Price`.
+pub trait NotionalDivisor: Copy {
+    type Output;
+    fn divide(notional: Notional, divisor: Self) -> Option<Self::Output>;
+}
+
+impl NotionalDivisor for Price {
+    type Output = Quantity;
+    fn divide(notional: Notional, price: Price) -> Option<Quantity> {
+        notional.0.checked_div(price.0).map(Quantity)
+    }
+}
+
+// e.g. the average price of a fill
+impl NotionalDivisor for Quantity {
+    type Output = Price;
+    fn divide(notional: Notional, quantity: Quantity) -> Option<Price> {
+        notional.0.checked_div(quantity.0).map(Price)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn dividing_a_notional_by_zero_is_none() {
+        let notional = Notional(dec!(250));
+        assert_eq!(
+            notional.checked_div(Price(dec!(100))),
+            Some(Quantity(dec!(2.5)))
+        );
+        assert_eq!(
+            notional.checked_div(Quantity(dec!(2))),
+            Some(Price(dec!(125)))
+        );
+        assert_eq!(notional.checked_div(Price::ZERO), None);
+        assert_eq!(notional.checked_div(Quantity::ZERO), None);
     }
 }
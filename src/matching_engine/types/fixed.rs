@@ -0,0 +1,279 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Number of decimal places a market quotes prices and quantities in.
+///
+/// A fixed-point value is the decimal value multiplied by `10^scale`, so with
+/// a `price_scale` of 2 the price 101.25 is stored as 10125 ticks.
+///
+/// Scales are only ever set through `Precision::new`, deserializing
+/// included, so neither exceeds `MAX_SCALE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedPrecision")]
+pub struct Precision {
+    price_scale: u32,
+    quantity_scale: u32,
+}
+
+/// `Precision` as it arrives over the wire, before its scales are checked.
+#[derive(Deserialize)]
+struct UncheckedPrecision {
+    price_scale: u32,
+    quantity_scale: u32,
+}
+
+impl TryFrom<UncheckedPrecision> for Precision {
+    type Error = FixedPointError;
+
+    fn try_from(unchecked: UncheckedPrecision) -> Result<Precision, FixedPointError> {
+        Precision::new(unchecked.price_scale, unchecked.quantity_scale)
+    }
+}
+
+impl Precision {
+    /// Largest scale that still leaves headroom in an `i64` mantissa.
+    pub const MAX_SCALE: u32 = 12;
+
+    pub fn new(price_scale: u32, quantity_scale: u32) -> Result<Precision, FixedPointError> {
+        for scale in [price_scale, quantity_scale] {
+            if scale > Self::MAX_SCALE {
+                return Err(FixedPointError::ScaleTooLarge(scale));
+            }
+        }
+        Ok(Precision {
+            price_scale,
+            quantity_scale,
+        })
+    }
+
+    pub fn price_scale(&self) -> u32 {
+        self.price_scale
+    }
+
+    pub fn quantity_scale(&self) -> u32 {
+        self.quantity_scale
+    }
+}
+
+impl Default for Precision {
+    /// Matches the `NUMERIC(32, 8)` columns in the `orders` table.
+    fn default() -> Self {
+        Precision {
+            price_scale: 8,
+            quantity_scale: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FixedPointError {
+    Overflow,
+    PrecisionLoss { value: Decimal, scale: u32 },
+    ScaleTooLarge(u32),
+}
+
+impl fmt::Display for FixedPointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixedPointError::Overflow => write!(f, "fixed-point arithmetic overflowed"),
+            FixedPointError::PrecisionLoss { value, scale } => write!(
+                f,
+                "{} has more than {} decimal places for this market",
+                value, scale
+            ),
+            FixedPointError::ScaleTooLarge(scale) => write!(
+                f,
+                "scale {} exceeds the maximum of {}",
+                scale,
+                Precision::MAX_SCALE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FixedPointError {}
+
+fn to_mantissa(value: Decimal, scale: u32) -> Result<i64, FixedPointError> {
+    if value.normalize().scale() > scale {
+        return Err(FixedPointError::PrecisionLoss { value, scale });
+    }
+    let factor = Decimal::from(10i64.pow(scale));
+    value
+        .checked_mul(factor)
+        .and_then(|scaled| scaled.to_i64())
+        .ok_or(FixedPointError::Overflow)
+}
+
+/// Like `to_mantissa`, but rounds with `strategy` instead of rejecting extra
+/// places, and saturates at the ends of the `i64` range.
+fn to_mantissa_rounded(value: Decimal, scale: u32, strategy: RoundingStrategy) -> i64 {
+    let factor = Decimal::from(10i64.pow(scale));
+    value
+        .round_dp_with_strategy(scale, strategy)
+        .checked_mul(factor)
+        .and_then(|scaled| scaled.to_i64())
+        .unwrap_or(if value.is_sign_negative() {
+            i64::MIN
+        } else {
+            i64::MAX
+        })
+}
+
+/// A price as an integer number of ticks at the market's `price_scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Price(pub i64);
+
+impl Price {
+    pub fn from_decimal(value: Decimal, precision: Precision) -> Result<Price, FixedPointError> {
+        to_mantissa(value, precision.price_scale).map(Price)
+    }
+
+    /// The lowest tick at or above `value`. Meant for range bounds, where a
+    /// value between ticks is still meaningful.
+    pub fn ceil_from_decimal(value: Decimal, precision: Precision) -> Price {
+        Price(to_mantissa_rounded(
+            value,
+            precision.price_scale,
+            RoundingStrategy::ToPositiveInfinity,
+        ))
+    }
+
+    /// The highest tick at or below `value`.
+    pub fn floor_from_decimal(value: Decimal, precision: Precision) -> Price {
+        Price(to_mantissa_rounded(
+            value,
+            precision.price_scale,
+            RoundingStrategy::ToNegativeInfinity,
+        ))
+    }
+
+    pub fn to_decimal(self, precision: Precision) -> Decimal {
+        Decimal::new(self.0, precision.price_scale)
+    }
+
+    pub fn ticks(self) -> i64 {
+        self.0
+    }
+}
+
+/// A quantity as an integer number of lots at the market's `quantity_scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Quantity(pub i64);
+
+impl Quantity {
+    pub const ZERO: Quantity = Quantity(0);
+
+    pub fn from_decimal(value: Decimal, precision: Precision) -> Result<Quantity, FixedPointError> {
+        to_mantissa(value, precision.quantity_scale).map(Quantity)
+    }
+
+    pub fn to_decimal(self, precision: Precision) -> Decimal {
+        Decimal::new(self.0, precision.quantity_scale)
+    }
+
+    pub fn lots(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Quantity) -> Result<Quantity, FixedPointError> {
+        self.0
+            .checked_add(other.0)
+            .map(Quantity)
+            .ok_or(FixedPointError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Quantity) -> Result<Quantity, FixedPointError> {
+        self.0
+            .checked_sub(other.0)
+            .map(Quantity)
+            .ok_or(FixedPointError::Overflow)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn round_trips_through_decimal() {
+        let precision = Precision::new(2, 4).unwrap();
+
+        let price = Price::from_decimal(dec!(101.25), precision).unwrap();
+        assert_eq!(price.ticks(), 10125);
+        assert_eq!(price.to_decimal(precision), dec!(101.25));
+
+        let quantity = Quantity::from_decimal(dec!(0.5), precision).unwrap();
+        assert_eq!(quantity.lots(), 5000);
+        assert_eq!(quantity.to_decimal(precision), dec!(0.5));
+    }
+
+    #[tokio::test]
+    async fn deserialized_scales_are_checked() {
+        use serde::de::value::{Error, MapDeserializer};
+
+        let deserialize = |price_scale: u32, quantity_scale: u32| {
+            let fields = [
+                ("price_scale", price_scale),
+                ("quantity_scale", quantity_scale),
+            ];
+            Precision::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()))
+        };
+        assert_eq!(deserialize(2, 4).unwrap(), Precision::new(2, 4).unwrap());
+        assert!(deserialize(2, Precision::MAX_SCALE + 1).is_err());
+        assert!(deserialize(u32::MAX, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_values_finer_than_the_market_precision() {
+        let precision = Precision::new(2, 2).unwrap();
+        assert_eq!(
+            Price::from_decimal(dec!(1.005), precision),
+            Err(FixedPointError::PrecisionLoss {
+                value: dec!(1.005),
+                scale: 2
+            })
+        );
+        // Trailing zeros are not extra precision.
+        assert!(Price::from_decimal(dec!(1.0000), precision).is_ok());
+    }
+
+    #[tokio::test]
+    async fn bounds_round_inward_to_the_nearest_tick() {
+        let precision = Precision::new(2, 2).unwrap();
+        assert_eq!(Price::ceil_from_decimal(dec!(1.001), precision), Price(101));
+        assert_eq!(
+            Price::floor_from_decimal(dec!(1.009), precision),
+            Price(100)
+        );
+        assert_eq!(
+            Price::ceil_from_decimal(dec!(-1.009), precision),
+            Price(-100)
+        );
+        assert_eq!(Price::floor_from_decimal(dec!(1.00), precision), Price(100));
+        assert_eq!(
+            Price::floor_from_decimal(dec!(1_000_000_000_000_000_000), precision),
+            Price(i64::MAX)
+        );
+    }
+
+    #[tokio::test]
+    async fn overflow_is_reported_not_wrapped() {
+        let precision = Precision::default();
+        assert_eq!(
+            Quantity::from_decimal(dec!(1_000_000_000_000), precision),
+            Err(FixedPointError::Overflow)
+        );
+        assert_eq!(
+            Quantity(i64::MAX).checked_add(Quantity(1)),
+            Err(FixedPointError::Overflow)
+        );
+        assert_eq!(Quantity(5).checked_sub(Quantity(2)), Ok(Quantity(3)));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod decimal;
+pub mod fixed;
 pub mod order;
+pub mod trade;
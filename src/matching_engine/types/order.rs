@@ -1,14 +1,19 @@
+use bytes::BytesMut;
 use chrono::{DateTime, Utc};
+use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize)]
+use super::decimal::{Price, Quantity};
+use crate::matching_engine::engine::TradingPair;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 pub enum OrderSide {
     Bid,
     Ask,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderStatus {
     New,
     Filled,
@@ -16,14 +21,91 @@ pub enum OrderStatus {
     Cancelled,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OrderRecord {
     pub id: Uuid,
+    /// Market the order trades in. Set by the engine when it accepts the order.
+    pub pair: Option<TradingPair>,
+    pub user_id: Uuid,
+    /// Connection the order was entered on, if it came through one.
+    pub session_id: Option<Uuid>,
+    /// Identifier chosen by the client, unique per user among live orders.
+    pub client_order_id: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-    pub price: rust_decimal::Decimal,
-    pub size: rust_decimal::Decimal,
-    pub remaining_size: rust_decimal::Decimal,
+    pub price: Price,
+    pub size: Quantity,
+    pub remaining_size: Quantity,
     pub side: OrderSide,
     pub status: OrderStatus,
 }
+
+impl OrderSide {
+    /// Label of the `order_side` enum in Postgres.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderSide::Bid => "bid",
+            OrderSide::Ask => "ask",
+        }
+    }
+}
+
+impl OrderStatus {
+    /// Label of the `order_status` enum in Postgres.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "new",
+            OrderStatus::Filled => "filled",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Stores the enum as its label in a Postgres enum column of type `$pg_type`.
+macro_rules! impl_enum_sql {
+    ($name:ident, $pg_type:literal, [$($variant:ident),+]) => {
+        impl<'a> FromSql<'a> for $name {
+            fn from_sql(
+                _ty: &Type,
+                raw: &'a [u8],
+            ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+                let label = std::str::from_utf8(raw)?;
+                $(
+                    if label == $name::$variant.as_str() {
+                        return Ok($name::$variant);
+                    }
+                )+
+                Err(format!("unknown {} label {}", $pg_type, label).into())
+            }
+
+            fn accepts(ty: &Type) -> bool {
+                ty.name() == $pg_type
+            }
+        }
+
+        impl ToSql for $name {
+            fn to_sql(
+                &self,
+                _ty: &Type,
+                out: &mut BytesMut,
+            ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+                out.extend_from_slice(self.as_str().as_bytes());
+                Ok(IsNull::No)
+            }
+
+            fn accepts(ty: &Type) -> bool {
+                ty.name() == $pg_type
+            }
+
+            to_sql_checked!();
+        }
+    };
+}
+
+impl_enum_sql!(OrderSide, "order_side", [Bid, Ask]);
+impl_enum_sql!(
+    OrderStatus,
+    "order_status",
+    [New, Filled, PartiallyFilled, Cancelled]
+);
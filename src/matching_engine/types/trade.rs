@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::decimal::{Notional, Price, Quantity};
+use super::order::OrderSide;
+use crate::matching_engine::fees::Fee;
+
+/// A single execution between a buy order and a sell order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub id: Uuid,
+    pub buy_order_id: Uuid,
+    pub buyer_id: Uuid,
+    pub sell_order_id: Uuid,
+    pub seller_id: Uuid,
+    /// Side of the incoming order that crossed the book. `None` for auction
+    /// uncrosses, where every order executes at the same price.
+    pub aggressor: Option<OrderSide>,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub executed_at: DateTime<Utc>,
+    /// Set by the engine when the market has a fee schedule.
+    pub buyer_fee: Option<Fee>,
+    pub seller_fee: Option<Fee>,
+}
+
+impl Trade {
+    /// `buy` and `sell` are `(order_id, user_id)` pairs.
+    pub fn new(
+        buy: (Uuid, Uuid),
+        sell: (Uuid, Uuid),
+        aggressor: Option<OrderSide>,
+        price: Price,
+        quantity: Quantity,
+    ) -> Trade {
+        Trade {
+            id: Uuid::new_v4(),
+            buy_order_id: buy.0,
+            buyer_id: buy.1,
+            sell_order_id: sell.0,
+            seller_id: sell.1,
+            aggressor,
+            price,
+            quantity,
+            executed_at: Utc::now(),
+            buyer_fee: None,
+            seller_fee: None,
+        }
+    }
+
+    /// Cannot overflow: the engine rejects orders whose own notional is out
+    /// of range, and a trade is never worth more than both of its orders.
+    pub fn notional(&self) -> Notional {
+        self.price
+            .checked_mul(self.quantity)
+            .expect("trade notional is bounded by its orders")
+    }
+
+    pub fn maker_order_id(&self) -> Option<Uuid> {
+        match self.aggressor? {
+            OrderSide::Bid => Some(self.sell_order_id),
+            OrderSide::Ask => Some(self.buy_order_id),
+        }
+    }
+
+    pub fn taker_order_id(&self) -> Option<Uuid> {
+        match self.aggressor? {
+            OrderSide::Bid => Some(self.buy_order_id),
+            OrderSide::Ask => Some(self.sell_order_id),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    /// A trade between fresh orders, executed at `at`.
+    pub fn trade_at(at: DateTime<Utc>, price: Decimal, quantity: Decimal) -> Trade {
+        let mut trade = Trade::new(
+            (Uuid::new_v4(), Uuid::new_v4()),
+            (Uuid::new_v4(), Uuid::new_v4()),
+            Some(OrderSide::Bid),
+            Price(price),
+            Quantity(quantity),
+        );
+        trade.executed_at = at;
+        trade
+    }
+}
@@ -0,0 +1,68 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::db::pool::DbPool;
+use crate::errors::custom_error::OrderError;
+
+/// API keys clients authenticate their connections with. Only a hash of
+/// each key is stored.
+pub struct ApiKeyRepository;
+
+impl ApiKeyRepository {
+    /// Creates a key for `user_id`. The key is returned only here; it cannot
+    /// be read back later.
+    pub async fn issue(pool: &DbPool, user_id: Uuid) -> Result<String, OrderError> {
+        let api_key = Uuid::new_v4().simple().to_string();
+        let client = pool.get_connection().await?;
+        client
+            .execute(
+                "INSERT INTO api_keys (key_hash, user_id) VALUES ($1, $2)",
+                &[&Self::hash(&api_key), &user_id],
+            )
+            .await?;
+        Ok(api_key)
+    }
+
+    /// The user `api_key` was issued to, `None` if it was never issued.
+    pub async fn find_user(pool: &DbPool, api_key: &str) -> Result<Option<Uuid>, OrderError> {
+        let client = pool.get_connection().await?;
+        let row = client
+            .query_opt(
+                "SELECT user_id FROM api_keys WHERE key_hash = $1",
+                &[&Self::hash(api_key)],
+            )
+            .await?;
+        Ok(row.map(|row| row.get("user_id")))
+    }
+
+    fn hash(api_key: &str) -> Vec<u8> {
+        Sha256::digest(api_key.as_bytes()).to_vec()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+
+    #[tokio::test]
+    async fn issued_keys_find_their_user() {
+        migrate();
+        let user_id = Uuid::new_v4();
+        let api_key = ApiKeyRepository::issue(&DB_POOL, user_id).await.unwrap();
+
+        assert_eq!(
+            ApiKeyRepository::find_user(&DB_POOL, &api_key)
+                .await
+                .unwrap(),
+            Some(user_id)
+        );
+        assert_eq!(
+            ApiKeyRepository::find_user(&DB_POOL, "not-a-key")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::pool::DbPool;
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::candles::{Candle, CandleInterval};
+use crate::matching_engine::engine::TradingPair;
+
+pub struct CandleRepository;
+
+impl CandleRepository {
+    /// Candles for `pair` opening in `[from, to)`, oldest first. Intervals
+    /// without trades are left out.
+    pub async fn find_range(
+        pool: &DbPool,
+        pair: &TradingPair,
+        interval: CandleInterval,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Candle>, OrderError> {
+        let client = pool.get_connection().await?;
+        // The view name comes from a fixed set, never from the caller.
+        let query = format!(
+            "SELECT bucket, open, high, low, close, volume, quote_volume, trades
+             FROM candles_{}
+             WHERE market = $1 AND bucket >= $2 AND bucket < $3
+             ORDER BY bucket",
+            interval.as_str()
+        );
+        let rows = client
+            .query(&query, &[&pair.to_string(), &from, &to])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| Candle {
+                interval,
+                open_time: row.get("bucket"),
+                open: row.get("open"),
+                high: row.get("high"),
+                low: row.get("low"),
+                close: row.get("close"),
+                volume: row.get("volume"),
+                quote_volume: row.get("quote_volume"),
+                trades: row.get::<_, i64>("trades") as u64,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::domain::trade::ExecutedTrade;
+    use crate::matching_engine::types::decimal::{Notional, Price, Quantity};
+    use crate::matching_engine::types::order::OrderSide;
+    use crate::matching_engine::types::trade::Trade;
+    use crate::repository::trade_repository::TradeRepository;
+    use chrono::{Duration, TimeZone};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn candles_are_built_from_stored_trades() {
+        migrate();
+        // A market of its own, so other tests' trades don't land in it.
+        let pair = TradingPair::new(Uuid::new_v4().simple().to_string(), "USD".to_string());
+        let start = Utc.with_ymd_and_hms(2026, 3, 14, 15, 0, 0).unwrap();
+        let trade = |sequence, seconds, price: Decimal, quantity: Decimal| {
+            let mut trade = Trade::new(
+                (Uuid::new_v4(), Uuid::new_v4()),
+                (Uuid::new_v4(), Uuid::new_v4()),
+                Some(OrderSide::Ask),
+                Price(price),
+                Quantity(quantity),
+            );
+            trade.executed_at = start + Duration::seconds(seconds);
+            ExecutedTrade {
+                sequence,
+                pair: pair.clone(),
+                trade,
+            }
+        };
+        let trades = vec![
+            trade(1, 10, dec!(100), dec!(1)),
+            trade(2, 30, dec!(105), dec!(1)),
+            trade(3, 50, dec!(98), dec!(2)),
+            trade(4, 70, dec!(101), dec!(1)),
+        ];
+        TradeRepository::insert_batch(&DB_POOL, &trades)
+            .await
+            .unwrap();
+
+        let candles = CandleRepository::find_range(
+            &DB_POOL,
+            &pair,
+            CandleInterval::OneMinute,
+            start,
+            start + Duration::hours(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(candles.len(), 2);
+        let first = &candles[0];
+        assert_eq!(first.open_time, start);
+        assert_eq!(
+            (first.open, first.high, first.low, first.close),
+            (
+                Price(dec!(100)),
+                Price(dec!(105)),
+                Price(dec!(98)),
+                Price(dec!(98))
+            )
+        );
+        assert_eq!(first.volume, Quantity(dec!(4)));
+        assert_eq!(first.quote_volume, Notional(dec!(401)));
+        assert_eq!(first.trades, 3);
+
+        let daily = CandleRepository::find_range(
+            &DB_POOL,
+            &pair,
+            CandleInterval::OneDay,
+            start - Duration::days(1),
+            start + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].close, Price(dec!(101)));
+    }
+}
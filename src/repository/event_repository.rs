@@ -0,0 +1,298 @@
+use crate::db::pool::DbPool;
+use crate::domain::order::OrderStatus;
+use crate::domain::trade::ExecutedTrade;
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::events::{EngineEvent, SequencedEvent};
+use crate::matching_engine::market_state::TradingState;
+use crate::repository::settlement_repository::SettlementRepository;
+use crate::repository::trade_repository::TradeRepository;
+
+/// Applies the engine's event stream to the `markets`, `orders`, `trades`
+/// and settlement tables.
+pub struct EventRepository;
+
+impl EventRepository {
+    /// Applies a batch of events in one transaction and advances the
+    /// checkpoint to the newest of them.
+    ///
+    /// Events at or below the stored checkpoint were already applied and are
+    /// skipped, so a batch can be retried or replayed safely.
+    pub async fn apply_batch(pool: &DbPool, events: &[SequencedEvent]) -> Result<(), OrderError> {
+        let Some(newest) = events.iter().map(|event| event.sequence).max() else {
+            return Ok(());
+        };
+        let mut client = pool.get_connection().await?;
+        let transaction = client.transaction().await?;
+        let checkpoint = transaction
+            .query_opt(
+                "SELECT last_sequence FROM engine_checkpoint FOR UPDATE",
+                &[],
+            )
+            .await?
+            .map(|row| row.get::<_, i64>("last_sequence") as u64)
+            .unwrap_or(0);
+
+        let insert_trade = transaction.prepare(TradeRepository::INSERT).await?;
+        let fill_order = transaction
+            .prepare(
+                "UPDATE orders
+                 SET remaining_size = GREATEST(remaining_size - $2, 0),
+                     status = CASE WHEN remaining_size - $2 <= 0
+                                   THEN 'filled'::order_status
+                                   ELSE 'partially_filled'::order_status
+                              END,
+                     updated_at = $3
+                 WHERE id = $1",
+            )
+            .await?;
+        for event in events.iter().filter(|event| event.sequence > checkpoint) {
+            match &event.event {
+                EngineEvent::MarketAdded {
+                    pair,
+                    precision,
+                    state,
+                } => {
+                    transaction
+                        .execute(
+                            "INSERT INTO markets (pair, price_scale, quantity_scale,
+                                                  trading_state, updated_at)
+                             VALUES ($1, $2, $3, $4, $5)
+                             ON CONFLICT (pair) DO UPDATE
+                             SET price_scale = EXCLUDED.price_scale,
+                                 quantity_scale = EXCLUDED.quantity_scale,
+                                 trading_state = EXCLUDED.trading_state,
+                                 updated_at = EXCLUDED.updated_at",
+                            &[
+                                &pair.to_string(),
+                                &(precision.price_scale() as i32),
+                                &(precision.quantity_scale() as i32),
+                                &state.to_string(),
+                                &event.emitted_at,
+                            ],
+                        )
+                        .await?;
+                }
+                EngineEvent::TradingStateChanged { pair, to, .. } => {
+                    transaction
+                        .execute(
+                            "UPDATE markets SET trading_state = $2, updated_at = $3
+                             WHERE pair = $1",
+                            &[&pair.to_string(), &to.to_string(), &event.emitted_at],
+                        )
+                        .await?;
+                }
+                EngineEvent::MarketDelisted { pair, .. } => {
+                    transaction
+                        .execute(
+                            "UPDATE markets SET trading_state = $2, updated_at = $3
+                             WHERE pair = $1",
+                            &[
+                                &pair.to_string(),
+                                &TradingState::Delisted.to_string(),
+                                &event.emitted_at,
+                            ],
+                        )
+                        .await?;
+                }
+                EngineEvent::MarketRemoved { pair } => {
+                    transaction
+                        .execute("DELETE FROM markets WHERE pair = $1", &[&pair.to_string()])
+                        .await?;
+                }
+                EngineEvent::OrderAccepted {
+                    pair,
+                    order_id,
+                    user_id,
+                    session_id,
+                    client_order_id,
+                    side,
+                    price,
+                    size,
+                    created_at,
+                } => {
+                    transaction
+                        .execute(
+                            "INSERT INTO orders (id, user_id, session_id, client_order_id, market,
+                                                 side, status, price, size, remaining_size,
+                                                 created_at, updated_at, sequence)
+                             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9, $10, $10, $11)
+                             ON CONFLICT (id, created_at) DO UPDATE
+                             SET sequence = EXCLUDED.sequence
+                             WHERE orders.sequence IS NULL",
+                            &[
+                                order_id,
+                                user_id,
+                                session_id,
+                                client_order_id,
+                                &pair.to_string(),
+                                side,
+                                &OrderStatus::New,
+                                price,
+                                size,
+                                created_at,
+                                &(event.sequence as i64),
+                            ],
+                        )
+                        .await?;
+                }
+                EngineEvent::Trade { .. } => {
+                    let executed =
+                        ExecutedTrade::from_event(event).expect("trade events carry a trade");
+                    let trade = &executed.trade;
+                    let inserted =
+                        TradeRepository::insert_with(&transaction, &insert_trade, &executed)
+                            .await?;
+                    if inserted == 0 {
+                        continue;
+                    }
+                    for order_id in [trade.buy_order_id, trade.sell_order_id] {
+                        transaction
+                            .execute(
+                                &fill_order,
+                                &[&order_id, &trade.quantity, &trade.executed_at],
+                            )
+                            .await?;
+                    }
+                }
+                // Fills of a market order only reach `orders` here, once it
+                // is done; its trades were applied before it had a row.
+                EngineEvent::MarketOrderFilled {
+                    pair,
+                    order_id,
+                    user_id,
+                    session_id,
+                    client_order_id,
+                    side,
+                    average_price,
+                    filled,
+                    remaining,
+                    created_at,
+                } => {
+                    let status = if remaining.is_zero() {
+                        OrderStatus::Filled
+                    } else {
+                        OrderStatus::Cancelled
+                    };
+                    transaction
+                        .execute(
+                            "INSERT INTO orders (id, user_id, session_id, client_order_id, market,
+                                                 side, status, price, size, remaining_size,
+                                                 created_at, updated_at, sequence)
+                             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                             ON CONFLICT DO NOTHING",
+                            &[
+                                order_id,
+                                user_id,
+                                session_id,
+                                client_order_id,
+                                &pair.to_string(),
+                                side,
+                                &status,
+                                average_price,
+                                &(*filled + *remaining),
+                                remaining,
+                                created_at,
+                                &event.emitted_at,
+                                &(event.sequence as i64),
+                            ],
+                        )
+                        .await?;
+                }
+                EngineEvent::TradeSettled { settlement, .. } => {
+                    SettlementRepository::insert_with(&transaction, settlement).await?;
+                }
+                EngineEvent::BalanceTransferred { transfer } => {
+                    SettlementRepository::insert_transfer_with(&transaction, transfer).await?;
+                }
+                // An amend that loses priority re-queues the order, so it
+                // takes the amend's sequence as its place in line.
+                EngineEvent::OrderAmended {
+                    order_id,
+                    price,
+                    remaining,
+                    lost_priority,
+                    ..
+                } => {
+                    transaction
+                        .execute(
+                            "UPDATE orders
+                             SET price = $2, remaining_size = $3, updated_at = $6,
+                                 sequence = CASE WHEN $4 THEN $5 ELSE sequence END
+                             WHERE id = $1",
+                            &[
+                                order_id,
+                                price,
+                                remaining,
+                                lost_priority,
+                                &(event.sequence as i64),
+                                &event.emitted_at,
+                            ],
+                        )
+                        .await?;
+                }
+                EngineEvent::OrderCancelled {
+                    order_id,
+                    remaining,
+                    ..
+                } => {
+                    transaction
+                        .execute(
+                            "UPDATE orders SET status = $2, remaining_size = $3, updated_at = $4
+                             WHERE id = $1",
+                            &[
+                                order_id,
+                                &OrderStatus::Cancelled,
+                                remaining,
+                                &event.emitted_at,
+                            ],
+                        )
+                        .await?;
+                }
+                _ => {}
+            }
+        }
+
+        transaction
+            .execute(
+                "INSERT INTO engine_checkpoint (id, last_sequence, updated_at)
+                 VALUES (TRUE, $1, NOW())
+                 ON CONFLICT (id) DO UPDATE
+                 SET last_sequence = GREATEST(engine_checkpoint.last_sequence,
+                                              EXCLUDED.last_sequence),
+                     updated_at = EXCLUDED.updated_at",
+                &[&(newest as i64)],
+            )
+            .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Sequence number of the last event applied to the database.
+    pub async fn last_sequence(pool: &DbPool) -> Result<Option<u64>, OrderError> {
+        let client = pool.get_connection().await?;
+        let row = client
+            .query_opt("SELECT last_sequence FROM engine_checkpoint", &[])
+            .await?;
+        Ok(row.map(|row| row.get::<_, i64>("last_sequence") as u64))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::pool::DB_POOL;
+    use tokio::sync::{Mutex, MutexGuard};
+
+    /// Tests share one checkpoint and events at or below it are skipped, so
+    /// tests that write events take turns. Returns the sequence to number
+    /// the caller's events after.
+    pub async fn take_checkpoint() -> (MutexGuard<'static, ()>, u64) {
+        static CHECKPOINT: Mutex<()> = Mutex::const_new(());
+        let guard = CHECKPOINT.lock().await;
+        let base = EventRepository::last_sequence(&DB_POOL)
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        (guard, base)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::db::pool::DbPool;
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::market_state::TradingState;
+use crate::matching_engine::types::fixed::Precision;
+
+/// A listed market as the database last saw it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketRecord {
+    pub pair: TradingPair,
+    pub precision: Precision,
+    pub state: TradingState,
+}
+
+/// Reads the `markets` table, which `EventRepository` keeps up to date.
+pub struct MarketRepository;
+
+impl MarketRepository {
+    /// Every market, skipping rows that no longer parse.
+    pub async fn find_all(pool: &DbPool) -> Result<Vec<MarketRecord>, OrderError> {
+        let client = pool.get_connection().await?;
+        let rows = client
+            .query("SELECT * FROM markets ORDER BY pair", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                Some(MarketRecord {
+                    pair: row.get::<_, &str>("pair").parse().ok()?,
+                    precision: Precision::new(
+                        row.get::<_, i32>("price_scale") as u32,
+                        row.get::<_, i32>("quantity_scale") as u32,
+                    )
+                    .ok()?,
+                    state: row.get::<_, &str>("trading_state").parse().ok()?,
+                })
+            })
+            .collect())
+    }
+}
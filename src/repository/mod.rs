@@ -1 +1,7 @@
+pub mod api_key_repository;
+pub mod candle_repository;
+pub mod event_repository;
+pub mod market_repository;
 pub mod order_repository;
+pub mod settlement_repository;
+pub mod trade_repository;
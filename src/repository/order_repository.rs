@@ -1,57 +1,499 @@
+#[cfg(test)]
+use std::sync::Mutex;
+
+#[cfg(test)]
+use tokio_postgres::error::SqlState;
+use uuid::Uuid;
+
 use crate::db::pool::DbPool;
-use crate::domain::order::Order;
+#[cfg(test)]
+use crate::domain::order::OrderStatus;
+use crate::domain::order::{Order, OrderCursor, OrderFilter, Page, PageRequest};
 use crate::errors::custom_error::OrderError;
 
-pub struct OrderRepository;
+/// Storage for orders.
+pub trait OrderRepository {
+    async fn find_by_id(&self, order_id: Uuid) -> Result<Order, OrderError>;
+
+    /// Orders still resting, in time priority: by the event that last queued
+    /// them, then by `created_at`.
+    async fn find_open(&self) -> Result<Vec<Order>, OrderError>;
+
+    /// Orders matching `filter`, newest first.
+    async fn find_orders(
+        &self,
+        filter: &OrderFilter,
+        page: PageRequest,
+    ) -> Result<Page<Order>, OrderError>;
+
+    /// A user's resting orders across every market, newest first.
+    async fn find_open_for_user(
+        &self,
+        user_id: Uuid,
+        page: PageRequest,
+    ) -> Result<Page<Order>, OrderError> {
+        self.find_orders(&OrderFilter::open(user_id), page).await
+    }
+}
+
+/// Writes that production leaves to the engine's event stream, for seeding
+/// and inspecting tests.
+#[cfg(test)]
+pub trait OrderRepositoryExt: OrderRepository {
+    async fn create_order(&self, new_order: &Order) -> Result<Order, OrderError>;
+
+    async fn update_order_status(
+        &self,
+        order_id: Uuid,
+        new_status: OrderStatus,
+    ) -> Result<Order, OrderError>;
+
+    async fn delete_order(&self, order_id: Uuid) -> Result<usize, OrderError>;
+}
 
-impl OrderRepository {
-    pub async fn create_order(pool: &DbPool, new_order: &Order) -> Result<Order, OrderError> {
-        let client = pool.get_connection().await?;
+/// Orders in the Postgres `orders` table.
+#[derive(Clone)]
+pub struct PgOrderRepository {
+    pool: DbPool,
+}
+
+impl PgOrderRepository {
+    pub fn new(pool: DbPool) -> PgOrderRepository {
+        PgOrderRepository { pool }
+    }
+}
+
+impl OrderRepository for PgOrderRepository {
+    async fn find_by_id(&self, order_id: Uuid) -> Result<Order, OrderError> {
+        let client = self.pool.get_connection().await?;
         let row = client
-            .query_one(
-                "INSERT INTO orders (user_id, token_id, order_type, price, amount, status) 
-                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+            .query_opt("SELECT * FROM orders WHERE id = $1", &[&order_id])
+            .await?
+            .ok_or(OrderError::NotFound(order_id))?;
+        Order::from_row(&row)
+    }
+
+    async fn find_open(&self) -> Result<Vec<Order>, OrderError> {
+        let client = self.pool.get_connection().await?;
+        let rows = client
+            .query(
+                "SELECT * FROM orders
+                 WHERE status IN ('new', 'partially_filled')
+                 ORDER BY sequence NULLS FIRST, created_at, id",
+                &[],
+            )
+            .await?;
+        rows.iter().map(Order::from_row).collect()
+    }
+
+    async fn find_orders(
+        &self,
+        filter: &OrderFilter,
+        page: PageRequest,
+    ) -> Result<Page<Order>, OrderError> {
+        let client = self.pool.get_connection().await?;
+        let market = filter.pair.as_ref().map(|pair| pair.to_string());
+        let (after_created_at, after_id) = match page.after {
+            Some(OrderCursor { created_at, id }) => (Some(created_at), Some(id)),
+            None => (None, None),
+        };
+        let rows = client
+            .query(
+                "SELECT * FROM orders
+                 WHERE user_id = $1
+                   AND ($2::text IS NULL OR market = $2)
+                   AND (cardinality($3::order_status[]) = 0 OR status = ANY($3))
+                   AND ($4::timestamptz IS NULL OR created_at >= $4)
+                   AND ($5::timestamptz IS NULL OR created_at < $5)
+                   AND ($6::timestamptz IS NULL OR (created_at, id) < ($6, $7))
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT $8",
                 &[
-                    &new_order.user_id,
-                    &new_order.token_id,
-                    &new_order.order_type,
-                    &new_order.price,
-                    &new_order.amount,
-                    &new_order.status,
+                    &filter.user_id,
+                    &market,
+                    &filter.statuses,
+                    &filter.from,
+                    &filter.to,
+                    &after_created_at,
+                    &after_id,
+                    &(page.page_size() as i64 + 1),
                 ],
             )
             .await?;
-        Ok(Order::from_row(&row))
+        let orders = rows.iter().map(Order::from_row).collect::<Result<_, _>>()?;
+        Ok(Page::from_overfetch(orders, page.page_size()))
     }
+}
 
-    pub async fn find_by_id(pool: &DbPool, order_id: i32) -> Result<Order, OrderError> {
-        let client = pool.get_connection().await?;
+#[cfg(test)]
+impl OrderRepositoryExt for PgOrderRepository {
+    async fn create_order(&self, new_order: &Order) -> Result<Order, OrderError> {
+        let client = self.pool.get_connection().await?;
+        let market = new_order.pair.as_ref().map(|pair| pair.to_string());
         let row = client
-            .query_one("SELECT * FROM orders WHERE id = $1", &[&order_id])
-            .await?;
-        Ok(Order::from_row(&row))
+            .query_one(
+                "INSERT INTO orders (id, user_id, session_id, client_order_id, market, side,
+                                     status, price, size, remaining_size, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING *",
+                &[
+                    &new_order.id,
+                    &new_order.user_id,
+                    &new_order.session_id,
+                    &new_order.client_order_id,
+                    &market,
+                    &new_order.side,
+                    &new_order.status,
+                    &new_order.price,
+                    &new_order.size,
+                    &new_order.remaining_size,
+                    &new_order.created_at,
+                    &new_order.updated_at,
+                ],
+            )
+            .await
+            .map_err(|e| match e.code() {
+                Some(&SqlState::UNIQUE_VIOLATION) => OrderError::AlreadyExists(new_order.id),
+                _ => e.into(),
+            })?;
+        Order::from_row(&row)
     }
 
-    pub async fn update_order_status(
-        pool: &DbPool,
-        order_id: i32,
-        new_status: &str,
+    async fn update_order_status(
+        &self,
+        order_id: Uuid,
+        new_status: OrderStatus,
     ) -> Result<Order, OrderError> {
-        let client = pool.get_connection().await?;
+        let client = self.pool.get_connection().await?;
         let row = client
-            .query_one(
-                "UPDATE orders SET status = $1 WHERE id = $2 RETURNING *",
+            .query_opt(
+                "UPDATE orders SET status = $1, updated_at = NOW() WHERE id = $2 RETURNING *",
                 &[&new_status, &order_id],
             )
-            .await?;
-        Ok(Order::from_row(&row))
+            .await?
+            .ok_or(OrderError::NotFound(order_id))?;
+        Order::from_row(&row)
     }
 
-    pub async fn delete_order(pool: &DbPool, order_id: i32) -> Result<usize, OrderError> {
-        let client = pool.get_connection().await?;
+    async fn delete_order(&self, order_id: Uuid) -> Result<usize, OrderError> {
+        let client = self.pool.get_connection().await?;
         let result = client
             .execute("DELETE FROM orders WHERE id = $1", &[&order_id])
             .await?;
         Ok(result as usize)
     }
 }
+
+/// Orders held in memory, for running services without a database.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct InMemoryOrderRepository {
+    orders: Mutex<Vec<Order>>,
+}
+
+#[cfg(test)]
+impl InMemoryOrderRepository {
+    pub fn new() -> InMemoryOrderRepository {
+        InMemoryOrderRepository::default()
+    }
+}
+
+#[cfg(test)]
+impl OrderRepository for InMemoryOrderRepository {
+    async fn find_by_id(&self, order_id: Uuid) -> Result<Order, OrderError> {
+        let orders = self.orders.lock().unwrap();
+        orders
+            .iter()
+            .find(|order| order.id == order_id)
+            .cloned()
+            .ok_or(OrderError::NotFound(order_id))
+    }
+
+    async fn find_open(&self) -> Result<Vec<Order>, OrderError> {
+        let orders = self.orders.lock().unwrap();
+        let mut open = orders
+            .iter()
+            .filter(|order| {
+                matches!(
+                    order.status,
+                    OrderStatus::New | OrderStatus::PartiallyFilled
+                )
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        // Orders written here never have a sequence, and Postgres lists those
+        // by `created_at`, then `id`.
+        open.sort_by_key(|order| (order.created_at, order.id));
+        Ok(open)
+    }
+
+    async fn find_orders(
+        &self,
+        filter: &OrderFilter,
+        page: PageRequest,
+    ) -> Result<Page<Order>, OrderError> {
+        let orders = self.orders.lock().unwrap();
+        let mut found = orders
+            .iter()
+            .filter(|order| filter.matches(order))
+            .filter(|order| {
+                page.after
+                    .is_none_or(|after| (order.created_at, order.id) < (after.created_at, after.id))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        found.sort_by_key(|order| std::cmp::Reverse((order.created_at, order.id)));
+        found.truncate(page.page_size() + 1);
+        Ok(Page::from_overfetch(found, page.page_size()))
+    }
+}
+
+#[cfg(test)]
+impl OrderRepositoryExt for InMemoryOrderRepository {
+    async fn create_order(&self, new_order: &Order) -> Result<Order, OrderError> {
+        let mut orders = self.orders.lock().unwrap();
+        if orders.iter().any(|order| order.id == new_order.id) {
+            return Err(OrderError::AlreadyExists(new_order.id));
+        }
+        orders.push(new_order.clone());
+        Ok(new_order.clone())
+    }
+
+    async fn update_order_status(
+        &self,
+        order_id: Uuid,
+        new_status: OrderStatus,
+    ) -> Result<Order, OrderError> {
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders
+            .iter_mut()
+            .find(|order| order.id == order_id)
+            .ok_or(OrderError::NotFound(order_id))?;
+        order.status = new_status;
+        order.updated_at = chrono::Utc::now();
+        Ok(order.clone())
+    }
+
+    async fn delete_order(&self, order_id: Uuid) -> Result<usize, OrderError> {
+        let mut orders = self.orders.lock().unwrap();
+        let before = orders.len();
+        orders.retain(|order| order.id != order_id);
+        Ok(before - orders.len())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use crate::matching_engine::types::order::OrderSide;
+    use chrono::{Duration, SubsecRound, Utc};
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn orders_round_trip_through_the_database() {
+        migrate();
+        let repository = PgOrderRepository::new(DB_POOL.clone());
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let mut order = Order::new(OrderSide::Ask, Quantity(dec!(1.5)))
+            .with_pair(pair)
+            .with_user(Uuid::new_v4())
+            .with_client_order_id("round-trip");
+        order.price = Price(dec!(101.25));
+        order.remaining_size = Quantity(dec!(0.5));
+        order.status = OrderStatus::PartiallyFilled;
+
+        let created = repository.create_order(&order).await.unwrap();
+        assert_eq!(created.pair, order.pair);
+        assert_eq!(created.side, OrderSide::Ask);
+        assert_eq!(created.status, OrderStatus::PartiallyFilled);
+        assert_eq!(created.remaining_size, Quantity(dec!(0.5)));
+        assert_eq!(created.client_order_id.as_deref(), Some("round-trip"));
+
+        let cancelled = repository
+            .update_order_status(order.id, OrderStatus::Cancelled)
+            .await
+            .unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+
+        DB_POOL
+            .get_connection()
+            .await
+            .unwrap()
+            .execute(
+                "UPDATE orders SET market = 'BTCUSD' WHERE id = $1",
+                &[&order.id],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            repository.find_by_id(order.id).await,
+            Err(OrderError::Malformed { id, .. }) if id == order.id
+        ));
+        assert_eq!(repository.delete_order(order.id).await.unwrap(), 1);
+        assert!(matches!(
+            repository.find_by_id(order.id).await,
+            Err(OrderError::NotFound(id)) if id == order.id
+        ));
+    }
+
+    /// Six orders for one user, a minute apart, alternating between two
+    /// markets; the oldest two are filled.
+    async fn seed(repository: &impl OrderRepositoryExt) -> (Uuid, Vec<Order>) {
+        let user_id = Uuid::new_v4();
+        let start = Utc::now().trunc_subsecs(6) - Duration::hours(1);
+        let mut orders = Vec::new();
+        for i in 0..6 {
+            let base = if i % 2 == 0 { "BTC" } else { "ETH" };
+            let mut order = Order::new(OrderSide::Bid, Quantity(dec!(1)))
+                .with_pair(TradingPair::new(base.to_string(), "USD".to_string()))
+                .with_user(user_id);
+            order.created_at = start + Duration::minutes(i);
+            order.updated_at = order.created_at;
+            if i < 2 {
+                order.status = OrderStatus::Filled;
+            }
+            repository.create_order(&order).await.unwrap();
+            orders.push(order);
+        }
+        // A neighbour's order must never show up.
+        let other = Order::new(OrderSide::Bid, Quantity(dec!(1))).with_user(Uuid::new_v4());
+        repository.create_order(&other).await.unwrap();
+        (user_id, orders)
+    }
+
+    async fn check_queries(repository: &impl OrderRepositoryExt) {
+        let (user_id, orders) = seed(repository).await;
+        let ids = |page: &Page<Order>| page.items.iter().map(|order| order.id).collect::<Vec<_>>();
+
+        let first = repository
+            .find_orders(&OrderFilter::for_user(user_id), PageRequest::first(4))
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(&first),
+            vec![orders[5].id, orders[4].id, orders[3].id, orders[2].id]
+        );
+        let second = repository
+            .find_orders(
+                &OrderFilter::for_user(user_id),
+                PageRequest {
+                    after: first.next,
+                    limit: 4,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids(&second), vec![orders[1].id, orders[0].id]);
+        assert_eq!(second.next, None);
+
+        // Out-of-range limits are clamped rather than overflowing or
+        // returning a page that cannot continue.
+        let everything = repository
+            .find_orders(
+                &OrderFilter::for_user(user_id),
+                PageRequest::first(usize::MAX),
+            )
+            .await
+            .unwrap();
+        assert_eq!(everything.items.len(), 6);
+        let single = repository
+            .find_orders(&OrderFilter::for_user(user_id), PageRequest::first(0))
+            .await
+            .unwrap();
+        assert_eq!(ids(&single), vec![orders[5].id]);
+        assert!(single.next.is_some());
+
+        let open = repository
+            .find_open_for_user(user_id, PageRequest::first(10))
+            .await
+            .unwrap();
+        assert_eq!(open.items.len(), 4);
+
+        let filter = OrderFilter {
+            pair: orders[0].pair.clone(),
+            statuses: vec![OrderStatus::Filled],
+            from: Some(orders[0].created_at),
+            to: Some(orders[5].created_at),
+            ..OrderFilter::for_user(user_id)
+        };
+        let history = repository
+            .find_orders(&filter, PageRequest::first(10))
+            .await
+            .unwrap();
+        assert_eq!(ids(&history), vec![orders[0].id]);
+    }
+
+    #[tokio::test]
+    async fn orders_are_listed_newest_first_in_pages() {
+        check_queries(&InMemoryOrderRepository::new()).await;
+        migrate();
+        check_queries(&PgOrderRepository::new(DB_POOL.clone())).await;
+    }
+
+    #[tokio::test]
+    async fn in_memory_orders_behave_like_the_table() {
+        let repository = InMemoryOrderRepository::new();
+        let first = Order::new(OrderSide::Bid, Quantity(dec!(1)));
+        let mut second = Order::new(OrderSide::Ask, Quantity(dec!(2)));
+        second.created_at = first.created_at;
+        let mut filled = Order::new(OrderSide::Ask, Quantity(dec!(3)));
+        filled.status = OrderStatus::Filled;
+        for order in [&first, &second, &filled] {
+            repository.create_order(order).await.unwrap();
+        }
+        assert!(matches!(
+            repository.create_order(&first).await,
+            Err(OrderError::AlreadyExists(_))
+        ));
+
+        // Ties on `created_at` go to the lower id, as in the table.
+        let open = repository.find_open().await.unwrap();
+        assert_eq!(
+            open.iter().map(|order| order.id).collect::<Vec<_>>(),
+            vec![first.id.min(second.id), first.id.max(second.id)]
+        );
+
+        repository
+            .update_order_status(second.id, OrderStatus::Cancelled)
+            .await
+            .unwrap();
+        assert_eq!(repository.find_open().await.unwrap().len(), 1);
+        assert_eq!(repository.delete_order(filled.id).await.unwrap(), 1);
+        assert!(matches!(
+            repository.find_by_id(filled.id).await,
+            Err(OrderError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn open_orders_come_back_in_the_same_order_from_either_repository() {
+        migrate();
+        let postgres = PgOrderRepository::new(DB_POOL.clone());
+        let memory = InMemoryOrderRepository::new();
+        let created_at = Utc::now().trunc_subsecs(6);
+        let mut orders = Vec::new();
+        for offset in [1, 0, 1, 1] {
+            let mut order = Order::new(OrderSide::Bid, Quantity(dec!(1)))
+                .with_pair("BTC/USD".parse().unwrap())
+                .with_user(Uuid::new_v4());
+            order.created_at = created_at - Duration::seconds(offset);
+            postgres.create_order(&order).await.unwrap();
+            memory.create_order(&order).await.unwrap();
+            orders.push(order.id);
+        }
+
+        let from_memory = memory.find_open().await.unwrap();
+        let from_postgres = postgres.find_open().await.unwrap();
+        let ids = |found: Vec<Order>| {
+            found
+                .into_iter()
+                .map(|order| order.id)
+                .filter(|id| orders.contains(id))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(from_memory), ids(from_postgres));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::db::pool::DbPool;
+use crate::errors::custom_error::OrderError;
+#[cfg(test)]
+use crate::services::settlement::{EntryKind, JournalEntry};
+use crate::services::settlement::{Settlement, Transfer};
+use rust_decimal::Decimal;
+use tokio_postgres::Transaction;
+use uuid::Uuid;
+
+pub struct SettlementRepository;
+
+impl SettlementRepository {
+    /// Writes a settlement and its journal in one transaction.
+    ///
+    /// Returns `false` without writing anything if the trade is already
+    /// settled, so replaying the event stream is safe.
+    #[cfg(test)]
+    pub async fn insert(pool: &DbPool, settlement: &Settlement) -> Result<bool, OrderError> {
+        let mut client = pool.get_connection().await?;
+        let transaction = client.transaction().await?;
+        let inserted = Self::insert_with(&transaction, settlement).await?;
+        transaction.commit().await?;
+        Ok(inserted)
+    }
+
+    /// `insert` inside a transaction the caller commits.
+    pub(crate) async fn insert_with(
+        transaction: &Transaction<'_>,
+        settlement: &Settlement,
+    ) -> Result<bool, tokio_postgres::Error> {
+        let inserted = transaction
+            .execute(
+                "INSERT INTO settlements (trade_id, pair, settled_at)
+                 VALUES ($1, $2, $3) ON CONFLICT (trade_id) DO NOTHING",
+                &[
+                    &settlement.trade_id,
+                    &settlement.pair,
+                    &settlement.settled_at,
+                ],
+            )
+            .await?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        let statement = transaction
+            .prepare(
+                "INSERT INTO balance_journal (trade_id, order_id, user_id, asset, amount, kind)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .await?;
+        for entry in settlement.entries.iter() {
+            transaction
+                .execute(
+                    &statement,
+                    &[
+                        &entry.trade_id,
+                        &entry.order_id,
+                        &entry.user_id,
+                        &entry.asset,
+                        &entry.amount,
+                        &entry.kind.as_str(),
+                    ],
+                )
+                .await?;
+        }
+        Ok(true)
+    }
+
+    /// Writes a deposit or withdrawal and its journal entry inside a
+    /// transaction the caller commits. Returns `false` without writing
+    /// anything if the transfer is already stored.
+    pub(crate) async fn insert_transfer_with(
+        transaction: &Transaction<'_>,
+        transfer: &Transfer,
+    ) -> Result<bool, tokio_postgres::Error> {
+        let inserted = transaction
+            .execute(
+                "INSERT INTO transfers (id, user_id, asset, amount, transferred_at)
+                 VALUES ($1, $2, $3, $4, $5) ON CONFLICT (id) DO NOTHING",
+                &[
+                    &transfer.id,
+                    &transfer.user_id,
+                    &transfer.asset,
+                    &transfer.amount,
+                    &transfer.transferred_at,
+                ],
+            )
+            .await?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+        transaction
+            .execute(
+                "INSERT INTO balance_journal (transfer_id, user_id, asset, amount, kind)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &transfer.id,
+                    &transfer.user_id,
+                    &transfer.asset,
+                    &transfer.amount,
+                    &transfer.kind().as_str(),
+                ],
+            )
+            .await?;
+        Ok(true)
+    }
+
+    #[cfg(test)]
+    pub async fn find_journal(
+        pool: &DbPool,
+        trade_id: Uuid,
+    ) -> Result<Vec<JournalEntry>, OrderError> {
+        let client = pool.get_connection().await?;
+        let rows = client
+            .query(
+                "SELECT * FROM balance_journal WHERE trade_id = $1 ORDER BY id",
+                &[&trade_id],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| JournalEntry {
+                trade_id: row.get("trade_id"),
+                order_id: row.get("order_id"),
+                user_id: row.get("user_id"),
+                asset: row.get("asset"),
+                amount: row.get::<_, Decimal>("amount"),
+                kind: match row.get::<_, &str>("kind") {
+                    "fee" => EntryKind::Fee,
+                    "deposit" => EntryKind::Deposit,
+                    "withdrawal" => EntryKind::Withdrawal,
+                    _ => EntryKind::Trade,
+                },
+            })
+            .collect())
+    }
+
+    /// Every user's balance of every asset they hold, summed from the
+    /// journal: what was available and held when the engine stopped.
+    pub async fn balances(pool: &DbPool) -> Result<Vec<(Uuid, String, Decimal)>, OrderError> {
+        let client = pool.get_connection().await?;
+        let rows = client
+            .query(
+                "SELECT user_id, asset, SUM(amount) AS balance FROM balance_journal
+                 GROUP BY user_id, asset
+                 HAVING SUM(amount) <> 0",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("user_id"), row.get("asset"), row.get("balance")))
+            .collect())
+    }
+
+    /// A user's settled balance of `asset`, summed from the journal.
+    #[cfg(test)]
+    pub async fn balance(pool: &DbPool, user_id: Uuid, asset: &str) -> Result<Decimal, OrderError> {
+        let client = pool.get_connection().await?;
+        let row = client
+            .query_one(
+                "SELECT COALESCE(SUM(amount), 0) AS balance FROM balance_journal
+                 WHERE user_id = $1 AND asset = $2",
+                &[&user_id, &asset],
+            )
+            .await?;
+        Ok(row.get("balance"))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use crate::matching_engine::types::order::OrderSide;
+    use crate::matching_engine::types::trade::Trade;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn settlements_are_written_once() {
+        migrate();
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let trade = Trade::new(
+            (Uuid::new_v4(), buyer),
+            (Uuid::new_v4(), seller),
+            Some(OrderSide::Ask),
+            Price(dec!(100.5)),
+            Quantity(dec!(0.25)),
+        );
+        let settlement = Settlement::for_trade(&pair, &trade, Uuid::nil());
+
+        assert!(SettlementRepository::insert(&DB_POOL, &settlement)
+            .await
+            .unwrap());
+        assert!(!SettlementRepository::insert(&DB_POOL, &settlement)
+            .await
+            .unwrap());
+
+        let journal = SettlementRepository::find_journal(&DB_POOL, trade.id)
+            .await
+            .unwrap();
+        assert_eq!(journal, settlement.entries);
+        assert_eq!(
+            SettlementRepository::balance(&DB_POOL, seller, "USD")
+                .await
+                .unwrap(),
+            dec!(25.125)
+        );
+    }
+}
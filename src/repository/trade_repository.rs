@@ -0,0 +1,216 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio_postgres::{Statement, Transaction};
+use uuid::Uuid;
+
+use crate::db::pool::DbPool;
+use crate::domain::trade::ExecutedTrade;
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::types::decimal::Notional;
+
+pub struct TradeRepository;
+
+/// Quote notional one user traded on one UTC day, across every market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyVolume {
+    pub user_id: Uuid,
+    pub day: NaiveDate,
+    pub notional: Notional,
+}
+
+impl TradeRepository {
+    pub(crate) const INSERT: &'static str =
+        "INSERT INTO trades (id, sequence, market, buy_order_id, buyer_id, sell_order_id,
+                             seller_id, aggressor, maker_order_id, taker_order_id, price,
+                             quantity, buyer_fee_rate, buyer_fee_amount, buyer_fee_asset,
+                             seller_fee_rate, seller_fee_amount, seller_fee_asset, executed_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                 $18, $19)
+         ON CONFLICT DO NOTHING";
+
+    /// Inserts one trade with a prepared `INSERT`, returning 0 if it is
+    /// already stored.
+    pub(crate) async fn insert_with(
+        transaction: &Transaction<'_>,
+        statement: &Statement,
+        executed: &ExecutedTrade,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let trade = &executed.trade;
+        let (buyer_fee, seller_fee) = (trade.buyer_fee.as_ref(), trade.seller_fee.as_ref());
+        transaction
+            .execute(
+                statement,
+                &[
+                    &trade.id,
+                    &(executed.sequence as i64),
+                    &executed.pair.to_string(),
+                    &trade.buy_order_id,
+                    &trade.buyer_id,
+                    &trade.sell_order_id,
+                    &trade.seller_id,
+                    &trade.aggressor,
+                    &trade.maker_order_id(),
+                    &trade.taker_order_id(),
+                    &trade.price,
+                    &trade.quantity,
+                    &buyer_fee.map(|fee| fee.rate),
+                    &buyer_fee.map(|fee| fee.amount),
+                    &buyer_fee.map(|fee| fee.asset.as_str()),
+                    &seller_fee.map(|fee| fee.rate),
+                    &seller_fee.map(|fee| fee.amount),
+                    &seller_fee.map(|fee| fee.asset.as_str()),
+                    &trade.executed_at,
+                ],
+            )
+            .await
+    }
+
+    /// Writes a batch of trades in one transaction and returns how many were
+    /// new. Trades that are already stored are skipped, so replaying the
+    /// event stream is safe.
+    #[cfg(test)]
+    pub async fn insert_batch(pool: &DbPool, trades: &[ExecutedTrade]) -> Result<u64, OrderError> {
+        if trades.is_empty() {
+            return Ok(0);
+        }
+        let mut client = pool.get_connection().await?;
+        let transaction = client.transaction().await?;
+        let statement = transaction.prepare(Self::INSERT).await?;
+        let mut inserted = 0;
+        for executed in trades.iter() {
+            inserted += Self::insert_with(&transaction, &statement, executed).await?;
+        }
+        transaction.commit().await?;
+        Ok(inserted)
+    }
+
+    /// Trades a user took part in on either side, oldest first.
+    pub async fn find_for_user(
+        pool: &DbPool,
+        user_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ExecutedTrade>, OrderError> {
+        let client = pool.get_connection().await?;
+        let rows = client
+            .query(
+                "SELECT * FROM trades
+                 WHERE (buyer_id = $1 OR seller_id = $1)
+                   AND executed_at >= $2 AND executed_at < $3
+                 ORDER BY executed_at, sequence",
+                &[&user_id, &from, &to],
+            )
+            .await?;
+        Ok(rows.iter().map(ExecutedTrade::from_row).collect())
+    }
+
+    /// Each user's traded notional per day since `from`, oldest day first.
+    /// A trade counts once for each side, and once for a user on both.
+    pub async fn daily_volumes(
+        pool: &DbPool,
+        from: DateTime<Utc>,
+    ) -> Result<Vec<DailyVolume>, OrderError> {
+        let client = pool.get_connection().await?;
+        let rows = client
+            .query(
+                "SELECT user_id, (executed_at AT TIME ZONE 'UTC')::date AS day,
+                        SUM(price * quantity) AS notional
+                 FROM (SELECT buyer_id AS user_id, executed_at, price, quantity
+                       FROM trades WHERE executed_at >= $1
+                       UNION ALL
+                       SELECT seller_id, executed_at, price, quantity
+                       FROM trades WHERE executed_at >= $1 AND seller_id <> buyer_id) sides
+                 GROUP BY user_id, day
+                 ORDER BY day, user_id",
+                &[&from],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| DailyVolume {
+                user_id: row.get("user_id"),
+                day: row.get("day"),
+                notional: row.get("notional"),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::fees::{Fee, Liquidity};
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use crate::matching_engine::types::order::OrderSide;
+    use crate::matching_engine::types::trade::Trade;
+    use chrono::{Duration, SubsecRound};
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn trades_are_batched_and_found_by_user() {
+        migrate();
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut taker = Trade::new(
+            (Uuid::new_v4(), buyer),
+            (Uuid::new_v4(), seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(0.5)),
+        );
+        // Postgres keeps microseconds.
+        taker.executed_at = taker.executed_at.trunc_subsecs(6);
+        taker.buyer_fee = Some(Fee {
+            user_id: buyer,
+            liquidity: Liquidity::Taker,
+            rate: dec!(0.002),
+            amount: dec!(0.1),
+            asset: "USD".to_string(),
+        });
+        let mut auction = Trade::new(
+            (Uuid::new_v4(), Uuid::new_v4()),
+            (Uuid::new_v4(), buyer),
+            None,
+            Price(dec!(101)),
+            Quantity(dec!(1)),
+        );
+        auction.executed_at = taker.executed_at + Duration::seconds(1);
+        let batch = vec![
+            ExecutedTrade {
+                sequence: 7,
+                pair: pair.clone(),
+                trade: taker,
+            },
+            ExecutedTrade {
+                sequence: 9,
+                pair,
+                trade: auction,
+            },
+        ];
+
+        assert_eq!(
+            TradeRepository::insert_batch(&DB_POOL, &batch)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            TradeRepository::insert_batch(&DB_POOL, &batch[..1])
+                .await
+                .unwrap(),
+            0
+        );
+
+        let day = batch[0].trade.executed_at - Duration::hours(1);
+        let found = TradeRepository::find_for_user(&DB_POOL, buyer, day, day + Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(found, batch);
+        assert_eq!(
+            found[0].trade.maker_order_id(),
+            Some(found[0].trade.sell_order_id)
+        );
+    }
+}
@@ -1 +1,5 @@
 pub mod order_service;
+pub mod payment_gateway;
+pub mod persistence;
+pub mod recovery;
+pub mod settlement;
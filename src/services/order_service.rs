@@ -1,29 +1,199 @@
-use rust_decimal::prelude::Zero;
+use uuid::Uuid;
 
-use crate::db::pool::DbPool;
-use crate::domain::order::Order;
-use crate::matching_engine::types::decimal::Price;
+use crate::domain::order::{Order, OrderFilter, Page, PageRequest};
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::pipeline::{CommandPublisher, EngineCommand, Reply};
+use crate::matching_engine::ring_buffer::WaitStrategy;
+use crate::matching_engine::types::decimal::{Notional, Price, Quantity};
 use crate::repository::order_repository::OrderRepository;
 
-pub struct OrderService;
+pub struct OrderService<R: OrderRepository> {
+    repository: R,
+}
+
+impl<R: OrderRepository> OrderService<R> {
+    pub fn new(repository: R) -> OrderService<R> {
+        OrderService { repository }
+    }
 
-impl OrderService {
-    pub async fn create_order(pool: &DbPool, new_order: &Order) -> Result<Order, String> {
-        if new_order.amount <= Price::from(rust_decimal::Decimal::zero()) {
-            return Err("Order amount must be greater than zero".to_string());
+    /// Checks what every order needs and returns the market it names.
+    fn validate(order: &Order) -> Result<TradingPair, String> {
+        let Some(pair) = order.pair.clone() else {
+            return Err("Order must name a market".to_string());
+        };
+        if order.size <= Quantity::ZERO {
+            return Err("Order size must be greater than zero".to_string());
         }
-        if new_order.price <= Price::from(rust_decimal::Decimal::zero()) {
+        Ok(pair)
+    }
+
+    fn validate_limit(order: &Order) -> Result<TradingPair, String> {
+        let pair = Self::validate(order)?;
+        if order.price <= Price::ZERO {
             return Err("Order price must be greater than zero".to_string());
         }
+        match order.price.checked_mul(order.size) {
+            Some(notional) if notional > Notional::ZERO => {}
+            Some(_) => return Err("Order notional must be greater than zero".to_string()),
+            None => return Err("Order notional is out of range".to_string()),
+        }
+        Ok(pair)
+    }
+
+    /// Validates `order` and hands it to the matching engine as a limit order
+    /// at `order.price` in `order.pair`, returning where the engine's answer
+    /// will arrive. Fails rather than waits if the engine's inbound ring is
+    /// full.
+    pub fn place_order<W: WaitStrategy>(
+        &self,
+        publisher: &CommandPublisher<W>,
+        order: Order,
+    ) -> Result<Reply, String> {
+        let pair = Self::validate_limit(&order)?;
+        Self::publish(
+            publisher,
+            EngineCommand::PlaceLimitOrder {
+                pair,
+                price: order.price,
+                order,
+            },
+        )
+    }
 
-        Ok(OrderRepository::create_order(pool, new_order)
+    /// Validates `order` and hands it to the matching engine as a market
+    /// order in `order.pair`; its price is ignored.
+    pub fn place_market_order<W: WaitStrategy>(
+        &self,
+        publisher: &CommandPublisher<W>,
+        order: Order,
+    ) -> Result<Reply, String> {
+        let pair = Self::validate(&order)?;
+        Self::publish(publisher, EngineCommand::PlaceMarketOrder { pair, order })
+    }
+
+    fn publish<W: WaitStrategy>(
+        publisher: &CommandPublisher<W>,
+        command: EngineCommand,
+    ) -> Result<Reply, String> {
+        publisher
+            .try_submit(command)
+            .map_err(|_| "The engine is busy, try again".to_string())
+    }
+
+    pub async fn get_order(&self, order_id: Uuid) -> Result<Order, String> {
+        self.repository
+            .find_by_id(order_id)
             .await
-            .unwrap())
+            .map_err(|e| e.to_string())
     }
 
-    pub async fn get_order(pool: &DbPool, order_id: i32) -> Result<Order, String> {
-        OrderRepository::find_by_id(pool, order_id)
+    pub async fn open_orders(
+        &self,
+        user_id: Uuid,
+        page: PageRequest,
+    ) -> Result<Page<Order>, String> {
+        self.repository
+            .find_open_for_user(user_id, Self::clamp(page))
             .await
             .map_err(|e| e.to_string())
     }
+
+    pub async fn order_history(
+        &self,
+        filter: &OrderFilter,
+        page: PageRequest,
+    ) -> Result<Page<Order>, String> {
+        self.repository
+            .find_orders(filter, Self::clamp(page))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    fn clamp(page: PageRequest) -> PageRequest {
+        PageRequest {
+            limit: page.page_size(),
+            ..page
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::domain::order::MAX_PAGE_SIZE;
+    use crate::matching_engine::engine::MatchingEngine;
+    use crate::matching_engine::events::EngineEvent;
+    use crate::matching_engine::pipeline::pipeline;
+    use crate::matching_engine::ring_buffer::BusySpinWait;
+    use crate::matching_engine::types::order::OrderSide;
+    use crate::repository::order_repository::{InMemoryOrderRepository, OrderRepositoryExt};
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn placed_orders_reach_the_engine() {
+        let pair: TradingPair = "BTC/USD".parse().unwrap();
+        let (publisher, subscriber, runner) = pipeline(MatchingEngine::new(), 8, 8, BusySpinWait);
+        publisher.publish(EngineCommand::AddMarket { pair: pair.clone() });
+
+        let mut order = Order::new(OrderSide::Bid, Quantity(dec!(2))).with_pair(pair.clone());
+        order.price = Price(dec!(250));
+        let service = OrderService::new(InMemoryOrderRepository::new());
+        assert!(service.place_order(&publisher, order.clone()).is_ok());
+        order.pair = None;
+        assert!(service.place_order(&publisher, order.clone()).is_err());
+        order.pair = Some(pair.clone());
+        order.size = Quantity::ZERO;
+        assert_eq!(
+            service.place_order(&publisher, order.clone()).err(),
+            Some("Order size must be greater than zero".to_string())
+        );
+        // Market orders are held to the same checks, apart from the price.
+        assert_eq!(
+            service.place_market_order(&publisher, order.clone()).err(),
+            Some("Order size must be greater than zero".to_string())
+        );
+        order.pair = None;
+        order.size = Quantity(dec!(1));
+        assert_eq!(
+            service.place_market_order(&publisher, order).err(),
+            Some("Order must name a market".to_string())
+        );
+        publisher.publish(EngineCommand::Shutdown);
+
+        let engine = runner.run();
+        subscriber.next();
+        match subscriber.next().event {
+            EngineEvent::OrderAccepted {
+                pair: accepted_in,
+                price,
+                ..
+            } => {
+                assert_eq!(accepted_in, pair);
+                assert_eq!(price, Price(dec!(250)));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        let book = engine.market(&pair).unwrap().orderbook();
+        assert_eq!(book.open_orders_for(Uuid::nil()), 1);
+    }
+
+    #[tokio::test]
+    async fn pages_are_capped() {
+        let repository = InMemoryOrderRepository::new();
+        let user_id = Uuid::new_v4();
+        for _ in 0..MAX_PAGE_SIZE + 1 {
+            let order = Order::new(OrderSide::Bid, Quantity(dec!(1)))
+                .with_pair("BTC/USD".parse().unwrap())
+                .with_user(user_id);
+            repository.create_order(&order).await.unwrap();
+        }
+        let service = OrderService::new(repository);
+
+        let page = service
+            .open_orders(user_id, PageRequest::first(usize::MAX))
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), MAX_PAGE_SIZE);
+        assert!(page.next.is_some());
+    }
 }
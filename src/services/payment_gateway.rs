@@ -0,0 +1,772 @@
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use uuid::Uuid;
+
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::types::decimal::{Price, Quantity};
+use crate::matching_engine::types::order::OrderSide;
+use crate::matching_engine::types::trade::Trade;
+use crate::services::persistence::PersistedSequence;
+use crate::services::settlement::{EntryKind, Settlement};
+
+/// A user's holdings of one asset.
+///
+/// `held` is reserved by open orders and cannot back a new one until it is
+/// released or spent by a fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl Balance {
+    #[cfg(test)]
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BalanceError {
+    InsufficientFunds {
+        user_id: Uuid,
+        asset: String,
+        required: Decimal,
+        available: Decimal,
+    },
+    NonPositiveAmount(Decimal),
+    DuplicateHold(Uuid),
+    AmountOutOfRange {
+        price: Price,
+        quantity: Quantity,
+    },
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::InsufficientFunds {
+                user_id,
+                asset,
+                required,
+                available,
+            } => write!(
+                f,
+                "user {} needs {} {} but only {} is available",
+                user_id, required, asset, available
+            ),
+            BalanceError::NonPositiveAmount(amount) => {
+                write!(f, "amount {} must be greater than zero", amount)
+            }
+            BalanceError::DuplicateHold(order_id) => {
+                write!(f, "order {} already has funds reserved", order_id)
+            }
+            BalanceError::AmountOutOfRange { price, quantity } => write!(
+                f,
+                "{} at {} is too large an amount to reserve",
+                quantity.0, price.0
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+/// Funds set aside for one open order.
+#[derive(Debug, Clone, PartialEq)]
+struct Hold {
+    user_id: Uuid,
+    asset: String,
+    amount: Decimal,
+    /// Quantity the order can still fill; the hold is released once it is zero.
+    open_quantity: Quantity,
+}
+
+/// In-memory account ledger that backs order entry.
+///
+/// Bids reserve quote, asks reserve base. Fills move reserved funds to the
+/// counterparty, and whatever an order did not spend is released when it
+/// completes or is cancelled.
+#[derive(Debug, Default)]
+pub struct PaymentGateway {
+    balances: HashMap<(Uuid, String), Balance>,
+    holds: HashMap<Uuid, Hold>,
+    /// Collects fees, and pays out maker rebates.
+    fee_account: Uuid,
+    /// Trades settled here, with the sequence number of the event that
+    /// publishes each settlement, oldest first.
+    settled: HashSet<Uuid>,
+    settled_order: VecDeque<(u64, Uuid)>,
+    /// How far the settlement journal has reached the database, if it is
+    /// persisted at all.
+    journal: Option<PersistedSequence>,
+}
+
+impl PaymentGateway {
+    #[cfg(test)]
+    pub fn new() -> PaymentGateway {
+        PaymentGateway::default()
+    }
+
+    /// Books fees to `fee_account` instead of the nil user.
+    pub fn with_fee_account(fee_account: Uuid) -> PaymentGateway {
+        PaymentGateway {
+            fee_account,
+            ..PaymentGateway::default()
+        }
+    }
+
+    /// Hands idempotency over to the persisted journal: trades whose
+    /// settlement events are at or below `journal` are forgotten here, since
+    /// the `settlements` table already refuses to settle them twice.
+    pub fn set_journal(&mut self, journal: PersistedSequence) {
+        self.journal = Some(journal);
+    }
+
+    pub fn balance(&self, user_id: Uuid, asset: &str) -> Balance {
+        self.balances
+            .get(&(user_id, asset.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn balance_mut(&mut self, user_id: Uuid, asset: &str) -> &mut Balance {
+        self.balances
+            .entry((user_id, asset.to_string()))
+            .or_default()
+    }
+
+    pub fn deposit(
+        &mut self,
+        user_id: Uuid,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::NonPositiveAmount(amount));
+        }
+        self.balance_mut(user_id, asset).available += amount;
+        Ok(())
+    }
+
+    pub fn withdraw(
+        &mut self,
+        user_id: Uuid,
+        asset: &str,
+        amount: Decimal,
+    ) -> Result<(), BalanceError> {
+        if amount <= Decimal::ZERO {
+            return Err(BalanceError::NonPositiveAmount(amount));
+        }
+        let balance = self.balance_mut(user_id, asset);
+        if balance.available < amount {
+            return Err(BalanceError::InsufficientFunds {
+                user_id,
+                asset: asset.to_string(),
+                required: amount,
+                available: balance.available,
+            });
+        }
+        balance.available -= amount;
+        Ok(())
+    }
+
+    /// Sets a user's `asset` back to `amount` available, its settled balance
+    /// from before a restart. Holds are taken again as the open orders are
+    /// restored.
+    pub fn restore_balance(&mut self, user_id: Uuid, asset: &str, amount: Decimal) {
+        self.balance_mut(user_id, asset).available = amount;
+    }
+
+    /// Amount an order has reserved, if it has a hold.
+    #[cfg(test)]
+    pub fn held_for(&self, order_id: Uuid) -> Option<Decimal> {
+        self.holds.get(&order_id).map(|hold| hold.amount)
+    }
+
+    /// Reserves what an order on `pair` can spend: `quantity * price` of the
+    /// quote for a bid, `quantity` of the base for an ask.
+    #[cfg(test)]
+    pub fn reserve(
+        &mut self,
+        pair: &TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        side: OrderSide,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<(), BalanceError> {
+        self.reserve_with_fee(
+            pair,
+            order_id,
+            user_id,
+            side,
+            price,
+            quantity,
+            Decimal::ZERO,
+        )
+    }
+
+    /// `reserve`, plus `fee_rate` of the amount for fees charged in the
+    /// reserved asset.
+    #[allow(clippy::too_many_arguments)]
+    pub fn reserve_with_fee(
+        &mut self,
+        pair: &TradingPair,
+        order_id: Uuid,
+        user_id: Uuid,
+        side: OrderSide,
+        price: Price,
+        quantity: Quantity,
+        fee_rate: Decimal,
+    ) -> Result<(), BalanceError> {
+        if self.holds.contains_key(&order_id) {
+            return Err(BalanceError::DuplicateHold(order_id));
+        }
+        let (asset, amount) = match side {
+            OrderSide::Bid => (
+                pair.quote(),
+                price
+                    .checked_mul(quantity)
+                    .ok_or(BalanceError::AmountOutOfRange { price, quantity })?
+                    .0,
+            ),
+            OrderSide::Ask => (pair.base(), quantity.0),
+        };
+        let amount = amount
+            .checked_mul(Decimal::ONE + fee_rate)
+            .ok_or(BalanceError::AmountOutOfRange { price, quantity })?;
+        let balance = self.balance_mut(user_id, asset);
+        if balance.available < amount {
+            return Err(BalanceError::InsufficientFunds {
+                user_id,
+                asset: asset.to_string(),
+                required: amount,
+                available: balance.available,
+            });
+        }
+        balance.available -= amount;
+        balance.held += amount;
+        self.holds.insert(
+            order_id,
+            Hold {
+                user_id,
+                asset: asset.to_string(),
+                amount,
+                open_quantity: quantity,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns an order's unspent reservation to the user's available balance.
+    pub fn release(&mut self, order_id: Uuid) -> Decimal {
+        let Some(hold) = self.holds.remove(&order_id) else {
+            return Decimal::ZERO;
+        };
+        let balance = self.balance_mut(hold.user_id, &hold.asset);
+        balance.held -= hold.amount;
+        balance.available += hold.amount;
+        hold.amount
+    }
+
+    /// Spends `amount` of `asset` for an order, from its hold first.
+    ///
+    /// A debit in another asset than the hold, such as an ask's fee charged
+    /// in the quote it just received, is paid from the available balance;
+    /// `check_covered` has made sure that is enough.
+    fn spend(
+        &mut self,
+        order_id: Uuid,
+        user_id: Uuid,
+        asset: &str,
+        amount: Decimal,
+        filled: Quantity,
+    ) {
+        let from_hold = match self.holds.get_mut(&order_id) {
+            Some(hold) if hold.asset == asset => {
+                let from_hold = amount.min(hold.amount);
+                hold.amount -= from_hold;
+                hold.open_quantity -= filled.min(hold.open_quantity);
+                from_hold
+            }
+            _ => Decimal::ZERO,
+        };
+        let balance = self.balance_mut(user_id, asset);
+        balance.held -= from_hold;
+        balance.available -= amount - from_hold;
+
+        if self
+            .holds
+            .get(&order_id)
+            .is_some_and(|hold| hold.open_quantity.is_zero())
+        {
+            self.release(order_id);
+        }
+    }
+
+    /// Fails if applying `settlement` would take any balance below zero.
+    ///
+    /// Entries are walked in order, so a credit can fund a later debit. An
+    /// order's debit draws on its hold first when it is in the hold's asset,
+    /// anything else only on the available balance.
+    fn check_covered(&self, settlement: &Settlement) -> Result<(), BalanceError> {
+        let mut holds: HashMap<(Uuid, &str), Decimal> = HashMap::new();
+        let mut available: HashMap<(Uuid, &str), Decimal> = HashMap::new();
+        for entry in settlement.entries.iter() {
+            let balance = available
+                .entry((entry.user_id, entry.asset.as_str()))
+                .or_insert_with(|| self.balance(entry.user_id, &entry.asset).available);
+            if entry.amount >= Decimal::ZERO {
+                *balance += entry.amount;
+                continue;
+            }
+            let mut required = -entry.amount;
+            if let Some(order_id) = entry.order_id {
+                let hold = holds
+                    .entry((order_id, entry.asset.as_str()))
+                    .or_insert_with(|| {
+                        self.holds
+                            .get(&order_id)
+                            .filter(|hold| hold.asset == entry.asset)
+                            .map_or(Decimal::ZERO, |hold| hold.amount)
+                    });
+                let from_hold = required.min(*hold);
+                *hold -= from_hold;
+                required -= from_hold;
+            }
+            if *balance < required {
+                return Err(BalanceError::InsufficientFunds {
+                    user_id: entry.user_id,
+                    asset: entry.asset.clone(),
+                    required,
+                    available: *balance,
+                });
+            }
+            *balance -= required;
+        }
+        Ok(())
+    }
+
+    /// Settles a trade: turns the holds behind it into transfers between the
+    /// counterparties and moves fees to the fee account.
+    ///
+    /// Every movement is applied together and returned as a journal, to be
+    /// published as event `sequence`. A trade that was already settled is
+    /// ignored and yields `None`. If any side cannot cover its debits nothing
+    /// moves, and the trade stays unsettled.
+    pub fn settle_trade(
+        &mut self,
+        pair: &TradingPair,
+        trade: &Trade,
+        sequence: u64,
+    ) -> Result<Option<Settlement>, BalanceError> {
+        self.forget_persisted();
+        if self.settled.contains(&trade.id) {
+            return Ok(None);
+        }
+        let settlement = Settlement::for_trade(pair, trade, self.fee_account);
+        debug_assert!(
+            settlement.is_balanced(),
+            "{:?} does not net to zero",
+            settlement
+        );
+        self.check_covered(&settlement)?;
+        self.settled.insert(trade.id);
+        self.settled_order.push_back((sequence, trade.id));
+        for entry in settlement.entries.iter() {
+            // Only the trade debit counts towards the order's fills.
+            let filled = match entry.kind {
+                EntryKind::Trade => trade.quantity,
+                _ => Quantity::ZERO,
+            };
+            match entry.order_id {
+                Some(order_id) => {
+                    self.spend(order_id, entry.user_id, &entry.asset, -entry.amount, filled)
+                }
+                None => self.balance_mut(entry.user_id, &entry.asset).available += entry.amount,
+            }
+        }
+        Ok(Some(settlement))
+    }
+
+    /// Whether the trade was settled here and is not yet known to be in the
+    /// persisted journal.
+    #[cfg(test)]
+    pub fn is_settled(&self, trade_id: Uuid) -> bool {
+        self.settled.contains(&trade_id)
+    }
+
+    fn forget_persisted(&mut self) {
+        let Some(persisted) = self.journal.as_ref().map(PersistedSequence::get) else {
+            return;
+        };
+        while let Some(&(sequence, trade_id)) = self.settled_order.front() {
+            if sequence > persisted {
+                break;
+            }
+            self.settled.remove(&trade_id);
+            self.settled_order.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn btc_usd() -> TradingPair {
+        TradingPair::new("BTC".to_string(), "USD".to_string())
+    }
+
+    #[tokio::test]
+    async fn reserving_more_than_available_is_rejected() {
+        let mut gateway = PaymentGateway::new();
+        let user = Uuid::new_v4();
+        gateway.deposit(user, "USD", dec!(150)).unwrap();
+
+        let result = gateway.reserve(
+            &btc_usd(),
+            Uuid::new_v4(),
+            user,
+            OrderSide::Bid,
+            Price(dec!(100)),
+            Quantity(dec!(2)),
+        );
+        assert_eq!(
+            result,
+            Err(BalanceError::InsufficientFunds {
+                user_id: user,
+                asset: "USD".to_string(),
+                required: dec!(200),
+                available: dec!(150),
+            })
+        );
+        assert_eq!(gateway.balance(user, "USD").held, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn release_returns_the_hold() {
+        let mut gateway = PaymentGateway::new();
+        let (user, order) = (Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(user, "BTC", dec!(3)).unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                order,
+                user,
+                OrderSide::Ask,
+                Price(dec!(100)),
+                Quantity(dec!(2)),
+            )
+            .unwrap();
+        assert_eq!(
+            gateway.balance(user, "BTC"),
+            Balance {
+                available: dec!(1),
+                held: dec!(2)
+            }
+        );
+
+        assert_eq!(gateway.release(order), dec!(2));
+        assert_eq!(gateway.balance(user, "BTC").available, dec!(3));
+        assert_eq!(gateway.release(order), Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn fills_transfer_held_funds_and_release_price_improvement() {
+        let mut gateway = PaymentGateway::new();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let (bid, ask) = (Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(buyer, "USD", dec!(1_000)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(5)).unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                bid,
+                buyer,
+                OrderSide::Bid,
+                Price(dec!(101)),
+                Quantity(dec!(2)),
+            )
+            .unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                ask,
+                seller,
+                OrderSide::Ask,
+                Price(dec!(100)),
+                Quantity(dec!(5)),
+            )
+            .unwrap();
+
+        let trade = Trade::new(
+            (bid, buyer),
+            (ask, seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(2)),
+        );
+        gateway.settle_trade(&btc_usd(), &trade, 1).unwrap();
+
+        assert_eq!(
+            gateway.balance(buyer, "USD"),
+            Balance {
+                available: dec!(800),
+                held: Decimal::ZERO
+            }
+        );
+        assert_eq!(gateway.balance(buyer, "BTC").available, dec!(2));
+        assert_eq!(gateway.held_for(bid), None);
+        assert_eq!(
+            gateway.balance(seller, "BTC"),
+            Balance {
+                available: Decimal::ZERO,
+                held: dec!(3)
+            }
+        );
+        assert_eq!(gateway.balance(seller, "USD").available, dec!(200));
+    }
+
+    #[tokio::test]
+    async fn settlement_moves_fees_and_is_idempotent() {
+        use crate::matching_engine::fees::{Fee, Liquidity};
+
+        let fee_account = Uuid::new_v4();
+        let mut gateway = PaymentGateway::with_fee_account(fee_account);
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let (bid, ask) = (Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(buyer, "USD", dec!(200)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(1)).unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                bid,
+                buyer,
+                OrderSide::Bid,
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+            )
+            .unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                ask,
+                seller,
+                OrderSide::Ask,
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+            )
+            .unwrap();
+
+        let mut trade = Trade::new(
+            (bid, buyer),
+            (ask, seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(1)),
+        );
+        let fee = |user_id, liquidity, amount| Fee {
+            user_id,
+            liquidity,
+            rate: Decimal::ZERO,
+            amount,
+            asset: "USD".to_string(),
+        };
+        trade.buyer_fee = Some(fee(buyer, Liquidity::Taker, dec!(0.2)));
+        trade.seller_fee = Some(fee(seller, Liquidity::Maker, dec!(-0.05)));
+
+        let settlement = gateway
+            .settle_trade(&btc_usd(), &trade, 1)
+            .unwrap()
+            .unwrap();
+        assert!(settlement.is_balanced());
+        assert_eq!(settlement.entries.len(), 8);
+        assert_eq!(gateway.balance(buyer, "USD").available, dec!(99.8));
+        assert_eq!(gateway.balance(buyer, "BTC").available, dec!(1));
+        assert_eq!(gateway.balance(seller, "USD").available, dec!(100.05));
+        assert_eq!(gateway.balance(fee_account, "USD").available, dec!(0.15));
+
+        assert_eq!(gateway.settle_trade(&btc_usd(), &trade, 1), Ok(None));
+        assert_eq!(gateway.balance(buyer, "BTC").available, dec!(1));
+    }
+
+    #[tokio::test]
+    async fn trades_that_would_overdraw_are_not_settled() {
+        let mut gateway = PaymentGateway::new();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let ask = Uuid::new_v4();
+        gateway.deposit(buyer, "USD", dec!(50)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(1)).unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                ask,
+                seller,
+                OrderSide::Ask,
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+            )
+            .unwrap();
+
+        // The bid has no hold and the buyer cannot pay for it.
+        let trade = Trade::new(
+            (Uuid::new_v4(), buyer),
+            (ask, seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(1)),
+        );
+        assert_eq!(
+            gateway.settle_trade(&btc_usd(), &trade, 1),
+            Err(BalanceError::InsufficientFunds {
+                user_id: buyer,
+                asset: "USD".to_string(),
+                required: dec!(100),
+                available: dec!(50),
+            })
+        );
+        assert!(!gateway.is_settled(trade.id));
+        assert_eq!(gateway.balance(buyer, "USD").available, dec!(50));
+        assert_eq!(gateway.held_for(ask), Some(dec!(1)));
+
+        gateway.deposit(buyer, "USD", dec!(50)).unwrap();
+        assert!(gateway
+            .settle_trade(&btc_usd(), &trade, 1)
+            .unwrap()
+            .is_some());
+        assert_eq!(gateway.balance(buyer, "USD").available, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn fees_in_the_reserved_asset_are_held_with_the_order() {
+        use crate::matching_engine::fees::{Fee, Liquidity};
+
+        let mut gateway = PaymentGateway::new();
+        let (buyer, seller, bid) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(buyer, "USD", dec!(100.2)).unwrap();
+        gateway
+            .reserve_with_fee(
+                &btc_usd(),
+                bid,
+                buyer,
+                OrderSide::Bid,
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+                dec!(0.002),
+            )
+            .unwrap();
+        assert_eq!(gateway.held_for(bid), Some(dec!(100.2)));
+
+        let mut trade = Trade::new(
+            (bid, buyer),
+            (Uuid::new_v4(), seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(0.5)),
+        );
+        trade.buyer_fee = Some(Fee {
+            user_id: buyer,
+            liquidity: Liquidity::Taker,
+            rate: dec!(0.002),
+            amount: dec!(0.1),
+            asset: "USD".to_string(),
+        });
+        gateway.deposit(seller, "BTC", dec!(0.5)).unwrap();
+        gateway
+            .settle_trade(&btc_usd(), &trade, 1)
+            .unwrap()
+            .unwrap();
+        // The fee came out of the hold, not the empty available balance.
+        assert_eq!(
+            gateway.balance(buyer, "USD"),
+            Balance {
+                available: Decimal::ZERO,
+                held: dec!(50.1)
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn fees_in_another_asset_leave_the_hold_alone() {
+        use crate::matching_engine::fees::{Fee, Liquidity};
+
+        let mut gateway = PaymentGateway::new();
+        let (buyer, seller, ask) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(seller, "BTC", dec!(1)).unwrap();
+        gateway
+            .reserve(
+                &btc_usd(),
+                ask,
+                seller,
+                OrderSide::Ask,
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+            )
+            .unwrap();
+
+        // The ask's fee is charged in the quote it receives.
+        let mut trade = Trade::new(
+            (Uuid::new_v4(), buyer),
+            (ask, seller),
+            Some(OrderSide::Bid),
+            Price(dec!(100)),
+            Quantity(dec!(0.5)),
+        );
+        trade.seller_fee = Some(Fee {
+            user_id: seller,
+            liquidity: Liquidity::Maker,
+            rate: dec!(0.002),
+            amount: dec!(0.1),
+            asset: "USD".to_string(),
+        });
+        gateway.deposit(buyer, "USD", dec!(50)).unwrap();
+        gateway
+            .settle_trade(&btc_usd(), &trade, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(gateway.held_for(ask), Some(dec!(0.5)));
+        assert_eq!(gateway.balance(seller, "BTC").held, dec!(0.5));
+        assert_eq!(
+            gateway.balance(seller, "USD"),
+            Balance {
+                available: dec!(49.9),
+                held: Decimal::ZERO
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn settled_trades_are_forgotten_once_persisted() {
+        let mut gateway = PaymentGateway::new();
+        let journal = PersistedSequence::new(0);
+        gateway.set_journal(journal.clone());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        gateway.deposit(buyer, "USD", dec!(200)).unwrap();
+        gateway.deposit(seller, "BTC", dec!(2)).unwrap();
+        let trade = || {
+            Trade::new(
+                (Uuid::new_v4(), buyer),
+                (Uuid::new_v4(), seller),
+                Some(OrderSide::Bid),
+                Price(dec!(100)),
+                Quantity(dec!(1)),
+            )
+        };
+        let (first, second) = (trade(), trade());
+        gateway.settle_trade(&btc_usd(), &first, 5).unwrap();
+        assert!(gateway.is_settled(first.id));
+
+        // Nothing is forgotten while the journal is behind.
+        gateway.settle_trade(&btc_usd(), &second, 9).unwrap();
+        assert!(gateway.is_settled(first.id));
+
+        journal.advance(5);
+        assert_eq!(gateway.settle_trade(&btc_usd(), &second, 9), Ok(None));
+        assert!(!gateway.is_settled(first.id));
+        assert!(gateway.is_settled(second.id));
+    }
+}
@@ -0,0 +1,365 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc::{self, error::SendError};
+use tokio::task::JoinHandle;
+
+use crate::db::pool::DbPool;
+use crate::errors::custom_error::OrderError;
+use crate::matching_engine::events::SequencedEvent;
+use crate::matching_engine::pipeline::EventSubscriber;
+use crate::matching_engine::ring_buffer::WaitStrategy;
+use crate::repository::event_repository::EventRepository;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PersistenceConfig {
+    /// Events that may be queued before senders have to wait.
+    pub channel_capacity: usize,
+    /// Most events written in one transaction.
+    pub max_batch: usize,
+    /// Delay before the first retry of a batch that failed on the pool or
+    /// the connection; doubled on every further failure up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        PersistenceConfig {
+            channel_capacity: 8192,
+            max_batch: 512,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// The writer's progress, readable without keeping the channel open.
+#[derive(Debug, Clone)]
+pub struct PersistedSequence(Arc<AtomicU64>);
+
+impl PersistedSequence {
+    pub fn new(sequence: u64) -> PersistedSequence {
+        PersistedSequence(Arc::new(AtomicU64::new(sequence)))
+    }
+
+    /// Sequence number of the newest event committed to the database.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn advance(&self, sequence: u64) {
+        self.0.fetch_max(sequence, Ordering::AcqRel);
+    }
+}
+
+/// A batch the database rejected, which stopped the writer.
+#[derive(Debug)]
+pub struct PersistenceError {
+    /// The rejected events, for the operator to inspect or replay.
+    pub batch: Vec<SequencedEvent>,
+    pub source: OrderError,
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sequences = self.batch.iter().map(|event| event.sequence);
+        write!(
+            f,
+            "Engine events {}..={} were rejected: {}",
+            sequences.clone().min().unwrap_or(0),
+            sequences.max().unwrap_or(0),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+/// Sending side of the persistence writer.
+#[derive(Debug, Clone)]
+pub struct PersistenceHandle {
+    events: mpsc::Sender<SequencedEvent>,
+    last_persisted: PersistedSequence,
+}
+
+impl PersistenceHandle {
+    /// Queues `event`, waiting while the writer is `channel_capacity` events
+    /// behind.
+    pub async fn send(&self, event: SequencedEvent) -> Result<(), SendError<SequencedEvent>> {
+        self.events.send(event).await
+    }
+
+    /// `send` for the engine's own thread, outside the async runtime.
+    #[allow(clippy::result_large_err)]
+    pub fn blocking_send(&self, event: SequencedEvent) -> Result<(), SendError<SequencedEvent>> {
+        self.events.blocking_send(event)
+    }
+
+    pub fn persisted_sequence(&self) -> PersistedSequence {
+        self.last_persisted.clone()
+    }
+
+    /// Forwards everything the engine publishes to the writer, blocking the
+    /// calling thread until the writer stops.
+    pub fn forward<W: WaitStrategy>(self, events: EventSubscriber<W>) {
+        while self.blocking_send(events.next()).is_ok() {}
+    }
+}
+
+/// Writes engine events to Postgres off the matching path.
+///
+/// Events are taken from a bounded channel in batches of up to `max_batch`
+/// and applied in one transaction each. A batch that fails because the pool
+/// or the connection did is retried with backoff until it commits, so a
+/// database outage shows up as back-pressure on the senders once the channel
+/// fills. A batch the database rejects would fail the same way forever; the
+/// writer stops and hands it back instead, and senders see the channel close.
+pub struct PersistenceWriter {
+    pool: DbPool,
+    config: PersistenceConfig,
+    events: mpsc::Receiver<SequencedEvent>,
+    last_persisted: PersistedSequence,
+}
+
+impl PersistenceWriter {
+    /// Starts the writer on the current runtime. `last_persisted` is the
+    /// sequence number already in the database. The task finishes once every
+    /// handle is dropped and the queue is flushed.
+    pub fn spawn(
+        pool: DbPool,
+        config: PersistenceConfig,
+        last_persisted: u64,
+    ) -> (PersistenceHandle, JoinHandle<Result<(), PersistenceError>>) {
+        let (sender, receiver) = mpsc::channel(config.channel_capacity);
+        let last_persisted = PersistedSequence::new(last_persisted);
+        let writer = PersistenceWriter {
+            pool,
+            config,
+            events: receiver,
+            last_persisted: last_persisted.clone(),
+        };
+        let handle = PersistenceHandle {
+            events: sender,
+            last_persisted,
+        };
+        (handle, tokio::spawn(writer.run()))
+    }
+
+    async fn run(mut self) -> Result<(), PersistenceError> {
+        let mut batch = Vec::with_capacity(self.config.max_batch);
+        while self
+            .events
+            .recv_many(&mut batch, self.config.max_batch)
+            .await
+            > 0
+        {
+            if let Err(source) = self.write(&batch).await {
+                return Err(PersistenceError { batch, source });
+            }
+            if let Some(newest) = batch.iter().map(|event| event.sequence).max() {
+                self.last_persisted.advance(newest);
+            }
+            batch.clear();
+        }
+        Ok(())
+    }
+
+    async fn write(&self, batch: &[SequencedEvent]) -> Result<(), OrderError> {
+        let mut backoff = self.config.initial_backoff;
+        loop {
+            match EventRepository::apply_batch(&self.pool, batch).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is_transient() => {
+                    eprintln!(
+                        "Failed to persist {} engine events, retrying in {:?}: {}",
+                        batch.len(),
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::domain::order::OrderStatus;
+    use crate::matching_engine::engine::{MatchingEngine, TradingPair};
+    use crate::matching_engine::types::decimal::{Price, Quantity};
+    use crate::matching_engine::types::order::{OrderRecord, OrderSide};
+    use crate::repository::event_repository::tests::take_checkpoint;
+    use crate::repository::order_repository::{OrderRepository, PgOrderRepository};
+    use crate::repository::trade_repository::TradeRepository;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn engine_events_are_written_in_batches() {
+        migrate();
+        let pair = TradingPair::new("ETH".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_market(pair.clone()).unwrap();
+        let seller = uuid::Uuid::new_v4();
+        let ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(5))).with_user(seller);
+        let bid =
+            OrderRecord::new(OrderSide::Bid, Quantity(dec!(2))).with_user(uuid::Uuid::new_v4());
+        let (ask_id, bid_id) = (ask.id, bid.id);
+        engine
+            .place_limit_order(pair.clone(), Price(dec!(100)), ask)
+            .unwrap();
+        engine.set_clock(chrono::Utc::now());
+        engine
+            .place_limit_order(pair.clone(), Price(dec!(100)), bid)
+            .unwrap();
+        // Rows take the engine's time for each event, not the database's.
+        let cancelled_at =
+            chrono::DateTime::from_timestamp(chrono::Utc::now().timestamp() + 60, 0).unwrap();
+        engine.set_clock(cancelled_at);
+        engine.cancel_order(pair, ask_id, seller, None).unwrap();
+
+        // Sequence numbers must be ahead of whatever earlier runs persisted.
+        let (_checkpoint, base) = take_checkpoint().await;
+        let events = engine
+            .drain_events()
+            .map(|mut event| {
+                event.sequence += base;
+                event
+            })
+            .collect::<Vec<_>>();
+        let newest = events.last().unwrap().sequence;
+
+        let config = PersistenceConfig {
+            max_batch: 2,
+            ..PersistenceConfig::default()
+        };
+        let (handle, writer) = PersistenceWriter::spawn(DB_POOL.clone(), config, base);
+        for event in events.iter().cloned() {
+            handle.send(event).await.unwrap();
+        }
+        let progress = handle.persisted_sequence();
+        drop(handle);
+        writer.await.unwrap().unwrap();
+        assert_eq!(progress.get(), newest);
+
+        let orders = PgOrderRepository::new(DB_POOL.clone());
+        let ask = orders.find_by_id(ask_id).await.unwrap();
+        assert_eq!(ask.status, OrderStatus::Cancelled);
+        assert_eq!(ask.remaining_size, Quantity(dec!(3)));
+        assert_eq!(ask.updated_at, cancelled_at);
+        let bid = orders.find_by_id(bid_id).await.unwrap();
+        assert_eq!(bid.status, OrderStatus::Filled);
+        assert_eq!(bid.remaining_size, Quantity::ZERO);
+        let trades = TradeRepository::find_for_user(
+            &DB_POOL,
+            bid.user_id,
+            bid.created_at,
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(trades.len(), 1);
+
+        // Replaying the stream changes nothing.
+        EventRepository::apply_batch(&DB_POOL, &events)
+            .await
+            .unwrap();
+        let ask = orders.find_by_id(ask_id).await.unwrap();
+        assert_eq!(ask.remaining_size, Quantity(dec!(3)));
+    }
+
+    #[tokio::test]
+    async fn market_orders_and_settlements_are_written() {
+        use crate::repository::settlement_repository::SettlementRepository;
+        use crate::services::payment_gateway::PaymentGateway;
+
+        migrate();
+        let pair = TradingPair::new("AVAX".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_market(pair.clone()).unwrap();
+        let (buyer, seller) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let mut gateway = PaymentGateway::new();
+        gateway.deposit(buyer, "USD", dec!(1000)).unwrap();
+        gateway.deposit(seller, "AVAX", dec!(2)).unwrap();
+        engine.set_payment_gateway(gateway);
+        engine
+            .place_limit_order(
+                pair.clone(),
+                Price(dec!(30)),
+                OrderRecord::new(OrderSide::Ask, Quantity(dec!(2))).with_user(seller),
+            )
+            .unwrap();
+        let market = OrderRecord::new(OrderSide::Bid, Quantity(dec!(3))).with_user(buyer);
+        let market_id = market.id;
+        engine.place_market_order(pair, market).unwrap();
+
+        let (_checkpoint, base) = take_checkpoint().await;
+        let events = engine
+            .drain_events()
+            .map(|mut event| {
+                event.sequence += base;
+                event
+            })
+            .collect::<Vec<_>>();
+        EventRepository::apply_batch(&DB_POOL, &events)
+            .await
+            .unwrap();
+
+        let orders = PgOrderRepository::new(DB_POOL.clone());
+        let market = orders.find_by_id(market_id).await.unwrap();
+        assert_eq!(market.status, OrderStatus::Cancelled);
+        assert_eq!(market.price, Price(dec!(30)));
+        assert_eq!(market.size, Quantity(dec!(3)));
+        assert_eq!(market.remaining_size, Quantity(dec!(1)));
+        assert_eq!(
+            SettlementRepository::balance(&DB_POOL, buyer, "AVAX")
+                .await
+                .unwrap(),
+            dec!(2)
+        );
+        assert_eq!(
+            SettlementRepository::balance(&DB_POOL, seller, "USD")
+                .await
+                .unwrap(),
+            dec!(60)
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_batches_stop_the_writer() {
+        migrate();
+        let pair = TradingPair::new("DOT".to_string(), "USD".to_string());
+        let mut engine = MatchingEngine::new();
+        engine.add_market(pair.clone()).unwrap();
+        // Postgres refuses NUL bytes in text, however often it is asked.
+        let order = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1)))
+            .with_user(uuid::Uuid::new_v4())
+            .with_client_order_id("bad\0id".to_string());
+        engine
+            .place_limit_order(pair, Price(dec!(5)), order)
+            .unwrap();
+
+        let (_checkpoint, base) = take_checkpoint().await;
+        let (handle, writer) =
+            PersistenceWriter::spawn(DB_POOL.clone(), PersistenceConfig::default(), base);
+        let progress = handle.persisted_sequence();
+        for mut event in engine.drain_events() {
+            event.sequence += base;
+            handle.send(event).await.unwrap();
+        }
+
+        let error = writer.await.unwrap().unwrap_err();
+        assert!(!error.source.is_transient());
+        assert!(!error.batch.is_empty());
+        assert_eq!(progress.get(), base);
+        assert!(handle.send(error.batch[0].clone()).await.is_err());
+    }
+}
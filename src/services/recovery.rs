@@ -0,0 +1,335 @@
+use chrono::{Duration, Utc};
+
+use crate::db::pool::DbPool;
+use crate::matching_engine::engine::MatchingEngine;
+use crate::matching_engine::fees::VOLUME_WINDOW_DAYS;
+use crate::repository::event_repository::EventRepository;
+use crate::repository::market_repository::{MarketRecord, MarketRepository};
+use crate::repository::order_repository::{OrderRepository, PgOrderRepository};
+use crate::repository::settlement_repository::SettlementRepository;
+use crate::repository::trade_repository::TradeRepository;
+
+/// What was brought back on startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    pub markets: usize,
+    pub orders: usize,
+    /// Orders cancelled because their session ended with the restart, or
+    /// their owner's balance no longer covers them.
+    pub cancelled: usize,
+    /// Open orders for markets that are not listed.
+    pub skipped: usize,
+    /// Last event already in the database; the engine numbers on from here.
+    pub sequence: u64,
+}
+
+pub struct RecoveryService;
+
+impl RecoveryService {
+    /// Recovers from Postgres: the `markets` table, the open orders, the
+    /// engine checkpoint, the balances in `balance_journal` when the engine
+    /// tracks them and, from `trades`, the volume behind each user's fee tier.
+    pub async fn recover_from_database(
+        pool: &DbPool,
+        engine: &mut MatchingEngine,
+    ) -> Result<Recovery, String> {
+        let markets = MarketRepository::find_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        let sequence = EventRepository::last_sequence(pool)
+            .await
+            .map_err(|e| e.to_string())?
+            .unwrap_or(0);
+        // Balances come back before the orders, which take their holds again.
+        if let Some(accounts) = engine.payment_gateway_mut() {
+            let balances = SettlementRepository::balances(pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            for (user_id, asset, amount) in balances {
+                accounts.restore_balance(user_id, &asset, amount);
+            }
+        }
+        let orders = PgOrderRepository::new(pool.clone());
+        let recovery = Self::recover(&orders, &markets, sequence, engine).await?;
+
+        let since = Utc::now() - Duration::days(VOLUME_WINDOW_DAYS);
+        let volumes = TradeRepository::daily_volumes(pool, since)
+            .await
+            .map_err(|e| e.to_string())?;
+        for volume in volumes {
+            engine.restore_fee_volume(volume.user_id, volume.day, volume.notional);
+        }
+        Ok(recovery)
+    }
+
+    /// Reopens every market as it was left, then rebuilds the books from the
+    /// open and partially filled orders in `orders`. `sequence` is the last
+    /// event already persisted.
+    pub async fn recover(
+        orders: &impl OrderRepository,
+        markets: &[MarketRecord],
+        sequence: u64,
+        engine: &mut MatchingEngine,
+    ) -> Result<Recovery, String> {
+        for market in markets.iter() {
+            engine
+                .restore_market(market.pair.clone(), market.precision, market.state)
+                .map_err(|e| e.to_string())?;
+        }
+        let orders = orders.find_open().await.map_err(|e| e.to_string())?;
+        let restored = engine
+            .restore(orders, sequence)
+            .map_err(|e| e.to_string())?;
+        Ok(Recovery {
+            markets: markets.len(),
+            orders: restored.resting,
+            cancelled: restored.cancelled,
+            skipped: restored.skipped,
+            sequence,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::db::migrations::tests::migrate;
+    use crate::db::pool::DB_POOL;
+    use crate::matching_engine::engine::TradingPair;
+    use crate::matching_engine::types::decimal::{Notional, Price, Quantity};
+    use crate::matching_engine::types::order::{OrderRecord, OrderSide};
+    use crate::repository::event_repository::tests::take_checkpoint;
+    use rust_decimal_macros::dec;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn open_orders_are_restored_after_a_restart() {
+        migrate();
+        let pair = TradingPair::new("SOL".to_string(), "USD".to_string());
+        let mut before = MatchingEngine::new();
+        before.set_clock(Utc::now());
+        before.add_market(pair.clone()).unwrap();
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let open = OrderRecord::new(OrderSide::Bid, Quantity(dec!(4))).with_user(buyer);
+        let filled = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller);
+        let (open_id, filled_id) = (open.id, filled.id);
+        before
+            .place_limit_order(pair.clone(), Price(dec!(20)), open)
+            .unwrap();
+        before
+            .place_limit_order(pair.clone(), Price(dec!(20)), filled)
+            .unwrap();
+
+        let (_checkpoint, base) = take_checkpoint().await;
+        let events = before
+            .drain_events()
+            .map(|mut event| {
+                event.sequence += base;
+                event
+            })
+            .collect::<Vec<_>>();
+        EventRepository::apply_batch(&DB_POOL, &events)
+            .await
+            .unwrap();
+
+        let mut after = MatchingEngine::new();
+        let recovery = RecoveryService::recover_from_database(&DB_POOL, &mut after)
+            .await
+            .unwrap();
+        assert!(recovery.sequence >= events.last().unwrap().sequence);
+        assert_eq!(after.sequence(), recovery.sequence);
+
+        let book = after.market(&pair).unwrap().orderbook();
+        let handle = book.handle_of(open_id).unwrap();
+        assert_eq!(
+            book.get(handle).unwrap().order().remaining_size,
+            Quantity(dec!(3))
+        );
+        assert!(book.handle_of(filled_id).is_none());
+
+        // The trade still counts towards both users' fee tiers.
+        for user in [buyer, seller] {
+            assert_eq!(
+                after.fee_engine().rolling_volume(user, Utc::now()),
+                Notional(dec!(20))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn balances_and_holds_survive_a_restart() {
+        use crate::services::payment_gateway::{Balance, PaymentGateway};
+
+        migrate();
+        let pair = TradingPair::new("AVAX".to_string(), "USD".to_string());
+        let (buyer, seller) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut before = MatchingEngine::new();
+        before.set_payment_gateway(PaymentGateway::new());
+        before.set_clock(Utc::now());
+        before.add_market(pair.clone()).unwrap();
+        before
+            .deposit(Uuid::new_v4(), buyer, "USD".to_string(), dec!(1000))
+            .unwrap();
+        before
+            .withdraw(Uuid::new_v4(), buyer, "USD".to_string(), dec!(100))
+            .unwrap();
+        before
+            .deposit(Uuid::new_v4(), seller, "AVAX".to_string(), dec!(1))
+            .unwrap();
+        let bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(4))).with_user(buyer);
+        let ask = OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller);
+        before
+            .place_limit_order(pair.clone(), Price(dec!(20)), bid)
+            .unwrap();
+        before
+            .place_limit_order(pair.clone(), Price(dec!(20)), ask)
+            .unwrap();
+
+        let (_checkpoint, base) = take_checkpoint().await;
+        let events = before
+            .drain_events()
+            .map(|mut event| {
+                event.sequence += base;
+                event
+            })
+            .collect::<Vec<_>>();
+        EventRepository::apply_batch(&DB_POOL, &events)
+            .await
+            .unwrap();
+
+        let mut after = MatchingEngine::new();
+        after.set_payment_gateway(PaymentGateway::new());
+        RecoveryService::recover_from_database(&DB_POOL, &mut after)
+            .await
+            .unwrap();
+        let accounts = after.payment_gateway().unwrap();
+        for (user, asset) in [(buyer, "USD"), (buyer, "AVAX"), (seller, "USD")] {
+            assert_eq!(
+                accounts.balance(user, asset),
+                before.payment_gateway().unwrap().balance(user, asset)
+            );
+        }
+        assert_eq!(
+            accounts.balance(buyer, "USD"),
+            Balance {
+                available: dec!(820),
+                held: dec!(60),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn markets_and_amended_priority_survive_a_restart() {
+        use crate::matching_engine::engine::OrderRef;
+        use crate::matching_engine::events::EngineEvent;
+        use crate::matching_engine::types::fixed::Precision;
+
+        migrate();
+        // A market of its own, so orders left by earlier runs do not queue
+        // ahead of these.
+        let pair = TradingPair::new(format!("ADA{}", Uuid::new_v4().simple()), "USD".to_string());
+        let precision = Precision::new(4, 2).unwrap();
+        let mut before = MatchingEngine::new();
+        before
+            .add_market_with_precision(pair.clone(), precision)
+            .unwrap();
+        let seller = Uuid::new_v4();
+        let ask = || OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_user(seller);
+        let (first, second) = (ask(), ask());
+        let (first_id, second_id) = (first.id, second.id);
+        for order in [first, second] {
+            before
+                .place_limit_order(pair.clone(), Price(dec!(0.4512)), order)
+                .unwrap();
+        }
+        // Growing the first order sends it behind the second.
+        before
+            .amend_order(
+                pair.clone(),
+                OrderRef::Id(first_id),
+                seller,
+                None,
+                None,
+                Some(Quantity(dec!(2))),
+            )
+            .unwrap();
+
+        let (_checkpoint, base) = take_checkpoint().await;
+        let events = before
+            .drain_events()
+            .map(|mut event| {
+                event.sequence += base;
+                event
+            })
+            .collect::<Vec<_>>();
+        EventRepository::apply_batch(&DB_POOL, &events)
+            .await
+            .unwrap();
+
+        let mut after = MatchingEngine::new();
+        RecoveryService::recover_from_database(&DB_POOL, &mut after)
+            .await
+            .unwrap();
+        let book = after.market(&pair).unwrap().orderbook();
+        assert_eq!(book.precision(), precision);
+
+        after
+            .place_market_order(
+                pair.clone(),
+                OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_user(Uuid::new_v4()),
+            )
+            .unwrap();
+        let trade = after
+            .drain_events()
+            .find_map(|event| match event.event {
+                EngineEvent::Trade { trade, .. } => Some(trade),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(trade.sell_order_id, second_id);
+    }
+
+    #[tokio::test]
+    async fn books_are_rebuilt_from_any_order_repository() {
+        use crate::matching_engine::market_state::TradingState;
+        use crate::matching_engine::types::fixed::Precision;
+        use crate::repository::order_repository::{InMemoryOrderRepository, OrderRepositoryExt};
+
+        let pair = TradingPair::new("BTC".to_string(), "USD".to_string());
+        let repository = InMemoryOrderRepository::new();
+        let mut bid = OrderRecord::new(OrderSide::Bid, Quantity(dec!(1))).with_pair(pair.clone());
+        bid.price = Price(dec!(99));
+        let mut filled =
+            OrderRecord::new(OrderSide::Ask, Quantity(dec!(1))).with_pair(pair.clone());
+        filled.price = Price(dec!(101));
+        filled.status = crate::domain::order::OrderStatus::Filled;
+        let bid_id = bid.id;
+        repository.create_order(&bid).await.unwrap();
+        repository.create_order(&filled).await.unwrap();
+
+        let markets = [MarketRecord {
+            pair: pair.clone(),
+            precision: Precision::default(),
+            state: TradingState::Halted,
+        }];
+        let mut engine = MatchingEngine::new();
+        let recovery = RecoveryService::recover(&repository, &markets, 12, &mut engine)
+            .await
+            .unwrap();
+        assert_eq!(
+            recovery,
+            Recovery {
+                markets: 1,
+                orders: 1,
+                cancelled: 0,
+                skipped: 0,
+                sequence: 12
+            }
+        );
+        let market = engine.market(&pair).unwrap();
+        assert_eq!(market.state(), TradingState::Halted);
+        assert!(market.orderbook().handle_of(bid_id).is_some());
+        assert_eq!(market.orderbook().len(), 1);
+        assert_eq!(engine.sequence(), 12);
+    }
+}
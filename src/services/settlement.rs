@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::matching_engine::engine::TradingPair;
+use crate::matching_engine::fees::Fee;
+use crate::matching_engine::types::trade::Trade;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Trade,
+    Fee,
+    Deposit,
+    Withdrawal,
+}
+
+impl EntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Trade => "trade",
+            EntryKind::Fee => "fee",
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+        }
+    }
+}
+
+/// One balance movement. Debits are negative.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub trade_id: Uuid,
+    /// The order whose hold funds a debit; `None` for credits and for the
+    /// fee account's side of a fee.
+    pub order_id: Option<Uuid>,
+    pub user_id: Uuid,
+    pub asset: String,
+    pub amount: Decimal,
+    pub kind: EntryKind,
+}
+
+/// Funds that entered or left one user's account from outside the exchange.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub asset: String,
+    /// Positive for a deposit, negative for a withdrawal.
+    pub amount: Decimal,
+    pub transferred_at: DateTime<Utc>,
+}
+
+impl Transfer {
+    pub fn kind(&self) -> EntryKind {
+        match self.amount >= Decimal::ZERO {
+            true => EntryKind::Deposit,
+            false => EntryKind::Withdrawal,
+        }
+    }
+}
+
+/// Every balance movement caused by one trade, applied all at once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub trade_id: Uuid,
+    pub pair: String,
+    pub settled_at: DateTime<Utc>,
+    pub entries: Vec<JournalEntry>,
+}
+
+impl Settlement {
+    /// Base moves from seller to buyer, quote from buyer to seller, and each
+    /// side's fee moves to `fee_account` (or from it, for a rebate). Trades
+    /// settle as they execute, so they share a timestamp.
+    pub fn for_trade(pair: &TradingPair, trade: &Trade, fee_account: Uuid) -> Settlement {
+        let notional = trade.notional().0;
+        let quantity = trade.quantity.0;
+        let entry = |order_id, user_id, asset: &str, amount| JournalEntry {
+            trade_id: trade.id,
+            order_id,
+            user_id,
+            asset: asset.to_string(),
+            amount,
+            kind: EntryKind::Trade,
+        };
+        let mut entries = vec![
+            entry(
+                Some(trade.buy_order_id),
+                trade.buyer_id,
+                pair.quote(),
+                -notional,
+            ),
+            entry(None, trade.buyer_id, pair.base(), quantity),
+            entry(
+                Some(trade.sell_order_id),
+                trade.seller_id,
+                pair.base(),
+                -quantity,
+            ),
+            entry(None, trade.seller_id, pair.quote(), notional),
+        ];
+        // Fees are collected before rebates are paid, so a maker's rebate is
+        // covered by the taker's fee on the same trade whichever side the
+        // maker is on.
+        let (charged, rebated): (Vec<_>, Vec<_>) = [
+            (trade.buy_order_id, &trade.buyer_fee),
+            (trade.sell_order_id, &trade.seller_fee),
+        ]
+        .into_iter()
+        .filter_map(|(order_id, fee)| Some((order_id, fee.as_ref()?)))
+        .partition(|(_, fee)| fee.amount >= Decimal::ZERO);
+        for (order_id, fee) in charged.into_iter().chain(rebated) {
+            entries.extend(Self::fee_entries(trade.id, order_id, fee, fee_account));
+        }
+        Settlement {
+            trade_id: trade.id,
+            pair: pair.to_string(),
+            settled_at: trade.executed_at,
+            entries,
+        }
+    }
+
+    /// A fee the user pays is funded by `order_id`'s hold; a rebate is paid
+    /// out of the fee account's available balance.
+    fn fee_entries(
+        trade_id: Uuid,
+        order_id: Uuid,
+        fee: &Fee,
+        fee_account: Uuid,
+    ) -> Vec<JournalEntry> {
+        if fee.amount.is_zero() {
+            return Vec::new();
+        }
+        let payer = (fee.amount > Decimal::ZERO).then_some(order_id);
+        [
+            (payer, fee.user_id, -fee.amount),
+            (None, fee_account, fee.amount),
+        ]
+        .into_iter()
+        .map(|(order_id, user_id, amount)| JournalEntry {
+            trade_id,
+            order_id,
+            user_id,
+            asset: fee.asset.clone(),
+            amount,
+            kind: EntryKind::Fee,
+        })
+        .collect()
+    }
+
+    /// Every asset's movements net to zero.
+    pub fn is_balanced(&self) -> bool {
+        let mut net: HashMap<&str, Decimal> = HashMap::new();
+        for entry in self.entries.iter() {
+            *net.entry(entry.asset.as_str()).or_default() += entry.amount;
+        }
+        net.values().all(|amount| amount.is_zero())
+    }
+}